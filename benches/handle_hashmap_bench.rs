@@ -116,6 +116,20 @@ fn bench_query(c: &mut Criterion) {
             }
         })
     });
+    group.bench_function("hit_10k_on_100k_batch", |b| {
+        let mut m = HandleHashMap::new();
+        let mut rng_keys = Pcg::seed_from_u64(7);
+        let keys: Vec<_> = (0..100_000).map(|_| key(rng_keys.next_u64())).collect();
+        for (i, k) in keys.iter().enumerate() {
+            let _ = m.insert(k.clone(), i as u64).unwrap();
+        }
+        let n = keys.len();
+        let mut rng_q = Pcg::seed_from_u64(0x9e3779b97f4a7c15);
+        let queries: Vec<String> = (0..10_000)
+            .map(|_| keys[(rng_q.next_u64() as usize) % n].clone())
+            .collect();
+        b.iter(|| black_box(m.lookup_batch(&queries)))
+    });
     // miss
     group.bench_function("miss_10k_on_100k", |b| {
         let mut m = HandleHashMap::new();
@@ -131,6 +145,18 @@ fn bench_query(c: &mut Criterion) {
             }
         })
     });
+    group.bench_function("miss_10k_on_100k_batch", |b| {
+        let mut m = HandleHashMap::new();
+        let mut rng_ins = Pcg::seed_from_u64(11);
+        for i in 0..100_000 {
+            let _ = m.insert(key(rng_ins.next_u64()), i as u64).unwrap();
+        }
+        let mut miss = Pcg::seed_from_u64(0xdead_beefu64);
+        b.iter(|| {
+            let queries: Vec<String> = (0..10_000).map(|_| key(miss.next_u64())).collect();
+            black_box(m.lookup_batch(&queries))
+        })
+    });
     group.finish();
 }
 
@@ -164,6 +190,32 @@ fn bench_access(c: &mut Criterion) {
             BatchSize::SmallInput,
         )
     });
+    group.bench_function("random_increment_100k_unchecked", |b| {
+        b.iter_batched(
+            || {
+                let mut m = HandleHashMap::new();
+                let mut rng = Pcg::seed_from_u64(123);
+                let handles: Vec<_> = (0..100_000)
+                    .map(|i| m.insert(key(rng.next_u64()), i as u64).unwrap())
+                    .collect();
+                let n = handles.len();
+                let mut rsel = Pcg::seed_from_u64(0x9e3779b97f4a7c15);
+                let targets: Vec<Handle> = (0..100_000)
+                    .map(|_| handles[(rsel.next_u64() as usize) % n])
+                    .collect();
+                (m, targets)
+            },
+            |(mut m, targets)| {
+                for h in targets {
+                    // SAFETY: nothing is removed, so every target stays live.
+                    let v = unsafe { m.get_unchecked_mut(h) };
+                    *v = v.wrapping_add(1);
+                }
+                black_box(m)
+            },
+            BatchSize::SmallInput,
+        )
+    });
     // iter
     group.bench_function("iter_all_100k", |b| {
         let mut m = HandleHashMap::new();
@@ -0,0 +1,101 @@
+//! AnyRcMap: one ref-counted value per type, keyed by `TypeId`.
+//!
+//! The "resource map" of game and ECS engines: each resource type has at
+//! most one instance, found by type rather than by name, and goes away when
+//! its last `AnyRef` drops. Built on `RcHashMap<TypeId, Box<dyn Any>>`;
+//! Refs are projected onto the concrete type, so reads need no downcasting.
+//!
+//! ```rust
+//! use rc_hashmap::AnyRcMap;
+//!
+//! struct Gravity(f32);
+//!
+//! let mut m = AnyRcMap::new();
+//! let g = m.insert(Gravity(9.8)).unwrap();
+//! let found = m.find::<Gravity>().unwrap();
+//! assert_eq!(m.get(&found).unwrap().0, 9.8);
+//! drop((g, found));
+//! assert!(!m.contains::<Gravity>());
+//! ```
+
+use crate::handle_hash_map::{CappedInsertError, InsertError};
+use crate::hash::DefaultHashBuilder;
+use crate::rc_hash_map::{AccessError, MappedRef, RcHashMap};
+use crate::tokens::{EntryCount, UsizeCount};
+use core::any::{Any, TypeId};
+
+/// A Ref to the `T` stored in an `AnyRcMap`.
+pub type AnyRef<T, S = DefaultHashBuilder, C = UsizeCount> =
+    MappedRef<TypeId, Box<dyn Any>, T, S, C>;
+
+pub struct AnyRcMap<S = DefaultHashBuilder, C = UsizeCount>
+where
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    map: RcHashMap<TypeId, Box<dyn Any>, S, C>,
+}
+
+impl AnyRcMap {
+    pub fn new() -> Self {
+        Self {
+            map: RcHashMap::new(),
+        }
+    }
+}
+
+impl<C> Default for AnyRcMap<DefaultHashBuilder, C>
+where
+    C: EntryCount,
+{
+    fn default() -> Self {
+        Self {
+            map: RcHashMap::default(),
+        }
+    }
+}
+
+impl<S, C> AnyRcMap<S, C>
+where
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            map: RcHashMap::with_hasher(hasher),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Insert `value`; fails if a `T` is already present.
+    pub fn insert<T: 'static>(&mut self, value: T) -> Result<AnyRef<T, S, C>, InsertError> {
+        let r = self
+            .map
+            .insert_any(TypeId::of::<T>(), value)
+            .map_err(CappedInsertError::uncapped)?;
+        Ok(r.map_value(|v| v.downcast_ref::<T>().expect("keyed by its TypeId")))
+    }
+
+    /// Mint a Ref to the `T`, if present.
+    pub fn find<T: 'static>(&self) -> Option<AnyRef<T, S, C>> {
+        // Values are never replaced through this wrapper, so the key's
+        // type always matches.
+        let r = self.map.find(&TypeId::of::<T>())?;
+        Some(r.map_value(|v| v.downcast_ref::<T>().expect("keyed by its TypeId")))
+    }
+
+    /// Borrow the value behind `r`, validating owner identity.
+    pub fn get<'a, T: 'static>(&'a self, r: &'a AnyRef<T, S, C>) -> Result<&'a T, AccessError> {
+        r.get(&self.map)
+    }
+}
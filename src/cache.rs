@@ -0,0 +1,72 @@
+//! Cache: a small interface over get-or-compute caches.
+//!
+//! Applications that memoize values behind a key can program against
+//! `Cache` and swap the backing implementation (a plain `RcHashMap`, or an
+//! LRU/TTL wrapper around one) without writing an adapter layer.
+//!
+//! `RcHashMap` never evicts on its own: an entry is resident while any `Ref`
+//! to it is alive and is removed when the last one drops. `invalidate`
+//! releases whatever hold the cache itself has on the entry: the map's pin
+//! for `RcHashMap`, the hot-set Ref for an `EvictingCache`. Outside `Ref`s
+//! keep the entry resident until they drop.
+//!
+//! ```rust
+//! use rc_hashmap::{Cache, RcHashMap};
+//!
+//! fn lookup<C: Cache<u32, String>>(cache: &mut C, id: u32) -> C::Ref {
+//!     cache.get_or_insert_with(id, || format!("user-{id}")).unwrap()
+//! }
+//!
+//! let mut m = RcHashMap::new();
+//! let a = lookup(&mut m, 7);
+//! let b = lookup(&mut m, 7);
+//! assert_eq!(a.value(&m).unwrap(), "user-7");
+//! assert_eq!(m.stats().hits, 1);
+//! # drop((a, b));
+//! ```
+
+use crate::handle_hash_map::CappedInsertError;
+use core::borrow::Borrow;
+use core::hash::Hash;
+
+/// Hit/miss counters accumulated by `Cache::get_or_insert_with`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    /// Lookups that found a resident entry.
+    pub hits: u64,
+    /// Lookups that computed and inserted a new entry.
+    pub misses: u64,
+}
+
+/// A keyed cache that computes missing values on demand.
+pub trait Cache<K, V> {
+    /// What a lookup hands back, e.g. `Ref` for `RcHashMap`.
+    type Ref;
+
+    /// Return the entry for `key`, inserting `f()` if it is not resident.
+    /// Fails with `CapacityExceeded` if `key` is absent and the backing map
+    /// is at its `max_entries` cap, or resident and at its `max_refs` cap;
+    /// it never reports `DuplicateKey`.
+    fn get_or_insert_with<F>(&mut self, key: K, f: F) -> Result<Self::Ref, CappedInsertError>
+    where
+        F: FnOnce() -> V;
+
+    /// Drop the cache's own hold on `key` (a pin, or a policy's Ref), so it
+    /// is removed once no outside refs remain. Returns true if `key` is no
+    /// longer resident afterwards; outstanding refs may keep it alive.
+    fn invalidate<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+
+    /// Number of resident entries.
+    fn len(&self) -> usize;
+
+    /// True if no entries are resident.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hit/miss counters since construction.
+    fn stats(&self) -> CacheStats;
+}
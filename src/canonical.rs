@@ -0,0 +1,143 @@
+//! Canonical: keys normalized once, on construction.
+//!
+//! Maps that should treat `"Foo"`, `"foo"` and `" foo "` as one key need
+//! the same normalization before every insert and every lookup; a single
+//! call site that forgets it (or a `Borrow`-based lookup with a raw `&str`)
+//! silently misses. Use `Canonical<K, N>` as the map's key type instead:
+//! construction runs `N::canonicalize`, hashing and equality see only the
+//! canonical form, and there is deliberately no `Borrow` impl, so a raw,
+//! un-normalized query does not compile.
+//!
+//! ```rust
+//! use rc_hashmap::canonical::{Canonical, Lowercase};
+//! use rc_hashmap::RcHashMap;
+//!
+//! type Name = Canonical<String, Lowercase>;
+//!
+//! let mut m: RcHashMap<Name, u32> = RcHashMap::new();
+//! let r = m.insert(Name::new("Alice".into()), 1).unwrap();
+//! assert!(m.contains_key(&Name::from("ALICE")));
+//! assert_eq!(r.key(&m).unwrap().as_str(), "alice");
+//! ```
+//!
+//! Normalizations needing external tables (e.g. Unicode NFC) implement
+//! `Canonicalize` themselves.
+
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::marker::PhantomData;
+
+/// A key normalization, applied by `Canonical::new`.
+pub trait Canonicalize<K> {
+    fn canonicalize(key: K) -> K;
+}
+
+/// Unicode lowercase (`str::to_lowercase`).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Lowercase;
+
+impl Canonicalize<String> for Lowercase {
+    fn canonicalize(key: String) -> String {
+        if key.chars().any(char::is_uppercase) {
+            key.to_lowercase()
+        } else {
+            key
+        }
+    }
+}
+
+/// Strip leading and trailing whitespace.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Trim;
+
+impl Canonicalize<String> for Trim {
+    fn canonicalize(key: String) -> String {
+        let t = key.trim();
+        if t.len() == key.len() {
+            key
+        } else {
+            t.to_owned()
+        }
+    }
+}
+
+/// Apply `A`, then `B`; e.g. `(Trim, Lowercase)`.
+impl<K, A: Canonicalize<K>, B: Canonicalize<K>> Canonicalize<K> for (A, B) {
+    fn canonicalize(key: K) -> K {
+        B::canonicalize(A::canonicalize(key))
+    }
+}
+
+/// A key in canonical form under normalization `N`.
+pub struct Canonical<K, N> {
+    key: K,
+    _n: PhantomData<fn() -> N>,
+}
+
+impl<K, N: Canonicalize<K>> Canonical<K, N> {
+    pub fn new(key: K) -> Self {
+        Self {
+            key: N::canonicalize(key),
+            _n: PhantomData,
+        }
+    }
+}
+
+impl<K, N> Canonical<K, N> {
+    /// The canonical key.
+    pub fn get(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_inner(self) -> K {
+        self.key
+    }
+}
+
+impl<K, N: Canonicalize<K>> From<K> for Canonical<K, N> {
+    fn from(key: K) -> Self {
+        Self::new(key)
+    }
+}
+
+impl<N: Canonicalize<String>> From<&str> for Canonical<String, N> {
+    fn from(key: &str) -> Self {
+        Self::new(key.to_owned())
+    }
+}
+
+impl<K, N> core::ops::Deref for Canonical<K, N> {
+    type Target = K;
+    fn deref(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: Clone, N> Clone for Canonical<K, N> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone(),
+            _n: PhantomData,
+        }
+    }
+}
+
+impl<K: fmt::Debug, N> fmt::Debug for Canonical<K, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.key.fmt(f)
+    }
+}
+
+impl<K: PartialEq, N> PartialEq for Canonical<K, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<K: Eq, N> Eq for Canonical<K, N> {}
+
+impl<K: Hash, N> Hash for Canonical<K, N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state)
+    }
+}
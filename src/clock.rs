@@ -0,0 +1,100 @@
+//! Clock: the time source behind time-based features.
+//!
+//! Anything that stamps or expires entries reads time through `Clock`
+//! rather than calling `std::time::Instant::now()` directly, so tests can
+//! drive time by hand and targets without `std::time` can supply their own
+//! source (a hardware tick counter, a frame counter, …).
+//!
+//! Time is measured as a `Duration` since the clock's own epoch; only
+//! differences between readings are meaningful.
+//!
+//! ```rust
+//! use rc_hashmap::clock::{Clock, ManualClock};
+//! use std::rc::Rc;
+//! use std::time::Duration;
+//!
+//! let clock = Rc::new(ManualClock::new());
+//! let shared = clock.clone(); // e.g. handed to a map
+//! clock.advance(Duration::from_secs(5));
+//! assert_eq!(shared.now(), Duration::from_secs(5));
+//! ```
+
+use core::cell::Cell;
+use core::time::Duration;
+use std::rc::Rc;
+
+/// A monotonic time source.
+pub trait Clock {
+    /// Time elapsed since this clock's epoch. Must never go backwards.
+    fn now(&self) -> Duration;
+}
+
+impl<T: Clock + ?Sized> Clock for &T {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for Rc<T> {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// Wall-clock time from `std::time::Instant`, with its epoch at creation.
+/// The default clock.
+#[derive(Copy, Clone, Debug)]
+pub struct StdClock {
+    start: std::time::Instant,
+}
+
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests. Share it
+/// with a map through `Rc<ManualClock>` or `&ManualClock`.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    /// A clock reading zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move time forward by `d`.
+    pub fn advance(&self, d: Duration) {
+        self.now.set(self.now.get() + d);
+    }
+
+    /// Jump to `t`. Panics if `t` is earlier than the current reading.
+    pub fn set(&self, t: Duration) {
+        assert!(t >= self.now.get(), "ManualClock cannot go backwards");
+        self.now.set(t);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
@@ -1,65 +1,291 @@
 //! CountedHashMap: per-entry reference counting atop HandleHashMap using tokens.
+//!
+//! This layer is a supported public API for callers who want manual,
+//! token-based counting without `RcHashMap`'s Rc keepalive machinery.
+//!
+//! Contract
+//! - Every `CountedHandle` carries one linear token for its entry. Handles
+//!   are minted by `insert`, `insert_with`, `find`, and `get`, and must be
+//!   returned exactly once via `put`. Dropping a handle instead panics.
+//! - An entry is live while at least one handle for it is outstanding;
+//!   `put` of the last handle removes the entry and returns `(K, V)`.
+//! - Handles must be returned to the map that minted them. The map does not
+//!   check this; pairing is the caller's responsibility.
+//! - Entries inserted with `insert_detached` start with zero tokens and are
+//!   resident: they stay at zero count until a `purge`/`purge_if` sweep.
+//! - `force_remove` unlinks an entry while handles are outstanding. Those
+//!   handles become stale: the map keeps their counter aside, `get` still
+//!   clones them and `put` returns `PutResult::Stale` without touching
+//!   any entry.
+//! - Iteration: `iter`/`iter_mut` borrow entries without touching counts;
+//!   `iter_guarded`/`iter_mut_guarded` yield `ItemGuard`/`ItemGuardMut`
+//!   items that hold a token while alive and return it on drop.
+//! - For lexically scoped use, `CountedGuard` (via `guard`/`find_guard`)
+//!   holds the map borrowed and returns its token automatically on drop.
+//! - Dropping the map while handles are outstanding leaves those handles
+//!   with no valid `put` target. Either drain them first, or tear the map
+//!   down with `close`, which reports what was outstanding, and dispose of
+//!   each remaining handle with `ClosedMap::abandon`. Only a closed map
+//!   can abandon handles, so a live entry's count cannot be leaked.
+//!
+//! ```rust
+//! use rc_hashmap::{CountedHashMap, PutResult};
+//!
+//! let mut m: CountedHashMap<&str, i32> = CountedHashMap::new();
+//! let a = m.insert("a", 1).unwrap();
+//! let b = m.get(&a); // second token for the same entry
+//! assert!(matches!(m.put(a), PutResult::Live { .. }));
+//! match m.put(b) {
+//!     PutResult::Removed { key, value } => assert_eq!((key, value), ("a", 1)),
+//!     PutResult::Live { .. } | PutResult::Stale { .. } => unreachable!(),
+//! }
+//! assert!(m.is_empty());
+//! ```
 
-use crate::handle_hash_map::{Handle, HandleHashMap, InsertError};
-use crate::hash::DefaultHashBuilder;
-use crate::tokens::{Count, Token, UsizeCount};
+use crate::handle_hash_map::{Handle, HandleHashMap, InsertError, SlotStats, TryInsertError};
+use crate::hash::{DefaultHashBuilder, StoredHash};
+use crate::tokens::{EntryCount, UsizeCount};
+use core::mem::ManuallyDrop;
+use std::collections::HashMap;
 
+/// Stored value wrapper pairing the user value with its entry refcount.
 #[derive(Debug)]
-pub struct Counted<V> {
-    pub refcount: UsizeCount,
+pub struct Counted<V, C = UsizeCount> {
+    pub refcount: C,
     pub value: V,
+    /// Resident entries (from `insert_detached`) stay in the map at zero
+    /// count until removed by `purge`/`purge_if`.
+    pub resident: bool,
 }
 
-impl<V> Counted<V> {
+impl<V, C: EntryCount> Counted<V, C> {
     pub fn new(value: V, initial: usize) -> Self {
         Self {
-            refcount: UsizeCount::new(initial),
+            refcount: C::with_count(initial),
             value,
+            resident: false,
         }
     }
+
+    /// Highest refcount this entry ever had, if its counter tracks it
+    /// (see `tokens::HighWaterCount`).
+    pub fn high_water(&self) -> Option<usize> {
+        self.refcount.high_water()
+    }
+}
+
+/// Map with per-entry reference counts; see the module docs for the
+/// token contract. `C` is the per-entry counter, `UsizeCount` by default.
+pub struct CountedHashMap<K, V, S = DefaultHashBuilder, C = UsizeCount> {
+    pub(crate) inner: HandleHashMap<K, Counted<V, C>, S>,
+    // Counters of force-removed entries with handles still outstanding.
+    orphans: HashMap<Handle, C>,
+    brand: Brand,
 }
 
-pub struct CountedHashMap<K, V, S = DefaultHashBuilder> {
-    pub(crate) inner: HandleHashMap<K, Counted<V>, S>,
+/// Debug-only identity of the map that minted a `CountedHandle`, checked
+/// whenever the handle is used against a map. Zero-sized in release builds.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Brand {
+    #[cfg(debug_assertions)]
+    id: u64,
 }
 
-pub struct CountedHandle<'a> {
+impl Brand {
+    fn fresh() -> Self {
+        #[cfg(debug_assertions)]
+        {
+            use core::sync::atomic::{AtomicU64, Ordering};
+            static NEXT: AtomicU64 = AtomicU64::new(0);
+            Brand {
+                id: NEXT.fetch_add(1, Ordering::Relaxed),
+            }
+        }
+
+        #[cfg(not(debug_assertions))]
+        {
+            Brand {}
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn check(self, _minted_by: Brand) {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            self.id, _minted_by.id,
+            "CountedHandle used with a map that did not mint it"
+        );
+    }
+
+    /// Like `check`, for handles being returned by value: on mismatch the
+    /// handle is forgotten before panicking so its token does not panic again
+    /// during unwinding.
+    #[inline]
+    #[track_caller]
+    fn check_owned<'a, C: EntryCount>(self, h: CountedHandle<'a, C>) -> CountedHandle<'a, C> {
+        #[cfg(debug_assertions)]
+        if self.id != h.brand.id {
+            core::mem::forget(h);
+            panic!("CountedHandle returned to a map that did not mint it");
+        }
+        h
+    }
+}
+
+/// What `CountedHashMap::close` leaves of a map: its entries, and the
+/// means to dispose of handles that outlived it.
+pub struct ClosedMap<K, V, C = UsizeCount> {
+    /// Every entry's key, value and count of outstanding handles.
+    pub entries: Vec<(K, V, usize)>,
+    brand: Brand,
+    _ctr: core::marker::PhantomData<fn() -> C>,
+}
+
+impl<K, V, C: EntryCount> ClosedMap<K, V, C> {
+    /// Dispose of a handle minted by the closed map without returning its
+    /// token. Debug builds panic if another map minted it.
+    #[track_caller]
+    pub fn abandon(&self, h: CountedHandle<'_, C>) {
+        core::mem::forget(self.brand.check_owned(h));
+    }
+}
+
+/// Handle to a counted entry owning one token of its refcount. Must be
+/// returned to the originating map via `CountedHashMap::put`.
+///
+/// In debug builds each handle is branded with its minting map, so using it
+/// with (or returning it to) a different map panics deterministically
+/// instead of silently adjusting an unrelated entry's count.
+pub struct CountedHandle<'a, C: EntryCount = UsizeCount> {
     pub(crate) handle: Handle,
-    pub(crate) token: Token<'a, UsizeCount>, // owned and consumed by put()
+    pub(crate) token: C::Token<'a>, // owned and consumed by put()
+    brand: Brand,
 }
 
-impl<'a> CountedHandle<'a> {
-    pub fn key_ref<'m, K, V, S>(&self, map: &'m CountedHashMap<K, V, S>) -> Option<&'m K>
+impl<'a, C: EntryCount> CountedHandle<'a, C> {
+    pub fn key_ref<'m, K, V, S>(&self, map: &'m CountedHashMap<K, V, S, C>) -> Option<&'m K>
     where
         K: Eq + core::hash::Hash,
         S: core::hash::BuildHasher + Clone + Default,
     {
+        map.brand.check(self.brand);
+        map.inner.debug_check_key(self.handle);
         map.inner.handle_key(self.handle)
     }
 
-    pub fn value_ref<'m, K, V, S>(&self, map: &'m CountedHashMap<K, V, S>) -> Option<&'m V>
+    pub fn value_ref<'m, K, V, S>(&self, map: &'m CountedHashMap<K, V, S, C>) -> Option<&'m V>
     where
         K: Eq + core::hash::Hash,
         S: core::hash::BuildHasher + Clone + Default,
     {
+        map.brand.check(self.brand);
+        map.inner.debug_check_key(self.handle);
         map.inner.handle_value(self.handle).map(|c| &c.value)
     }
 
-    pub fn value_mut<'m, K, V, S>(&self, map: &'m mut CountedHashMap<K, V, S>) -> Option<&'m mut V>
+    pub fn value_mut<'m, K, V, S>(
+        &self,
+        map: &'m mut CountedHashMap<K, V, S, C>,
+    ) -> Option<&'m mut V>
     where
         K: Eq + core::hash::Hash,
         S: core::hash::BuildHasher + Clone + Default,
     {
+        map.brand.check(self.brand);
+        map.inner.debug_check_key(self.handle);
         map.inner
             .handle_value_mut(self.handle)
             .map(|c| &mut c.value)
     }
 }
 
+/// Error from checked removal: the entry still has outstanding tokens
+/// (not counting the one passed in, if any).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RefsOutstanding {
+    pub count: usize,
+}
+
 /// Result of returning a token; indicates whether the entry was removed.
 pub enum PutResult<K, V> {
-    Live,
-    Removed { key: K, value: V },
+    /// The entry is still present with `remaining` outstanding tokens
+    /// (zero only for resident entries).
+    Live {
+        remaining: usize,
+    },
+    Removed {
+        key: K,
+        value: V,
+    },
+    /// The entry was force-removed earlier; `remaining` stale handles to it
+    /// are still outstanding. The map's entries are unaffected.
+    Stale {
+        remaining: usize,
+    },
+}
+
+/// RAII alternative to `CountedHandle` for lexically scoped use. Holds the
+/// map mutably borrowed and returns its token on drop, removing the entry
+/// (and dropping its `K`/`V`) when that was the last outstanding token.
+pub struct CountedGuard<'m, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    map: &'m mut CountedHashMap<K, V, S, C>,
+    handle: ManuallyDrop<CountedHandle<'static, C>>,
+}
+
+impl<'m, K, V, S, C> CountedGuard<'m, K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    /// Borrow the entry's key.
+    pub fn key(&self) -> &K {
+        self.handle
+            .key_ref(self.map)
+            .expect("guarded entry must be live")
+    }
+
+    /// Borrow the entry's value.
+    pub fn value(&self) -> &V {
+        self.handle
+            .value_ref(self.map)
+            .expect("guarded entry must be live")
+    }
+
+    /// Mutably borrow the entry's value.
+    pub fn value_mut(&mut self) -> &mut V {
+        self.handle
+            .value_mut(self.map)
+            .expect("guarded entry must be live")
+    }
+
+    /// Detach the guard, returning the underlying handle without putting
+    /// its token. The caller takes over the `put` obligation.
+    pub fn into_handle(self) -> CountedHandle<'static, C> {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so the handle is moved out exactly once.
+        unsafe { ManuallyDrop::take(&mut this.handle) }
+    }
+}
+
+impl<K, V, S, C> Drop for CountedGuard<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    fn drop(&mut self) {
+        // SAFETY: `handle` is only taken here or in `into_handle`, which
+        // suppresses this destructor.
+        let ch = unsafe { ManuallyDrop::take(&mut self.handle) };
+        let _ = self.map.put(ch);
+    }
 }
 
 impl<K, V> CountedHashMap<K, V>
@@ -69,68 +295,283 @@ where
     pub fn new() -> Self {
         Self {
             inner: HandleHashMap::new(),
+            orphans: HashMap::new(),
+            brand: Brand::fresh(),
         }
     }
 }
 
-impl<K, V> Default for CountedHashMap<K, V>
+impl<K, V, C> Default for CountedHashMap<K, V, DefaultHashBuilder, C>
 where
     K: Eq + core::hash::Hash,
+    C: EntryCount,
 {
     fn default() -> Self {
-        Self::new()
+        Self::with_hasher(DefaultHashBuilder::default())
     }
 }
 
 /// Iterator over immutable entries yielding a CountedHandle and refs.
-pub(crate) struct Iter<'a, K, V, S> {
-    pub(crate) it: crate::handle_hash_map::Iter<'a, K, Counted<V>, S>,
+pub(crate) struct Iter<'a, K, V, S, C = UsizeCount> {
+    pub(crate) it: crate::handle_hash_map::Iter<'a, K, Counted<V, C>, S>,
+    brand: Brand,
     pub(crate) _pd: core::marker::PhantomData<&'a (K, V, S)>,
 }
 
-impl<'a, K, V, S> Iterator for Iter<'a, K, V, S> {
-    type Item = (CountedHandle<'static>, &'a K, &'a V);
+impl<'a, K, V, S, C: EntryCount> Iterator for Iter<'a, K, V, S, C> {
+    type Item = (CountedHandle<'static, C>, &'a K, &'a V);
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         self.it.next().map(|(h, k, c)| {
             let ch = CountedHandle {
                 handle: h,
                 token: c.refcount.get(),
+                brand: self.brand,
             };
             (ch, k, &c.value)
         })
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
+}
+
+impl<'a, K, V, S, C: EntryCount> Iter<'a, K, V, S, C> {
+    // Advance without minting a token for the entry.
+    pub(crate) fn next_borrowed(&mut self) -> Option<(Handle, &'a K, &'a V)> {
+        self.it.next().map(|(h, k, c)| (h, k, &c.value))
+    }
+}
+
+impl<K, V, S, C: EntryCount> ExactSizeIterator for Iter<'_, K, V, S, C> {}
+impl<K, V, S, C: EntryCount> core::iter::FusedIterator for Iter<'_, K, V, S, C> {}
+
+impl<K, V, S, C> Clone for Iter<'_, K, V, S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            it: self.it.clone(),
+            brand: self.brand,
+            _pd: core::marker::PhantomData,
+        }
+    }
 }
 
 /// Iterator over mutable entries yielding a CountedHandle and refs.
-pub(crate) struct IterMut<'a, K, V, S> {
-    pub(crate) it: crate::handle_hash_map::IterMut<'a, K, Counted<V>, S>,
+pub(crate) struct IterMut<'a, K, V, S, C = UsizeCount> {
+    pub(crate) it: crate::handle_hash_map::IterMut<'a, K, Counted<V, C>, S>,
+    brand: Brand,
     pub(crate) _pd: core::marker::PhantomData<&'a (K, V, S)>,
 }
 
-impl<'a, K, V, S> Iterator for IterMut<'a, K, V, S> {
-    type Item = (CountedHandle<'static>, &'a K, &'a mut V);
+impl<'a, K, V, S, C: EntryCount> Iterator for IterMut<'a, K, V, S, C> {
+    type Item = (CountedHandle<'static, C>, &'a K, &'a mut V);
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         self.it.next().map(|(h, k, c)| {
             let token = c.refcount.get();
-            let ch = CountedHandle { handle: h, token };
+            let ch = CountedHandle {
+                handle: h,
+                token,
+                brand: self.brand,
+            };
             (ch, k, &mut c.value)
         })
     }
 }
 
-impl<K, V, S> CountedHashMap<K, V, S>
+/// Item yielded by `CountedHashMap::iter_guarded`. Holds one token for its
+/// entry while alive and returns it on drop, so guards cannot leak counts.
+/// Dereferences to the entry's value.
+pub struct ItemGuard<'a, K, V, C: EntryCount = UsizeCount> {
+    handle: Handle,
+    key: &'a K,
+    value: &'a V,
+    counter: &'a C,
+    token: ManuallyDrop<C::Token<'static>>,
+    brand: Brand,
+}
+
+impl<'a, K, V, C: EntryCount> ItemGuard<'a, K, V, C> {
+    /// The entry's structural handle.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// Borrow the entry's key.
+    pub fn key(&self) -> &'a K {
+        self.key
+    }
+
+    /// Mint an owned `CountedHandle` for the entry that outlives the
+    /// iteration; it must be returned via `CountedHashMap::put`.
+    pub fn to_handle(&self) -> CountedHandle<'static, C> {
+        CountedHandle {
+            handle: self.handle,
+            token: self.counter.get(),
+            brand: self.brand,
+        }
+    }
+}
+
+impl<K, V, C: EntryCount> core::ops::Deref for ItemGuard<'_, K, V, C> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+impl<K, V, C: EntryCount> Drop for ItemGuard<'_, K, V, C> {
+    fn drop(&mut self) {
+        // SAFETY: `token` is moved out exactly once, here.
+        let t = unsafe { ManuallyDrop::take(&mut self.token) };
+        // The iterator's borrow of the map keeps every other handle for this
+        // entry outstanding, so this never drops a non-resident entry to zero.
+        let _ = self.counter.put(t);
+    }
+}
+
+/// Item yielded by `CountedHashMap::iter_mut_guarded`. Like `ItemGuard` but
+/// dereferences mutably to the entry's value.
+pub struct ItemGuardMut<'a, K, V, C: EntryCount = UsizeCount> {
+    handle: Handle,
+    key: &'a K,
+    value: &'a mut V,
+    counter: &'a C,
+    token: ManuallyDrop<C::Token<'static>>,
+    brand: Brand,
+}
+
+impl<'a, K, V, C: EntryCount> ItemGuardMut<'a, K, V, C> {
+    /// The entry's structural handle.
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// Borrow the entry's key.
+    pub fn key(&self) -> &'a K {
+        self.key
+    }
+
+    /// Mint an owned `CountedHandle` for the entry that outlives the
+    /// iteration; it must be returned via `CountedHashMap::put`.
+    pub fn to_handle(&self) -> CountedHandle<'static, C> {
+        CountedHandle {
+            handle: self.handle,
+            token: self.counter.get(),
+            brand: self.brand,
+        }
+    }
+}
+
+impl<K, V, C: EntryCount> core::ops::Deref for ItemGuardMut<'_, K, V, C> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.value
+    }
+}
+
+impl<K, V, C: EntryCount> core::ops::DerefMut for ItemGuardMut<'_, K, V, C> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.value
+    }
+}
+
+impl<K, V, C: EntryCount> Drop for ItemGuardMut<'_, K, V, C> {
+    fn drop(&mut self) {
+        // SAFETY: `token` is moved out exactly once, here.
+        let t = unsafe { ManuallyDrop::take(&mut self.token) };
+        let _ = self.counter.put(t);
+    }
+}
+
+/// Iterator yielding an `ItemGuard` per entry.
+pub struct GuardIter<'a, K, V, S, C = UsizeCount> {
+    it: crate::handle_hash_map::Iter<'a, K, Counted<V, C>, S>,
+    brand: Brand,
+}
+
+impl<'a, K, V, S, C: EntryCount> Iterator for GuardIter<'a, K, V, S, C> {
+    type Item = ItemGuard<'a, K, V, C>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next().map(|(handle, key, c)| ItemGuard {
+            handle,
+            key,
+            value: &c.value,
+            counter: &c.refcount,
+            token: ManuallyDrop::new(c.refcount.get()),
+            brand: self.brand,
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
+}
+
+impl<K, V, S, C: EntryCount> ExactSizeIterator for GuardIter<'_, K, V, S, C> {}
+
+/// Iterator yielding an `ItemGuardMut` per entry.
+pub struct GuardIterMut<'a, K, V, S, C = UsizeCount> {
+    it: crate::handle_hash_map::IterMut<'a, K, Counted<V, C>, S>,
+    brand: Brand,
+}
+
+impl<'a, K, V, S, C: EntryCount> Iterator for GuardIterMut<'a, K, V, S, C> {
+    type Item = ItemGuardMut<'a, K, V, C>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it.next().map(|(handle, key, c)| {
+            let Counted {
+                refcount, value, ..
+            } = c;
+            let counter: &'a C = refcount;
+            ItemGuardMut {
+                handle,
+                key,
+                value,
+                counter,
+                token: ManuallyDrop::new(counter.get()),
+                brand: self.brand,
+            }
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
+}
+
+impl<K, V, S, C: EntryCount> ExactSizeIterator for GuardIterMut<'_, K, V, S, C> {}
+
+impl<K, V, S, C> CountedHashMap<K, V, S, C>
 where
     K: Eq + core::hash::Hash,
     S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
 {
     pub fn with_hasher(hasher: S) -> Self {
         Self {
             inner: HandleHashMap::with_hasher(hasher),
+            orphans: HashMap::new(),
+            brand: Brand::fresh(),
         }
     }
 
+    /// The map's hash builder.
+    pub fn hasher(&self) -> &S {
+        self.inner.hasher()
+    }
+
+    /// Reserve room for at least `additional` more entries.
+    pub fn reserve(&mut self, additional: usize) {
+        self.inner.reserve(additional)
+    }
+
     pub fn len(&self) -> usize {
         self.inner.len()
     }
@@ -138,7 +579,38 @@ where
         self.inner.is_empty()
     }
 
-    pub fn find<Q>(&self, q: &Q) -> Option<CountedHandle<'static>>
+    /// Panic naming the first key mutated since insert; see
+    /// `HandleHashMap::validate`.
+    pub fn validate(&self)
+    where
+        K: core::fmt::Debug,
+    {
+        self.inner.validate()
+    }
+
+    /// Largest `len()` reached since construction or the last reset.
+    pub fn peak_len(&self) -> usize {
+        self.inner.peak_len()
+    }
+
+    /// Restart peak tracking from the current `len()`.
+    pub fn reset_peak_len(&mut self) {
+        self.inner.reset_peak_len()
+    }
+
+    /// Occupancy of the slot storage; see `HandleHashMap::slot_stats`.
+    pub fn slot_stats(&self) -> SlotStats {
+        self.inner.slot_stats()
+    }
+
+    /// Estimated bytes allocated by the map's storage, refcounts included.
+    /// See `HandleHashMap::allocated_bytes`.
+    pub fn allocated_bytes(&self) -> usize {
+        self.inner.allocated_bytes()
+    }
+
+    /// Look up `q` and mint a token for the entry if present.
+    pub fn find<Q>(&self, q: &Q) -> Option<CountedHandle<'static, C>>
     where
         K: core::borrow::Borrow<Q>,
         Q: ?Sized + core::hash::Hash + Eq,
@@ -147,9 +619,122 @@ where
         let entry = self.inner.handle_value(handle)?;
         let counter = &entry.refcount;
         let token = counter.get();
-        Some(CountedHandle { handle, token })
+        Some(CountedHandle {
+            handle,
+            token,
+            brand: self.brand,
+        })
+    }
+
+    /// Look up `q`, mint a token for the entry if present, and borrow its
+    /// value mutably, with a single probe.
+    pub fn find_mut<Q>(&mut self, q: &Q) -> Option<(CountedHandle<'static, C>, &mut V)>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let handle = self.inner.find(q)?;
+        let entry = self.inner.handle_value_mut(handle)?;
+        let token = entry.refcount.get();
+        let ch = CountedHandle {
+            handle,
+            token,
+            brand: self.brand,
+        };
+        Some((ch, &mut entry.value))
+    }
+
+    /// Look up a batch of keys, minting a token for each hit. Results are
+    /// reported per key in input order.
+    pub fn find_many<'q, Q, I>(&self, keys: I) -> Vec<Option<CountedHandle<'static, C>>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        self.inner
+            .find_many(keys)
+            .into_iter()
+            .map(|h| {
+                let handle = h?;
+                let token = self.inner.handle_value(handle)?.refcount.get();
+                Some(CountedHandle {
+                    handle,
+                    token,
+                    brand: self.brand,
+                })
+            })
+            .collect()
+    }
+
+    /// Borrow the value for `q` without minting a token.
+    pub fn get_value<Q>(&self, q: &Q) -> Option<&V>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let handle = self.inner.find(q)?;
+        self.inner.handle_value(handle).map(|c| &c.value)
     }
 
+    /// Mutably borrow the value for `q` without minting a token.
+    pub fn get_value_mut<Q>(&mut self, q: &Q) -> Option<&mut V>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let handle = self.inner.find(q)?;
+        self.inner.handle_value_mut(handle).map(|c| &mut c.value)
+    }
+
+    /// Some live entry's plain `Handle`; see `HandleHashMap::any_handle`.
+    /// Does not mint a token.
+    pub fn any_handle(&self) -> Option<Handle> {
+        self.inner.any_handle()
+    }
+
+    /// The plain `Handle` at position `i`; see `HandleHashMap::get_index`.
+    /// Does not mint a token.
+    pub fn get_index(&self, i: usize) -> Option<Handle> {
+        self.inner.get_index(i)
+    }
+
+    /// Position of `q`'s entry; see `HandleHashMap::get_index`.
+    pub fn get_index_of<Q>(&self, q: &Q) -> Option<usize>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.inner.get_index_of(q)
+    }
+
+    /// Look up `q` and return its plain `Handle`. Does not mint a token.
+    pub fn handle_of<Q>(&self, q: &Q) -> Option<Handle>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.inner.find(q)
+    }
+
+    /// Re-pack entries after heavy churn; see `HandleHashMap::compact`. Only
+    /// possible while no tokens are outstanding, since live `CountedHandle`s
+    /// cannot be translated. Returns `(old, new)` pairs for plain handles,
+    /// e.g. those of resident entries.
+    pub fn compact(&mut self) -> Result<Vec<(Handle, Handle)>, RefsOutstanding> {
+        let count: usize = self
+            .inner
+            .iter()
+            .map(|(_, _, c)| c.refcount.count())
+            .chain(self.orphans.values().map(|c| c.count()))
+            .sum();
+        if count > 0 {
+            return Err(RefsOutstanding { count });
+        }
+        Ok(self.inner.compact())
+    }
+
+    /// Returns true if `q` is present. Does not mint a token.
     pub fn contains_key<Q>(&self, q: &Q) -> bool
     where
         K: core::borrow::Borrow<Q>,
@@ -158,9 +743,128 @@ where
         self.inner.contains_key(q)
     }
 
+    /// Returns true if `handle` refers to a live entry. Does not mint a token.
+    pub fn contains_handle(&self, handle: Handle) -> bool {
+        self.inner.contains_handle(handle)
+    }
+
+    // Resolve a live handle's entry after checking it was minted here.
+    #[track_caller]
+    fn entry(&self, h: &CountedHandle<'_, C>) -> &Counted<V, C> {
+        self.brand.check(h.brand);
+        self.inner
+            .handle_value(h.handle)
+            .expect("handle must be valid while counted handle is live")
+    }
+
+    // Resolve a handle's counter, which is set aside if the entry was
+    // force-removed.
+    #[track_caller]
+    fn counter(&self, h: &CountedHandle<'_, C>) -> &C {
+        self.brand.check(h.brand);
+        match self.inner.handle_value(h.handle) {
+            Some(c) => &c.refcount,
+            None => self
+                .orphans
+                .get(&h.handle)
+                .expect("handle must be valid while counted handle is live"),
+        }
+    }
+
+    /// Current number of outstanding tokens for the entry behind `h`
+    /// (always at least 1, since `h` itself holds one). For a stale handle,
+    /// the number of stale handles outstanding.
+    pub fn refcount(&self, h: &CountedHandle<'_, C>) -> usize {
+        self.counter(h).count()
+    }
+
+    /// Highest refcount the entry behind `h` ever reached, or `None` if the
+    /// counter does not track it (see `tokens::HighWaterCount`).
+    pub fn high_water(&self, h: &CountedHandle<'_, C>) -> Option<usize> {
+        self.counter(h).high_water()
+    }
+
+    /// Tear down the map, returning every entry's key, value and count of
+    /// outstanding handles. Those handles can no longer be `put`; dispose of
+    /// them with `ClosedMap::abandon`. Handles left stale by `force_remove`
+    /// are not reported, but may be abandoned the same way.
+    pub fn close(self) -> ClosedMap<K, V, C> {
+        let entries = self
+            .inner
+            .into_raw_parts()
+            .slots
+            .into_iter()
+            .map(|(_, e)| {
+                let n = e.value.refcount.count();
+                (e.key, e.value.value, n)
+            })
+            .collect();
+        ClosedMap {
+            entries,
+            brand: self.brand,
+            _ctr: core::marker::PhantomData,
+        }
+    }
+
+    /// Remove the entry behind `handle` even while tokens are outstanding,
+    /// returning its `(K, V)`, or `None` for a stale handle. Outstanding
+    /// `CountedHandle`s become stale; see the module docs.
+    pub fn force_remove(&mut self, handle: Handle) -> Option<(K, V)> {
+        let (k, c) = self.inner.remove(handle)?;
+        if !c.refcount.is_zero() {
+            self.orphans.insert(handle, c.refcount);
+        }
+        Some((k, c.value))
+    }
+
+    // Return a stale handle's token to its set-aside counter, dropping the
+    // counter with the last one. Returns the stale handles left.
+    fn put_orphan(&mut self, handle: Handle, token: C::Token<'_>) -> usize {
+        let c = self
+            .orphans
+            .get(&handle)
+            .expect("CountedHandle must refer to a live or force-removed entry");
+        let now_zero = c.put(token);
+        let remaining = c.count();
+        if now_zero {
+            self.orphans.remove(&handle);
+        }
+        remaining
+    }
+
+    /// Mint a token for the entry behind a plain `Handle`, or `None` if the
+    /// handle is stale.
+    pub fn get_by_handle(&self, handle: Handle) -> Option<CountedHandle<'static, C>> {
+        let token = self.inner.handle_value(handle)?.refcount.get();
+        Some(CountedHandle {
+            handle,
+            token,
+            brand: self.brand,
+        })
+    }
+
+    /// Current refcount for the entry behind a plain `Handle`, or `None` if
+    /// the handle is stale.
+    pub fn refcount_of(&self, handle: Handle) -> Option<usize> {
+        self.inner.handle_value(handle).map(|c| c.refcount.count())
+    }
+
+    /// Move the value out of a counted entry, leaving `V::default()` behind.
+    /// The refcount and the entry's liveness are unaffected.
+    pub fn take_value(&mut self, h: &CountedHandle<'_, C>) -> V
+    where
+        V: Default,
+    {
+        self.brand.check(h.brand);
+        let entry = self
+            .inner
+            .handle_value_mut(h.handle)
+            .expect("handle must be valid while counted handle is live");
+        core::mem::take(&mut entry.value)
+    }
+
     /// Insert a new key -> value and mint a token for the returned handle.
-    #[allow(dead_code)]
-    pub fn insert(&mut self, key: K, value: V) -> Result<CountedHandle<'static>, InsertError> {
+    pub fn insert(&mut self, key: K, value: V) -> Result<CountedHandle<'static, C>, InsertError> {
         let counted = Counted::new(value, 0);
         match self.inner.insert(key, counted) {
             Ok(handle) => {
@@ -170,32 +874,156 @@ where
                     .expect("entry must exist immediately after successful insert");
                 let counter = &entry.refcount;
                 let token = counter.get();
-                Ok(CountedHandle { handle, token })
+                Ok(CountedHandle {
+                    handle,
+                    token,
+                    brand: self.brand,
+                })
             }
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
     /// Mint another token for the same entry; used to clone a counted handle.
-    pub fn get(&self, h: &CountedHandle<'_>) -> CountedHandle<'static> {
-        // Validate the handle still refers to a live entry while the existing token is held.
-        let entry = self
-            .inner
-            .handle_value(h.handle)
-            .expect("handle must be valid while counted handle is live");
-        let token = entry.refcount.get();
+    pub fn get(&self, h: &CountedHandle<'_, C>) -> CountedHandle<'static, C> {
+        // Validate the handle still refers to an entry while the existing token is held.
+        let token = self.counter(h).get();
         CountedHandle {
             handle: h.handle,
             token,
+            brand: self.brand,
         }
     }
 
+    /// Insert an entry with zero outstanding tokens. The entry is resident:
+    /// it stays in the map when its count drops back to zero and is only
+    /// removed by an explicit `purge`/`purge_if` sweep.
+    pub fn insert_detached(&mut self, key: K, value: V) -> Result<Handle, InsertError> {
+        let mut counted = Counted::new(value, 0);
+        counted.resident = true;
+        self.inner.insert(key, counted).map_err(Into::into)
+    }
+
+    /// Remove every entry with no outstanding tokens, returning their
+    /// `(K, V)` pairs.
+    pub fn purge(&mut self) -> Vec<(K, V)> {
+        self.purge_if(|_, _| true)
+    }
+
+    /// Remove entries with no outstanding tokens for which `pred` returns
+    /// true, returning their `(K, V)` pairs. Entries with outstanding tokens
+    /// are never removed.
+    pub fn purge_if<F>(&mut self, mut pred: F) -> Vec<(K, V)>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let victims: Vec<Handle> = self
+            .inner
+            .iter()
+            .filter(|(_, k, c)| c.refcount.is_zero() && pred(k, &c.value))
+            .map(|(h, _, _)| h)
+            .collect();
+        self.inner
+            .remove_many(victims)
+            .into_iter()
+            .flatten()
+            .map(|(k, c)| (k, c.value))
+            .collect()
+    }
+
+    /// Remove an entry that has no outstanding tokens (e.g. a resident entry
+    /// from `insert_detached`). Returns `Ok(None)` for a stale handle and
+    /// `Err(RefsOutstanding)` without modifying the map if any token is live.
+    pub fn try_remove(&mut self, handle: Handle) -> Result<Option<(K, V)>, RefsOutstanding> {
+        match self.inner.handle_value(handle) {
+            None => Ok(None),
+            Some(c) if !c.refcount.is_zero() => Err(RefsOutstanding {
+                count: c.refcount.count(),
+            }),
+            Some(_) => {
+                let (k, c) = self
+                    .inner
+                    .remove(handle)
+                    .expect("entry must exist after successful lookup");
+                Ok(Some((k, c.value)))
+            }
+        }
+    }
+
+    /// Remove the entry behind `h` if `h` holds its only token, consuming the
+    /// token and returning `(K, V)` even for resident entries. Otherwise the
+    /// handle is given back alongside the number of other outstanding tokens.
+    #[allow(clippy::type_complexity)]
+    pub fn try_remove_handle<'a>(
+        &mut self,
+        h: CountedHandle<'a, C>,
+    ) -> Result<(K, V), (CountedHandle<'a, C>, RefsOutstanding)> {
+        let count = self.entry(&h).refcount.count();
+        if count > 1 {
+            return Err((h, RefsOutstanding { count: count - 1 }));
+        }
+        let CountedHandle { handle, token, .. } = h;
+        let entry = self
+            .inner
+            .handle_value(handle)
+            .expect("entry must exist after successful lookup");
+        let now_zero = entry.refcount.put(token);
+        debug_assert!(now_zero);
+        let (k, c) = self
+            .inner
+            .remove(handle)
+            .expect("entry must exist when count reaches zero");
+        Ok((k, c.value))
+    }
+
+    /// Mint `n` additional handles for the same entry with one refcount update.
+    pub fn get_n(&self, h: &CountedHandle<'_, C>, n: usize) -> Vec<CountedHandle<'static, C>> {
+        self.counter(h)
+            .get_n(n)
+            .into_iter()
+            .map(|token| CountedHandle {
+                handle: h.handle,
+                token,
+                brand: self.brand,
+            })
+            .collect()
+    }
+
+    /// Return a batch of handles. Consecutive handles to the same entry are
+    /// returned with a single refcount update. Returns the `(K, V)` of every
+    /// entry whose count reached zero, in the order they were removed.
+    pub fn put_n(&mut self, handles: Vec<CountedHandle<'_, C>>) -> Vec<(K, V)> {
+        let mut removed = Vec::new();
+        let brand = self.brand;
+        let mut it = handles.into_iter().map(|h| brand.check_owned(h)).peekable();
+        while let Some(CountedHandle { handle, token, .. }) = it.next() {
+            let mut run = vec![token];
+            while let Some(next) = it.next_if(|n| n.handle == handle) {
+                run.push(next.token);
+            }
+            let Some(entry) = self.inner.handle_value(handle) else {
+                for token in run {
+                    self.put_orphan(handle, token);
+                }
+                continue;
+            };
+            if entry.refcount.put_n(run) && !entry.resident {
+                let (k, v) = self
+                    .inner
+                    .remove(handle)
+                    .expect("entry must exist when count reaches zero");
+                removed.push((k, v.value));
+            }
+        }
+        removed
+    }
+
     /// Insert using a lazy value constructor; only calls `default()` when inserting.
     pub fn insert_with<F>(
         &mut self,
         key: K,
         default: F,
-    ) -> Result<CountedHandle<'static>, InsertError>
+    ) -> Result<CountedHandle<'static, C>, InsertError>
     where
         F: FnOnce() -> V,
     {
@@ -206,21 +1034,142 @@ where
                     .handle_value(handle)
                     .expect("entry must exist immediately after successful insert");
                 let token = entry.refcount.get();
-                Ok(CountedHandle { handle, token })
+                Ok(CountedHandle {
+                    handle,
+                    token,
+                    brand: self.brand,
+                })
             }
-            Err(e) => Err(e),
+            Err(e) => Err(e.into()),
         }
     }
 
-    /// Return a token for an entry; removes and returns (K, V) when count hits zero.
-    pub fn put(&mut self, h: CountedHandle<'_>) -> PutResult<K, V> {
-        let CountedHandle { handle, token, .. } = h;
+    /// Insert using a lazy constructor that borrows the key; only calls
+    /// `make(&key)` when inserting.
+    pub fn insert_with_key<F>(
+        &mut self,
+        key: K,
+        make: F,
+    ) -> Result<CountedHandle<'static, C>, InsertError>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        let handle = self
+            .inner
+            .insert_with_key(key, |k| Counted::new(make(k), 0))?;
+        let entry = self
+            .inner
+            .handle_value(handle)
+            .expect("entry must exist immediately after successful insert");
+        let token = entry.refcount.get();
+        Ok(CountedHandle {
+            handle,
+            token,
+            brand: self.brand,
+        })
+    }
+
+    /// Insert using a fallible lazy constructor; mints a token only when the
+    /// entry is inserted. On failure the map is unchanged.
+    pub fn insert_with_result<F, E>(
+        &mut self,
+        key: K,
+        make: F,
+    ) -> Result<CountedHandle<'static, C>, TryInsertError<E>>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        let handle = self
+            .inner
+            .insert_with_result(key, || make().map(|v| Counted::new(v, 0)))?;
         let entry = self
             .inner
             .handle_value(handle)
-            .expect("CountedHandle must refer to a live entry when returned to put()");
+            .expect("entry must exist immediately after successful insert");
+        let token = entry.refcount.get();
+        Ok(CountedHandle {
+            handle,
+            token,
+            brand: self.brand,
+        })
+    }
+
+    /// Wrap a handle in a `CountedGuard` that returns its token on drop.
+    pub fn guard(&mut self, h: CountedHandle<'static, C>) -> CountedGuard<'_, K, V, S, C> {
+        CountedGuard {
+            map: self,
+            handle: ManuallyDrop::new(h),
+        }
+    }
+
+    /// Look up `q` and return a guard for the entry if present.
+    pub fn find_guard<Q>(&mut self, q: &Q) -> Option<CountedGuard<'_, K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let h = self.find(q)?;
+        Some(self.guard(h))
+    }
+
+    // Insert a key known to be absent under its precomputed hash; see
+    // `HandleHashMap::insert_unique_hashed`.
+    pub(crate) fn insert_unique_hashed(
+        &mut self,
+        hash: StoredHash,
+        key: K,
+        value: V,
+    ) -> CountedHandle<'static, C> {
+        let handle = self
+            .inner
+            .insert_unique_hashed(hash, key, Counted::new(value, 0));
+        let entry = self
+            .inner
+            .handle_value(handle)
+            .expect("entry must exist immediately after successful insert");
+        CountedHandle {
+            handle,
+            token: entry.refcount.get(),
+            brand: self.brand,
+        }
+    }
+
+    /// Find `key` or insert `default()` under it, with a single probe, and
+    /// mint a token for the entry either way. The flag is true when a new
+    /// entry was inserted.
+    pub fn find_or_insert_with<F>(
+        &mut self,
+        key: K,
+        default: F,
+    ) -> (CountedHandle<'static, C>, bool)
+    where
+        F: FnOnce() -> V,
+    {
+        let (handle, inserted) = self
+            .inner
+            .find_or_insert_with(key, || Counted::new(default(), 0));
+        let entry = self
+            .inner
+            .handle_value(handle)
+            .expect("entry must exist after find_or_insert_with");
+        let token = entry.refcount.get();
+        let ch = CountedHandle {
+            handle,
+            token,
+            brand: self.brand,
+        };
+        (ch, inserted)
+    }
+
+    /// Return a token for an entry; removes and returns (K, V) when count hits zero.
+    pub fn put(&mut self, h: CountedHandle<'_, C>) -> PutResult<K, V> {
+        let CountedHandle { handle, token, .. } = self.brand.check_owned(h);
+        let Some(entry) = self.inner.handle_value(handle) else {
+            let remaining = self.put_orphan(handle, token);
+            return PutResult::Stale { remaining };
+        };
         let now_zero = entry.refcount.put(token);
-        if now_zero {
+        if now_zero && !entry.resident {
             let (k, v) = self
                 .inner
                 .remove(handle)
@@ -230,35 +1179,128 @@ where
                 value: v.value,
             }
         } else {
-            PutResult::Live
+            PutResult::Live {
+                remaining: entry.refcount.count(),
+            }
         }
     }
 
-    // Simple iterators yield the same item shapes as HandleHashMap.
-    // For internal use, iter_raw and iter_mut_raw mint CountedHandles; callers must put() them.
+    /// Return a batch of tokens, then unlink every entry that reached zero in
+    /// one pass. Returns the removed `(K, V)` pairs.
+    pub fn put_many<'h, I>(&mut self, hs: I) -> Vec<(K, V)>
+    where
+        C: 'h,
+        I: IntoIterator<Item = CountedHandle<'h, C>>,
+    {
+        let mut removed = Vec::new();
+        self.put_many_with(hs, |_, k, v| removed.push((k, v)));
+        removed
+    }
 
-    #[allow(dead_code)]
-    pub fn iter(&self) -> impl Iterator<Item = (Handle, &K, &V)> {
+    // Shared body of `put_many`; `removed` also receives the entry's handle.
+    pub(crate) fn put_many_with<'h, I, R>(&mut self, hs: I, mut removed: R)
+    where
+        C: 'h,
+        I: IntoIterator<Item = CountedHandle<'h, C>>,
+        R: FnMut(Handle, K, V),
+    {
+        let mut dead = Vec::new();
+        for h in hs {
+            let CountedHandle { handle, token, .. } = self.brand.check_owned(h);
+            let Some(entry) = self.inner.handle_value(handle) else {
+                self.put_orphan(handle, token);
+                continue;
+            };
+            if entry.refcount.put(token) && !entry.resident {
+                dead.push(handle);
+            }
+        }
+        let unlinked = self.inner.remove_many(dead.iter().copied());
+        for (h, kv) in dead.into_iter().zip(unlinked) {
+            let (k, c) = kv.expect("entry must exist when count reaches zero");
+            removed(h, k, c.value);
+        }
+    }
+
+    // Simple iterators yield the same item shapes as HandleHashMap and do not
+    // mint tokens. The guarded iterators are the public token-holding API.
+    // For internal use, iter_raw and iter_mut_raw mint CountedHandles;
+    // callers must put() them.
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &K, &V)> + Clone {
         self.inner.iter().map(|(h, k, c)| (h, k, &c.value))
     }
 
-    #[allow(dead_code)]
+    /// Live entries with their current outstanding token counts, in
+    /// iteration order, e.g. for a debug dashboard of what keeps the map
+    /// populated. Does not mint tokens.
+    pub fn iter_counts(&self) -> impl Iterator<Item = (Handle, &K, usize)> + Clone {
+        self.inner
+            .iter()
+            .map(|(h, k, c)| (h, k, c.refcount.count()))
+    }
+
+    /// Entries whose outstanding token count satisfies `pred`, e.g.
+    /// `|n| n == 1` for uniquely held entries. Does not mint tokens.
+    pub fn iter_where_count<F>(&self, mut pred: F) -> impl Iterator<Item = (Handle, &K, &V)>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        self.inner
+            .iter()
+            .filter(move |(_, _, c)| pred(c.refcount.count()))
+            .map(|(h, k, c)| (h, k, &c.value))
+    }
+
+    /// Entries with their outstanding token counts, highest count first
+    /// (ties in iteration order). For "hottest entries" diagnostics; does
+    /// not mint tokens. Collects and sorts up front.
+    pub fn iter_by_refcount(&self) -> impl Iterator<Item = (Handle, &K, &V, usize)> {
+        let mut v: Vec<_> = self
+            .inner
+            .iter()
+            .map(|(h, k, c)| (h, k, &c.value, c.refcount.count()))
+            .collect();
+        v.sort_by_key(|e| core::cmp::Reverse(e.3));
+        v.into_iter()
+    }
+
     pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle, &K, &mut V)> {
         self.inner.iter_mut().map(|(h, k, c)| (h, k, &mut c.value))
     }
 
-    pub(crate) fn iter_raw(&self) -> Iter<'_, K, V, S> {
+    /// Iterate with an `ItemGuard` per entry. Each guard holds a token for
+    /// its entry and returns it on drop; use `ItemGuard::to_handle` to keep
+    /// an entry referenced past the iteration.
+    pub fn iter_guarded(&self) -> GuardIter<'_, K, V, S, C> {
+        GuardIter {
+            it: self.inner.iter(),
+            brand: self.brand,
+        }
+    }
+
+    /// Mutable counterpart of `iter_guarded`; guards dereference mutably.
+    pub fn iter_mut_guarded(&mut self) -> GuardIterMut<'_, K, V, S, C> {
+        GuardIterMut {
+            it: self.inner.iter_mut(),
+            brand: self.brand,
+        }
+    }
+
+    pub(crate) fn iter_raw(&self) -> Iter<'_, K, V, S, C> {
         let it = self.inner.iter();
         Iter {
             it,
+            brand: self.brand,
             _pd: core::marker::PhantomData,
         }
     }
 
-    pub(crate) fn iter_mut_raw(&mut self) -> IterMut<'_, K, V, S> {
+    pub(crate) fn iter_mut_raw(&mut self) -> IterMut<'_, K, V, S, C> {
         let it = self.inner.iter_mut();
         IterMut {
             it,
+            brand: self.brand,
             _pd: core::marker::PhantomData,
         }
     }
@@ -317,7 +1359,8 @@ mod tests {
                     3 => {
                         if let Some(h) = live[k].pop() {
                             match m.put(h) {
-                                PutResult::Live => {}
+                                PutResult::Live { .. } => {}
+                                PutResult::Stale { .. } => unreachable!(),
                                 PutResult::Removed { key: _, value: _ } => {
                                     // After removal there should be no more live handles for this key
                                     // (since this was the last token).
@@ -405,17 +1448,18 @@ mod tests {
     }
 
     /// `get` clones a counted handle by minting a new token for the same
-    /// entry. Returning one of two handles leaves the entry live; returning
-    /// the last one removes the entry and returns `(K, V)`.
+    /// entry. Returning one of two handles leaves the entry live and reports
+    /// one remaining token; returning the last one removes the entry and
+    /// returns `(K, V)`.
     #[test]
     fn get_mints_new_token_and_put_removes_at_zero() {
         let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
         let h1 = m.insert("a", 1).unwrap();
         let h2 = m.get(&h1);
 
-        // Returning one of them leaves the entry live
+        // Returning one of them leaves the entry live with one token remaining
         match m.put(h1) {
-            PutResult::Live => {}
+            PutResult::Live { remaining } => assert_eq!(remaining, 1),
             _ => panic!("expected Live when one handle remains"),
         }
         assert!(m.contains_key(&"a"));
@@ -431,6 +1475,207 @@ mod tests {
         assert!(!m.contains_key(&"a"));
     }
 
+    /// `refcount`/`refcount_of` track outstanding tokens; a stale handle
+    /// reports `None` after the entry is removed.
+    #[test]
+    fn refcount_reports_outstanding_tokens() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let h1 = m.insert("a", 1).unwrap();
+        let raw = h1.handle;
+        assert_eq!(m.refcount(&h1), 1);
+        let h2 = m.get(&h1);
+        let h3 = m.find(&"a").unwrap();
+        assert_eq!(m.refcount(&h2), 3);
+        assert_eq!(m.refcount_of(raw), Some(3));
+        let _ = m.put(h1);
+        let _ = m.put(h2);
+        assert_eq!(m.refcount(&h3), 1);
+        let _ = m.put(h3);
+        assert_eq!(m.refcount_of(raw), None);
+    }
+
+    /// `close` reports outstanding handles per entry, and `abandon` disposes
+    /// of the orphaned handles without panicking.
+    #[test]
+    fn close_then_abandon_outstanding_handles() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let a2 = m.get(&a);
+        let b = m.insert("b", 2).unwrap();
+        let _ = m.put(b);
+        m.insert_detached("c", 3).unwrap();
+        let closed = m.close();
+        let mut left = closed.entries.to_vec();
+        left.sort();
+        assert_eq!(left, [("a", 1, 2), ("c", 3, 0)]);
+        closed.abandon(a);
+        closed.abandon(a2);
+    }
+
+    /// Invariant (debug): a closed map only abandons handles it minted.
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "did not mint it")]
+    fn closed_map_rejects_foreign_handles() {
+        let mut live: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let h = live.insert("a", 1).unwrap();
+        let closed = CountedHashMap::<&'static str, i32>::new().close();
+        closed.abandon(h);
+    }
+
+    /// `iter_where_count` yields exactly the entries whose count matches.
+    #[test]
+    fn iter_where_count_filters_by_count() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let a2 = m.get(&a);
+        let b = m.insert("b", 2).unwrap();
+        m.insert_detached("c", 3).unwrap();
+        let keys = |m: &CountedHashMap<_, _>, n: usize| {
+            let mut v: Vec<_> = m.iter_where_count(|c| c == n).map(|(_, k, _)| *k).collect();
+            v.sort();
+            v
+        };
+        assert_eq!(keys(&m, 0), ["c"]);
+        assert_eq!(keys(&m, 1), ["b"]);
+        assert_eq!(keys(&m, 2), ["a"]);
+        assert_eq!(m.iter_where_count(|c| c > 2).count(), 0);
+        for h in [a, a2, b] {
+            let _ = m.put(h);
+        }
+    }
+
+    /// `iter_counts` reports each live entry's current token count and
+    /// mints none itself.
+    #[test]
+    fn iter_counts_reports_current_counts() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let a2 = m.get(&a);
+        let b = m.insert("b", 2).unwrap();
+        let mut counts: Vec<_> = m.iter_counts().map(|(h, k, n)| (h, *k, n)).collect();
+        counts.sort_by_key(|c| c.1);
+        assert_eq!(counts, [(a.handle, "a", 2), (b.handle, "b", 1)]);
+        let _ = m.put(a2);
+        assert!(m.iter_counts().any(|(_, k, n)| *k == "a" && n == 1));
+        let _ = m.put(a);
+        let _ = m.put(b);
+        assert_eq!(m.iter_counts().count(), 0);
+    }
+
+    /// `get_n` fans an entry out to `n` handles; `put_n` returns them in bulk
+    /// and reports removals only for entries whose count reached zero.
+    #[test]
+    fn get_n_and_put_n_balance() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let b = m.insert("b", 2).unwrap();
+        let mut fan = m.get_n(&a, 3);
+        assert_eq!(fan.len(), 3);
+        assert_eq!(m.refcount(&a), 4);
+
+        fan.push(b);
+        let removed = m.put_n(fan);
+        assert_eq!(removed, vec![("b", 2)]);
+        assert_eq!(m.refcount(&a), 1);
+
+        let removed = m.put_n(vec![a]);
+        assert_eq!(removed, vec![("a", 1)]);
+        assert!(m.is_empty());
+    }
+
+    /// Detached entries start at zero, survive `put` of their last token,
+    /// and are removed only by `purge`/`purge_if`, which skip entries with
+    /// outstanding tokens.
+    #[test]
+    fn insert_detached_resident_until_purged() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let ha = m.insert_detached("a", 1).unwrap();
+        m.insert_detached("b", 2).unwrap();
+        m.insert_detached("c", 3).unwrap();
+        assert!(m.insert_detached("a", 9).is_err());
+        assert_eq!(m.refcount_of(ha), Some(0));
+
+        let t = m.find(&"a").unwrap();
+        assert!(matches!(m.put(t), PutResult::Live { .. }));
+        assert!(m.contains_key(&"a"));
+
+        let held = m.find(&"b").unwrap();
+        let mut removed = m.purge_if(|k, _| *k != "c");
+        removed.sort();
+        assert_eq!(removed, vec![("a", 1)]);
+
+        let removed = m.purge();
+        assert_eq!(removed, vec![("c", 3)]);
+        assert!(m.contains_key(&"b"));
+        assert!(matches!(m.put(held), PutResult::Live { .. }));
+        assert_eq!(m.purge(), vec![("b", 2)]);
+        assert!(m.is_empty());
+    }
+
+    /// Checked removal refuses entries with outstanding tokens and reports
+    /// how many; it succeeds for zero-count residents and for a caller
+    /// holding the sole token.
+    #[test]
+    fn try_remove_checks_outstanding_refs() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let r = m.insert_detached("r", 0).unwrap();
+        let held = m.find(&"r").unwrap();
+        assert_eq!(m.try_remove(r), Err(RefsOutstanding { count: 1 }));
+        let _ = m.put(held);
+        assert_eq!(m.try_remove(r), Ok(Some(("r", 0))));
+        assert_eq!(m.try_remove(r), Ok(None));
+
+        let a = m.insert("a", 1).unwrap();
+        let a2 = m.get(&a);
+        let a = match m.try_remove_handle(a) {
+            Err((h, e)) => {
+                assert_eq!(e, RefsOutstanding { count: 1 });
+                h
+            }
+            Ok(_) => panic!("expected RefsOutstanding"),
+        };
+        let _ = m.put(a2);
+        assert!(matches!(m.try_remove_handle(a), Ok(("a", 1))));
+        assert!(m.is_empty());
+    }
+
+    /// Invariant: `force_remove` unlinks an entry with tokens outstanding;
+    /// the stale handles still clone and put, touching no live entry, and
+    /// `compact` counts them.
+    #[test]
+    fn force_remove_leaves_stale_handles() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let h = a.handle;
+        assert_eq!(m.force_remove(h), Some(("a", 1)));
+        assert_eq!(m.force_remove(h), None);
+        let a2 = m.get(&a);
+        assert_eq!(m.refcount(&a2), 2);
+        assert_eq!(m.compact(), Err(RefsOutstanding { count: 2 }));
+        let b = m.insert("a", 2).unwrap();
+        assert!(matches!(m.put(a), PutResult::Stale { remaining: 1 }));
+        assert!(m.put_many([a2]).is_empty());
+        assert_eq!(m.refcount(&b), 1);
+        assert!(matches!(m.put(b), PutResult::Removed { value: 2, .. }));
+        assert_eq!(m.compact(), Ok(Vec::new()));
+    }
+
+    /// `find_or_insert_with` mints a token on both the insert and the hit
+    /// path, so the entry lives until both handles are returned.
+    #[test]
+    fn find_or_insert_with_mints_on_hit_and_miss() {
+        let mut m: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let (h1, inserted) = m.find_or_insert_with("k", || 1);
+        assert!(inserted);
+        let (h2, inserted) = m.find_or_insert_with("k", || 2);
+        assert!(!inserted);
+        assert_eq!(h2.value_ref(&m), Some(&1));
+        assert_eq!(m.refcount(&h1), 2);
+        assert!(matches!(m.put(h1), PutResult::Live { remaining: 1 }));
+        assert!(matches!(m.put(h2), PutResult::Removed { .. }));
+    }
+
     /// `key_ref`/`value_ref` return references tied to the map borrow and
     /// reflect the current storage; `value_mut` updates persist.
     #[test]
@@ -477,6 +1722,41 @@ mod tests {
         }
     }
 
+    /// Guarded iteration returns every token automatically: refcounts are
+    /// unchanged afterwards, `DerefMut` updates persist, and `to_handle`
+    /// keeps an entry alive past the loop.
+    #[test]
+    fn guarded_iterators_balance_tokens() {
+        let mut m: CountedHashMap<String, i32> = CountedHashMap::new();
+        let ha = m.insert("a".to_string(), 1).unwrap();
+        let hb = m.insert("b".to_string(), 2).unwrap();
+
+        assert_eq!(m.iter_guarded().len(), 2);
+        let mut kept = Vec::new();
+        for g in m.iter_guarded() {
+            assert_eq!(m.refcount_of(g.handle()), Some(2));
+            if g.key() == "a" {
+                assert_eq!(*g, 1);
+                kept.push(g.to_handle());
+            }
+        }
+        assert_eq!(m.refcount(&ha), 2);
+        assert_eq!(m.refcount(&hb), 1);
+
+        for mut g in m.iter_mut_guarded() {
+            *g += 10;
+        }
+        assert_eq!(ha.value_ref(&m), Some(&11));
+        assert_eq!(hb.value_ref(&m), Some(&12));
+
+        assert!(matches!(m.put(ha), PutResult::Live { .. }));
+        let _ = m.put(hb);
+        assert!(m.contains_key("a"));
+        assert!(!m.contains_key("b"));
+        let removed = m.put_n(kept);
+        assert_eq!(removed, vec![("a".to_string(), 11)]);
+    }
+
     /// `iter_raw` mints a `CountedHandle` per entry for scoped work. These
     /// raw handles keep entries live until explicitly returned to `put`.
     /// Dropping the original handles while the raw handles are outstanding
@@ -495,15 +1775,15 @@ mod tests {
 
         // Drop the original handles; entries must remain live due to raw handles
         match m.put(h1) {
-            PutResult::Live => {}
+            PutResult::Live { .. } => {}
             _ => panic!("expected Live"),
         }
         match m.put(h2) {
-            PutResult::Live => {}
+            PutResult::Live { .. } => {}
             _ => panic!("expected Live"),
         }
         match m.put(h3) {
-            PutResult::Live => {}
+            PutResult::Live { .. } => {}
             _ => panic!("expected Live"),
         }
         assert!(m.contains_key(&"a".to_string()));
@@ -523,7 +1803,8 @@ mod tests {
                         _ => unreachable!(),
                     }
                 }
-                PutResult::Live => {}
+                PutResult::Live { .. } => {}
+                PutResult::Stale { .. } => unreachable!(),
             }
         }
         assert_eq!(
@@ -555,8 +1836,8 @@ mod tests {
             .collect();
 
         // Return the original handles; entries remain live due to raw handles
-        assert!(matches!(m.put(h1), PutResult::Live));
-        assert!(matches!(m.put(h2), PutResult::Live));
+        assert!(matches!(m.put(h1), PutResult::Live { .. }));
+        assert!(matches!(m.put(h2), PutResult::Live { .. }));
         assert!(m.contains_key(&"x"));
         assert!(m.contains_key(&"y"));
 
@@ -581,7 +1862,8 @@ mod tests {
                         _ => unreachable!(),
                     }
                 }
-                PutResult::Live => {}
+                PutResult::Live { .. } => {}
+                PutResult::Stale { .. } => unreachable!(),
             }
         }
         assert_eq!(removed, 2);
@@ -589,6 +1871,120 @@ mod tests {
         assert!(!m.contains_key(&"y"));
     }
 
+    /// `CountedGuard` returns its token on drop, including on early return;
+    /// dropping the last guard removes the entry. `into_handle` detaches the
+    /// token so the entry survives the guard.
+    #[test]
+    fn guard_returns_token_on_drop() {
+        fn bump(m: &mut CountedHashMap<String, i32>, k: &str) -> Option<i32> {
+            let mut g = m.find_guard(k)?;
+            *g.value_mut() += 1;
+            if *g.value() > 1 {
+                return Some(*g.value()); // early return still puts the token
+            }
+            Some(0)
+        }
+
+        let mut m: CountedHashMap<String, i32> = CountedHashMap::new();
+        let h = m.insert("k".to_string(), 0).unwrap();
+        assert_eq!(bump(&mut m, "k"), Some(0));
+        assert_eq!(bump(&mut m, "k"), Some(2));
+        assert_eq!(bump(&mut m, "missing"), None);
+
+        // `h` is still the only token; guarding it and dropping removes the entry.
+        let g = m.guard(h);
+        assert_eq!(g.key(), "k");
+        drop(g);
+        assert!(m.is_empty());
+
+        let h = m.insert("d".to_string(), 1).unwrap();
+        let h = m.guard(h).into_handle();
+        assert!(m.contains_key("d"));
+        assert!(matches!(m.put(h), PutResult::Removed { .. }));
+    }
+
+    /// Debug-only: a handle minted by one map is rejected deterministically
+    /// by another map's accessors and `put`, even when its slot key would
+    /// resolve to a live entry there.
+    #[cfg(debug_assertions)]
+    #[test]
+    fn wrong_map_handle_panics_in_debug() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        let mut a: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let mut b: CountedHashMap<&'static str, i32> = CountedHashMap::new();
+        let ha = a.insert("x", 1).unwrap();
+        let hb = b.insert("y", 2).unwrap();
+
+        let res = catch_unwind(AssertUnwindSafe(|| b.refcount(&ha)));
+        assert!(res.is_err());
+        let res = catch_unwind(AssertUnwindSafe(|| ha.value_ref(&b).copied()));
+        assert!(res.is_err());
+
+        let stray = a.get(&ha);
+        let res = catch_unwind(AssertUnwindSafe(|| b.put(stray)));
+        assert!(res.is_err());
+        assert_eq!(b.refcount(&hb), 1, "b's entry must be untouched");
+
+        // The rejected put leaked the stray token's count on `a`.
+        assert_eq!(a.refcount(&ha), 2);
+        assert!(matches!(a.put(ha), PutResult::Live { .. }));
+        let _ = b.put(hb);
+    }
+
+    /// Invariant: `put_many` returns every token and removes exactly the
+    /// entries whose count reached zero.
+    #[test]
+    fn put_many_removes_entries_at_zero() {
+        let mut m: CountedHashMap<&str, i32> = CountedHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let a2 = m.get(&a);
+        let b = m.insert("b", 2).unwrap();
+        let c = m.insert("c", 3).unwrap();
+        let mut removed = m.put_many([a, b, c]);
+        removed.sort();
+        assert_eq!(removed, vec![("b", 2), ("c", 3)]);
+        assert_eq!(m.len(), 1);
+        assert_eq!(m.refcount(&a2), 1);
+        let _ = m.put(a2);
+        assert!(m.is_empty());
+    }
+
+    /// Invariant: `compact` refuses while tokens are outstanding and
+    /// otherwise translates resident entries' handles.
+    #[test]
+    fn compact_requires_no_outstanding_tokens() {
+        let mut m: CountedHashMap<u32, u32> = CountedHashMap::new();
+        let hs: Vec<_> = (0..20).map(|i| m.insert_detached(i, i).unwrap()).collect();
+        let held = m.find(&19).unwrap();
+        m.purge_if(|k, _| *k < 15);
+        assert_eq!(m.compact().unwrap_err(), RefsOutstanding { count: 1 });
+        let _ = m.put(held);
+        let moves = m.compact().unwrap();
+        assert_eq!(moves.len(), 5);
+        let (old, new) = moves[0];
+        assert!(hs.contains(&old));
+        assert_eq!(m.refcount_of(new), Some(0));
+        assert_eq!(m.slot_stats().free, 0);
+    }
+
+    /// Invariant: the map is generic over its per-entry counter; with
+    /// `CheckedCount` the token flow and removal-at-zero are unchanged.
+    #[test]
+    fn checked_count_map_roundtrip() {
+        use crate::tokens::CheckedCount;
+        let mut m: CountedHashMap<&str, i32, DefaultHashBuilder, CheckedCount> =
+            CountedHashMap::default();
+        let a = m.insert("a", 1).unwrap();
+        let b = m.get(&a);
+        assert_eq!(m.refcount(&a), 2);
+        assert!(matches!(m.put(a), PutResult::Live { remaining: 1 }));
+        assert!(matches!(
+            m.put(b),
+            PutResult::Removed { key: "a", value: 1 }
+        ));
+        assert!(m.is_empty());
+    }
+
     /// Negative behavior: dropping a `CountedHandle` without calling `put`
     /// must panic due to the underlying `Token`'s `Drop` implementation.
     /// Likewise, collecting raw handles from `iter_raw` and dropping them
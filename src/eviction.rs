@@ -0,0 +1,394 @@
+//! Eviction: pluggable victim selection for capacity-capped caches.
+//!
+//! An `EvictionPolicy` is told about inserts, accesses and removals by
+//! `Handle`, and when the cache is over capacity picks a victim among the
+//! entries the cache may drop: those nobody else holds a Ref to. The crate
+//! ships `LruPolicy`, `LfuPolicy` and `RandomPolicy`; `EvictingCache` wires
+//! any of them to an `RcHashMap` behind the `Cache` trait.
+//!
+//! `EvictingCache` pins every resident entry (see `RcHashMap::pin`), so an
+//! entry survives without external Refs until it is evicted. Entries still
+//! referenced elsewhere are never evicted; if every entry is referenced the
+//! cache temporarily exceeds its capacity rather than invalidating Refs.
+//!
+//! ```rust
+//! use rc_hashmap::eviction::{EvictingCache, LruPolicy};
+//! use rc_hashmap::Cache;
+//!
+//! let mut cache = EvictingCache::new(2, LruPolicy::new());
+//! drop(cache.get_or_insert_with(1, || "one").unwrap());
+//! drop(cache.get_or_insert_with(2, || "two").unwrap());
+//! drop(cache.get_or_insert_with(1, || "one").unwrap()); // 1 is now most recent
+//! drop(cache.get_or_insert_with(3, || "three").unwrap()); // evicts 2
+//! assert!(cache.map().contains_key(&1) && !cache.map().contains_key(&2));
+//! ```
+
+use crate::cache::{Cache, CacheStats};
+use crate::handle_hash_map::{CappedInsertError, Handle};
+use crate::hash::DefaultHashBuilder;
+use crate::rc_hash_map::{RcHashMap, Ref};
+use crate::tokens::{EntryCount, UsizeCount};
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use std::collections::HashMap;
+
+/// Victim selection strategy for a capacity-capped cache.
+pub trait EvictionPolicy {
+    /// A new entry became resident.
+    fn on_insert(&mut self, h: Handle);
+    /// A resident entry was looked up.
+    fn on_access(&mut self, h: Handle);
+    /// An entry left the cache, by eviction or invalidation.
+    fn on_remove(&mut self, h: Handle);
+    /// Choose an entry to evict among those for which `evictable` returns
+    /// true, or `None` if there is none. Does not remove it; the cache
+    /// reports the removal through `on_remove`.
+    fn pick_victim(&mut self, evictable: &dyn Fn(Handle) -> bool) -> Option<Handle>;
+}
+
+/// Least recently used: evicts the entry whose last insert or access is
+/// oldest. Victim selection scans the tracked entries.
+#[derive(Debug, Default)]
+pub struct LruPolicy {
+    tick: u64,
+    last_used: HashMap<Handle, u64>,
+}
+
+impl LruPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn touch(&mut self, h: Handle) {
+        self.tick += 1;
+        self.last_used.insert(h, self.tick);
+    }
+}
+
+impl EvictionPolicy for LruPolicy {
+    fn on_insert(&mut self, h: Handle) {
+        self.touch(h);
+    }
+    fn on_access(&mut self, h: Handle) {
+        self.touch(h);
+    }
+    fn on_remove(&mut self, h: Handle) {
+        self.last_used.remove(&h);
+    }
+    fn pick_victim(&mut self, evictable: &dyn Fn(Handle) -> bool) -> Option<Handle> {
+        self.last_used
+            .iter()
+            .filter(|(h, _)| evictable(**h))
+            .min_by_key(|(_, t)| **t)
+            .map(|(h, _)| *h)
+    }
+}
+
+/// Least frequently used: evicts the entry with the fewest accesses since
+/// insert, the oldest first among ties.
+#[derive(Debug, Default)]
+pub struct LfuPolicy {
+    tick: u64,
+    // (uses, insertion tick)
+    uses: HashMap<Handle, (u64, u64)>,
+}
+
+impl LfuPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EvictionPolicy for LfuPolicy {
+    fn on_insert(&mut self, h: Handle) {
+        self.tick += 1;
+        self.uses.insert(h, (0, self.tick));
+    }
+    fn on_access(&mut self, h: Handle) {
+        if let Some((n, _)) = self.uses.get_mut(&h) {
+            *n += 1;
+        }
+    }
+    fn on_remove(&mut self, h: Handle) {
+        self.uses.remove(&h);
+    }
+    fn pick_victim(&mut self, evictable: &dyn Fn(Handle) -> bool) -> Option<Handle> {
+        self.uses
+            .iter()
+            .filter(|(h, _)| evictable(**h))
+            .min_by_key(|(_, u)| **u)
+            .map(|(h, _)| *h)
+    }
+}
+
+/// Uniformly random among the evictable entries, from a small seeded
+/// xorshift generator so runs are reproducible.
+#[derive(Debug)]
+pub struct RandomPolicy {
+    state: u64,
+    handles: Vec<Handle>,
+    pos: HashMap<Handle, usize>,
+}
+
+impl RandomPolicy {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // xorshift must not start at zero.
+            state: seed | 1,
+            handles: Vec::new(),
+            pos: HashMap::new(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
+
+impl EvictionPolicy for RandomPolicy {
+    fn on_insert(&mut self, h: Handle) {
+        if !self.pos.contains_key(&h) {
+            self.pos.insert(h, self.handles.len());
+            self.handles.push(h);
+        }
+    }
+    fn on_access(&mut self, _h: Handle) {}
+    fn on_remove(&mut self, h: Handle) {
+        if let Some(i) = self.pos.remove(&h) {
+            self.handles.swap_remove(i);
+            if let Some(moved) = self.handles.get(i) {
+                self.pos.insert(*moved, i);
+            }
+        }
+    }
+    fn pick_victim(&mut self, evictable: &dyn Fn(Handle) -> bool) -> Option<Handle> {
+        let candidates: Vec<Handle> = self
+            .handles
+            .iter()
+            .copied()
+            .filter(|h| evictable(*h))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let i = (self.next_u64() % candidates.len() as u64) as usize;
+        Some(candidates[i])
+    }
+}
+
+/// An `RcHashMap` capped at `capacity` resident entries, evicting by `P`.
+pub struct EvictingCache<K, V, P, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    S: BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    map: RcHashMap<K, V, S, C>,
+    policy: P,
+    capacity: usize,
+    stats: CacheStats,
+}
+
+impl<K, V, P> EvictingCache<K, V, P>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    P: EvictionPolicy,
+{
+    pub fn new(capacity: usize, policy: P) -> Self {
+        Self::with_map(RcHashMap::new(), capacity, policy)
+    }
+}
+
+impl<K, V, P, S, C> EvictingCache<K, V, P, S, C>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    P: EvictionPolicy,
+    S: BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// Wrap an empty map, e.g. one built with a custom hasher.
+    pub fn with_map(map: RcHashMap<K, V, S, C>, capacity: usize, policy: P) -> Self {
+        assert!(map.is_empty(), "EvictingCache needs an empty map");
+        Self {
+            map,
+            policy,
+            capacity,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// The underlying map, for reading through Refs.
+    pub fn map(&self) -> &RcHashMap<K, V, S, C> {
+        &self.map
+    }
+
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Evict unreferenced entries until back within capacity, or until only
+    // referenced entries remain.
+    fn shrink_to_capacity(&mut self) {
+        while self.map.len() > self.capacity {
+            let map = &self.map;
+            let Some(victim) = self.policy.pick_victim(&|h| map.is_pin_only(h)) else {
+                break;
+            };
+            self.map.unpin_handle(victim);
+            self.policy.on_remove(victim);
+        }
+    }
+}
+
+impl<K, V, P, S, C> Cache<K, V> for EvictingCache<K, V, P, S, C>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    P: EvictionPolicy,
+    S: BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    type Ref = Ref<K, V, S, C>;
+
+    fn get_or_insert_with<F>(&mut self, key: K, f: F) -> Result<Ref<K, V, S, C>, CappedInsertError>
+    where
+        F: FnOnce() -> V,
+    {
+        if self.map.contains_key(&key) {
+            self.stats.hits += 1;
+            // At the map's Ref cap, the hit cannot hand out another Ref.
+            let Some(r) = self.map.find(&key) else {
+                return Err(CappedInsertError::CapacityExceeded);
+            };
+            let h = r.handle();
+            // Invalidated earlier but kept alive by outside Refs: re-admit.
+            if self.map.pin_ref(&r) {
+                self.policy.on_insert(h);
+            } else {
+                self.policy.on_access(h);
+            }
+            return Ok(r);
+        }
+        self.stats.misses += 1;
+        let r = self.map.insert(key, f())?;
+        self.map.pin_ref(&r);
+        self.policy.on_insert(r.handle());
+        self.shrink_to_capacity();
+        Ok(r)
+    }
+
+    /// Release the cache's pin on `key`; the entry goes away once no
+    /// outside Refs remain.
+    fn invalidate<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        if let Some(h) = self.map.handle_of(key) {
+            if self.map.unpin_handle(h) {
+                self.policy.on_remove(h);
+            }
+        }
+        !self.map.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slotmap::{DefaultKey, KeyData};
+
+    fn h(i: u64) -> Handle {
+        Handle::new(DefaultKey::from(KeyData::from_ffi((1 << 32) | i)))
+    }
+
+    /// Invariant: LRU picks the least recently touched evictable entry.
+    #[test]
+    fn lru_picks_least_recent() {
+        let mut p = LruPolicy::new();
+        (1..=3).for_each(|i| p.on_insert(h(i)));
+        p.on_access(h(1));
+        assert_eq!(p.pick_victim(&|_| true), Some(h(2)));
+        assert_eq!(p.pick_victim(&|x| x != h(2)), Some(h(3)));
+        p.on_remove(h(3));
+        assert_eq!(p.pick_victim(&|x| x != h(2)), Some(h(1)));
+    }
+
+    /// Invariant: LFU picks the fewest-used evictable entry, oldest first.
+    #[test]
+    fn lfu_picks_least_used() {
+        let mut p = LfuPolicy::new();
+        (1..=3).for_each(|i| p.on_insert(h(i)));
+        p.on_access(h(1));
+        p.on_access(h(2));
+        assert_eq!(p.pick_victim(&|_| true), Some(h(3)));
+        p.on_access(h(3));
+        assert_eq!(p.pick_victim(&|_| true), Some(h(1)));
+        assert_eq!(p.pick_victim(&|_| false), None);
+    }
+
+    /// Invariant: random picks only evictable, tracked entries, and forgets
+    /// removed ones.
+    #[test]
+    fn random_picks_among_evictable() {
+        let mut p = RandomPolicy::new(7);
+        (1..=4).for_each(|i| p.on_insert(h(i)));
+        p.on_remove(h(2));
+        for _ in 0..32 {
+            let v = p.pick_victim(&|x| x != h(4)).unwrap();
+            assert!(v == h(1) || v == h(3));
+        }
+        assert_eq!(p.pick_victim(&|x| x == h(2)), None);
+    }
+
+    /// Invariant: the cache evicts by policy but never an entry with
+    /// outside Refs, exceeding capacity instead.
+    #[test]
+    fn cache_skips_referenced_entries() {
+        let mut c = EvictingCache::new(1, LruPolicy::new());
+        let a = c.get_or_insert_with("a", || 1).unwrap();
+        drop(c.get_or_insert_with("b", || 2).unwrap());
+        assert_eq!(c.len(), 2);
+        drop(a);
+        drop(c.get_or_insert_with("c", || 3).unwrap());
+        assert_eq!(c.len(), 1);
+        assert!(c.map().contains_key("c"));
+        assert_eq!(c.stats().misses, 3);
+        assert!(c.invalidate("c"));
+        assert!(Cache::is_empty(&c));
+    }
+
+    /// Invariant: a miss on a map at its `max_entries` cap reports
+    /// `CapacityExceeded` and leaves the cache unchanged.
+    #[test]
+    fn cache_reports_map_entry_cap() {
+        let mut c = EvictingCache::with_map(RcHashMap::with_max_entries(1), 4, LruPolicy::new());
+        drop(c.get_or_insert_with("a", || 1).unwrap());
+        assert!(matches!(
+            c.get_or_insert_with("b", || 2),
+            Err(CappedInsertError::CapacityExceeded)
+        ));
+        assert_eq!(c.len(), 1);
+        assert!(c.get_or_insert_with("a", || 1).is_ok());
+    }
+}
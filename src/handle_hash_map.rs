@@ -1,11 +1,11 @@
 //! HandleHashMap: structural layer with stable handles and debug reentrancy guard.
 
-use crate::hash::DefaultHashBuilder;
+use crate::hash::{DefaultHashBuilder, StoredHash};
 use crate::reentrancy::DebugReentrancy;
 use core::borrow::Borrow;
 use core::hash::{BuildHasher, Hash};
 use hashbrown::HashTable;
-use slotmap::{DefaultKey, SlotMap};
+use slotmap::{DefaultKey, SecondaryMap, SlotMap};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub struct Handle(DefaultKey);
@@ -23,6 +23,7 @@ impl Handle {
         K: Eq + Hash,
         S: BuildHasher + Clone + Default,
     {
+        map.debug_check_key(*self);
         map.handle_key(*self)
     }
 
@@ -31,6 +32,7 @@ impl Handle {
         K: Eq + Hash,
         S: BuildHasher + Clone + Default,
     {
+        map.debug_check_key(*self);
         map.handle_value(*self)
     }
 
@@ -39,21 +41,190 @@ impl Handle {
         K: Eq + Hash,
         S: BuildHasher + Clone + Default,
     {
+        map.debug_check_key(*self);
         map.handle_value_mut(*self)
     }
 }
 
+// Handles wrap a slotmap `DefaultKey`, so they can index third-party
+// `SecondaryMap`/`SparseSecondaryMap` side tables. A handle rebuilt from a
+// foreign key is safe to use: lookups of stale or unknown keys return `None`.
+#[cfg(feature = "slotmap-interop")]
+impl From<Handle> for DefaultKey {
+    fn from(h: Handle) -> Self {
+        h.0
+    }
+}
+
+#[cfg(feature = "slotmap-interop")]
+impl From<DefaultKey> for Handle {
+    fn from(k: DefaultKey) -> Self {
+        Handle(k)
+    }
+}
+
+#[cfg(feature = "slotmap-interop")]
+impl From<Handle> for slotmap::KeyData {
+    fn from(h: Handle) -> Self {
+        slotmap::Key::data(&h.0)
+    }
+}
+
+#[cfg(feature = "slotmap-interop")]
+impl From<slotmap::KeyData> for Handle {
+    fn from(d: slotmap::KeyData) -> Self {
+        Handle(DefaultKey::from(d))
+    }
+}
+
+// Handles serialize as slotmap's stable FFI `u64`, so handles stored in
+// persisted structures resolve again against a map restored by `load`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Handle {
+    fn serialize<Ser: serde::Serializer>(&self, s: Ser) -> Result<Ser::Ok, Ser::Error> {
+        slotmap::Key::data(&self.0).as_ffi().serialize(s)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Handle {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let ffi = u64::deserialize(d)?;
+        Ok(Handle(DefaultKey::from(slotmap::KeyData::from_ffi(ffi))))
+    }
+}
+
+/// A stored entry. Public only through `RawParts`.
 #[derive(Debug)]
-struct Entry<K, V> {
-    key: K,
-    value: V,
-    hash: u64,
+pub struct Entry<K, V> {
+    pub key: K,
+    pub value: V,
+    /// The map hasher's hash of `key` (see `hash::StoredHash`); lookups
+    /// use it instead of rehashing.
+    pub hash: StoredHash,
+}
+
+/// The storage behind a `HandleHashMap`, for custom bulk transformations
+/// or serialization formats; see `HandleHashMap::into_raw_parts`.
+pub struct RawParts<K, V, S> {
+    pub hasher: S,
+    /// Slot key of every live entry, indexed by `Entry::hash`.
+    pub index: HashTable<DefaultKey>,
+    /// Entry storage; a slot's key is the `Handle` of its entry.
+    pub slots: SlotMap<DefaultKey, Entry<K, V>>,
+}
+
+// Index probe: does slot `kk` hold `q`? The stored hash is compared first,
+// so `Eq` only runs on full-hash matches.
+fn probe_eq<'a, K, V, Q>(
+    slots: &'a SlotMap<DefaultKey, Entry<K, V>>,
+    hash: StoredHash,
+    q: &'a Q,
+) -> impl Fn(&DefaultKey) -> bool + 'a
+where
+    K: Borrow<Q>,
+    Q: ?Sized + Eq,
+{
+    move |&kk| {
+        slots
+            .get(kk)
+            .is_some_and(|e| e.hash == hash && e.key.borrow() == q)
+    }
+}
+
+// Index rehash callback: the index hash of slot `kk`'s entry.
+fn probe_hash<K, V>(slots: &SlotMap<DefaultKey, Entry<K, V>>) -> impl Fn(&DefaultKey) -> u64 + '_ {
+    move |&kk| slots.get(kk).map_or(0, |e| e.hash.index())
+}
+
+// Hint that `p` will be read soon. Prefetches never fault, so any address
+// is fine; a no-op on targets without a wired-up instruction.
+#[inline(always)]
+fn prefetch_read<T>(p: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_mm_prefetch::<{ core::arch::x86_64::_MM_HINT_T0 }>(p.cast())
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "prfm pldl1keep, [{p}]",
+            p = in(reg) p,
+            options(nostack, readonly, preserves_flags)
+        )
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    let _ = p;
+}
+
+// Keys `lookup_batch` resolves per prefetch round: enough to overlap the
+// slot misses, few enough that prefetched slots are still cached when read.
+const LOOKUP_BATCH_CHUNK: usize = 16;
+
+// High-water marks, kept apart from the map so insert paths can update
+// them while a reentrancy guard borrows it.
+#[derive(Copy, Clone, Default)]
+struct Marks {
+    peak_len: usize,    // high-water mark of len()
+    slot_extent: usize, // slots ever initialized (never reset)
+}
+
+impl Marks {
+    // Bookkeeping after any insert, with the new length.
+    #[inline]
+    fn note_insert(&mut self, len: usize) {
+        self.peak_len = self.peak_len.max(len);
+        self.slot_extent = self.slot_extent.max(len);
+    }
+}
+
+// Dense list of live slot keys behind positional access (`get_index`). A
+// removal moves the last key into the freed position, so positions stay
+// `0..len` and every operation is O(1). Kept apart from the map for the
+// same reason as `Marks`.
+#[derive(Default)]
+struct Order {
+    keys: Vec<DefaultKey>,
+    pos: SecondaryMap<DefaultKey, usize>,
+}
+
+impl Order {
+    // Positions in the given key order, e.g. slot order after a rebuild.
+    fn from_keys(keys: impl Iterator<Item = DefaultKey>) -> Self {
+        let mut order = Order::default();
+        keys.for_each(|k| order.push(k));
+        order
+    }
+
+    // Room for positions of slots up to `slot_capacity`, `additional` of
+    // them new.
+    fn reserve(&mut self, additional: usize, slot_capacity: usize) {
+        self.keys.reserve(additional);
+        self.pos.set_capacity(slot_capacity);
+    }
+
+    #[inline]
+    fn push(&mut self, k: DefaultKey) {
+        self.pos.insert(k, self.keys.len());
+        self.keys.push(k);
+    }
+
+    #[inline]
+    fn remove(&mut self, k: DefaultKey) {
+        let i = self.pos.remove(k).expect("live slot has a position");
+        self.keys.swap_remove(i);
+        if let Some(&moved) = self.keys.get(i) {
+            self.pos[moved] = i;
+        }
+    }
 }
 
 pub struct HandleHashMap<K, V, S = DefaultHashBuilder> {
     hasher: S,
     index: HashTable<DefaultKey>,
     slots: SlotMap<DefaultKey, Entry<K, V>>, // storage using generational keys
+    marks: Marks,
+    order: Order,
     reentrancy: DebugReentrancy,
 }
 
@@ -62,6 +233,101 @@ pub enum InsertError {
     DuplicateKey,
 }
 
+/// Error from inserts that honor an entry cap (`RcHashMap::insert`,
+/// `RcHashMapFixed::insert`): the key is already present, or it is absent
+/// and the map is full.
+#[derive(Debug)]
+pub enum CappedInsertError {
+    DuplicateKey,
+    /// The map is at its entry cap (`RcHashMap::set_max_entries`, or `N`
+    /// for `RcHashMapFixed`), or, from a find-or-insert, the key's entry is
+    /// at its Ref cap (`RcHashMap::with_max_refs`).
+    CapacityExceeded,
+}
+
+impl CappedInsertError {
+    // For wrappers whose inner map is never capped.
+    pub(crate) fn uncapped(self) -> InsertError {
+        match self {
+            CappedInsertError::DuplicateKey => InsertError::DuplicateKey,
+            CappedInsertError::CapacityExceeded => unreachable!("map has no entry cap"),
+        }
+    }
+}
+
+impl From<InsertError> for CappedInsertError {
+    fn from(e: InsertError) -> Self {
+        match e {
+            InsertError::DuplicateKey => CappedInsertError::DuplicateKey,
+        }
+    }
+}
+
+/// Error from `HandleHashMap::insert`/`insert_with`: the key is already
+/// present. Hands back the rejected key and value (for `insert_with`, the
+/// constructor, which did not run) along with the existing entry's handle,
+/// so callers can read or update it without probing again.
+pub struct DuplicateKey<K, V> {
+    pub existing: Handle,
+    pub key: K,
+    pub value: V,
+}
+
+// Manual so `unwrap` works for any key and for closure values.
+impl<K, V> core::fmt::Debug for DuplicateKey<K, V> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DuplicateKey")
+            .field("existing", &self.existing)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, V> From<DuplicateKey<K, V>> for InsertError {
+    fn from(_: DuplicateKey<K, V>) -> Self {
+        InsertError::DuplicateKey
+    }
+}
+
+/// Slot storage occupancy, from `slot_stats`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SlotStats {
+    /// Slots holding live entries (`len()`).
+    pub live: usize,
+    /// Slots freed by removals and kept for reuse by later inserts.
+    pub free: usize,
+    /// Slots allocated, live and free included, plus unused reserve.
+    pub capacity: usize,
+}
+
+impl SlotStats {
+    /// Fraction of allocated slots not holding a live entry, in `[0, 1]`.
+    pub fn fragmentation(&self) -> f64 {
+        if self.capacity == 0 {
+            0.0
+        } else {
+            1.0 - self.live as f64 / self.capacity as f64
+        }
+    }
+}
+
+/// Error from `insert_with_result`: either the key was already present (the
+/// constructor did not run) or the constructor failed (nothing inserted).
+#[derive(Debug)]
+pub enum TryInsertError<E> {
+    DuplicateKey,
+    /// See `CappedInsertError::CapacityExceeded`.
+    CapacityExceeded,
+    Constructor(E),
+}
+
+impl<E> From<InsertError> for TryInsertError<E> {
+    fn from(e: InsertError) -> Self {
+        match e {
+            InsertError::DuplicateKey => TryInsertError::DuplicateKey,
+        }
+    }
+}
+
 impl<K, V> HandleHashMap<K, V>
 where
     K: Eq + Hash,
@@ -81,6 +347,9 @@ where
 }
 
 /// Iterator over immutable entries in `HandleHashMap`.
+///
+/// Exact-sized, fused and cheaply cloneable. Not double-ended: slotmap's storage iterators
+/// only walk forward.
 pub struct Iter<'a, K, V, S> {
     it: slotmap::basic::Iter<'a, DefaultKey, Entry<K, V>>,
     pub(crate) _pd: core::marker::PhantomData<&'a (K, V, S)>,
@@ -94,9 +363,29 @@ impl<'a, K, V, S> Iterator for Iter<'a, K, V, S> {
             .next()
             .map(|(k, e)| (Handle::new(k), &e.key, &e.value))
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
+}
+
+impl<K, V, S> ExactSizeIterator for Iter<'_, K, V, S> {}
+impl<K, V, S> core::iter::FusedIterator for Iter<'_, K, V, S> {}
+
+// Manual impl: a derive would require `K, V, S: Clone`.
+impl<K, V, S> Clone for Iter<'_, K, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            it: self.it.clone(),
+            _pd: core::marker::PhantomData,
+        }
+    }
 }
 
 /// Iterator over mutable entries in `HandleHashMap`.
+///
+/// Exact-sized and fused; see `Iter` for why it is not double-ended.
 pub struct IterMut<'a, K, V, S> {
     it: slotmap::basic::IterMut<'a, DefaultKey, Entry<K, V>>,
     pub(crate) _pd: core::marker::PhantomData<&'a (K, V, S)>,
@@ -110,8 +399,16 @@ impl<'a, K, V, S> Iterator for IterMut<'a, K, V, S> {
             .next()
             .map(|(k, e)| (Handle::new(k), &e.key, &mut e.value))
     }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
 }
 
+impl<K, V, S> ExactSizeIterator for IterMut<'_, K, V, S> {}
+impl<K, V, S> core::iter::FusedIterator for IterMut<'_, K, V, S> {}
+
 impl<K, V, S> HandleHashMap<K, V, S>
 where
     K: Eq + Hash,
@@ -122,15 +419,72 @@ where
             index: HashTable::new(),
             hasher,
             slots: SlotMap::with_key(),
+            marks: Marks::default(),
+            order: Order::default(),
             reentrancy: DebugReentrancy::new(),
         }
     }
 
-    fn make_hash<Q>(&self, q: &Q) -> u64
+    /// The map's hash builder.
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// Decompose the map into its storage. `Handle`s stay meaningful: an
+    /// entry's handle wraps its slot key.
+    pub fn into_raw_parts(self) -> RawParts<K, V, S> {
+        RawParts {
+            hasher: self.hasher,
+            index: self.index,
+            slots: self.slots,
+        }
+    }
+
+    /// Rebuild a map from `parts`, checking that every entry's stored hash
+    /// matches `hasher`, that the index holds exactly the live slot keys
+    /// under their hashes, and that keys are unique. Returns the parts
+    /// unchanged if any check fails. Peak and free-slot statistics restart
+    /// from the current length.
+    pub fn from_raw_parts(parts: RawParts<K, V, S>) -> Result<Self, RawParts<K, V, S>> {
+        let RawParts {
+            hasher,
+            index,
+            slots,
+        } = &parts;
+        let valid = index.len() == slots.len()
+            && slots.iter().all(|(k, e)| {
+                e.hash == StoredHash::of(hasher, &e.key)
+                    && index.find(e.hash.index(), |&kk| kk == k).is_some()
+                    && index.find(e.hash.index(), |&kk| {
+                        slots.get(kk).is_some_and(|o| o.key == e.key)
+                    }) == Some(&k)
+            });
+        if !valid {
+            return Err(parts);
+        }
+        let RawParts {
+            hasher,
+            index,
+            slots,
+        } = parts;
+        Ok(Self {
+            hasher,
+            index,
+            marks: Marks {
+                peak_len: slots.len(),
+                slot_extent: slots.len(),
+            },
+            order: Order::from_keys(slots.keys()),
+            slots,
+            reentrancy: DebugReentrancy::new(),
+        })
+    }
+
+    pub(crate) fn make_hash<Q>(&self, q: &Q) -> StoredHash
     where
         Q: ?Sized + Hash,
     {
-        self.hasher.hash_one(q)
+        StoredHash::of(&self.hasher, q)
     }
 
     pub fn len(&self) -> usize {
@@ -140,118 +494,600 @@ where
         self.slots.is_empty()
     }
 
+    /// Largest `len()` reached since construction or the last
+    /// `reset_peak_len`.
+    pub fn peak_len(&self) -> usize {
+        self.marks.peak_len
+    }
+
+    /// Restart peak tracking from the current `len()`.
+    pub fn reset_peak_len(&mut self) {
+        self.marks.peak_len = self.slots.len();
+    }
+
+    /// Occupancy of the slot storage, for deciding when to compact or shrink.
+    /// Slotmap reuses freed slots before growing, so the number of slots
+    /// ever initialized equals the largest `len()` the map has had.
+    pub fn slot_stats(&self) -> SlotStats {
+        let live = self.slots.len();
+        SlotStats {
+            live,
+            free: self.marks.slot_extent - live,
+            capacity: self.slots.capacity(),
+        }
+    }
+
+    /// Estimated bytes allocated by the map's own storage: the hash index,
+    /// the slot array (vacant slots included) and the position list. Heap
+    /// memory owned by the keys and values themselves is not counted.
+    pub fn allocated_bytes(&self) -> usize {
+        // A slotmap slot pairs the entry with a u32 version; one extra slot
+        // is reserved as a sentinel. Secondary map slots are laid out alike.
+        let slot = core::mem::size_of::<(Entry<K, V>, u32)>();
+        let order = self.order.keys.capacity() * core::mem::size_of::<DefaultKey>()
+            + self.order.pos.capacity() * core::mem::size_of::<(usize, u32)>();
+        self.index.allocation_size() + (self.slots.capacity() + 1) * slot + order
+    }
+
+    /// Some live entry's handle, without hashing; for drain loops that
+    /// take whatever is left. Returns the entry at the last position (see
+    /// `get_index`), so it is O(1) however much of storage is vacant, and
+    /// removing it moves no other entry's position.
+    pub fn any_handle(&self) -> Option<Handle> {
+        self.order.keys.last().copied().map(Handle::new)
+    }
+
+    /// The handle at position `i`, for list-style views and pagination.
+    /// Positions are dense, `0..len`: inserts append, in insertion order,
+    /// and a removal moves the last entry into the freed position (like
+    /// `Vec::swap_remove`). They are kept by `compact`, renumbered in slot
+    /// order by `from_raw_parts` and `load`, and independent of `iter`
+    /// order. O(1).
+    pub fn get_index(&self, i: usize) -> Option<Handle> {
+        self.order.keys.get(i).copied().map(Handle::new)
+    }
+
+    /// Position of `q`'s entry; see `get_index`. O(1) after the lookup.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn get_index_of<Q>(&self, q: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let k = self.find(q)?.raw_handle();
+        self.order.pos.get(k).copied()
+    }
+
+    #[cfg_attr(debug_assertions, track_caller)]
     pub fn find<Q>(&self, q: &Q) -> Option<Handle>
     where
         K: Borrow<Q>,
         Q: ?Sized + Hash + Eq,
     {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_shared("find");
         let hash = self.make_hash(q);
-        if let Some(&k) = self.index.find(hash, |&k| {
-            self.slots
-                .get(k)
-                .map(|e| e.key.borrow() == q)
-                .unwrap_or(false)
-        }) {
-            return Some(Handle::new(k));
-        }
-        None
+        self.find_hashed_unguarded(hash, q)
+    }
+
+    // Look up `q` by a hash from `make_hash`, for callers that hash once
+    // and probe later (the entry API).
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub(crate) fn find_hashed<Q>(&self, hash: StoredHash, q: &Q) -> Option<Handle>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let _g = self.reentrancy.enter_shared("find_hashed");
+        self.find_hashed_unguarded(hash, q)
     }
 
+    fn find_hashed_unguarded<Q>(&self, hash: StoredHash, q: &Q) -> Option<Handle>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.index
+            .find(hash.index(), probe_eq(&self.slots, hash, q))
+            .map(|&k| Handle::new(k))
+    }
+
+    // Insert a key known to be absent under its `make_hash` hash, without
+    // probing for duplicates.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub(crate) fn insert_unique_hashed(&mut self, hash: StoredHash, key: K, value: V) -> Handle {
+        let _g = self.reentrancy.enter_exclusive("insert_unique_hashed");
+        debug_assert!(self.find_hashed_unguarded(hash, &key).is_none());
+        let k = self.slots.insert(Entry { key, value, hash });
+        let slots = &self.slots;
+        self.index.insert_unique(hash.index(), k, probe_hash(slots));
+        self.order.push(k);
+        self.marks.note_insert(self.slots.len());
+        Handle::new(k)
+    }
+
+    #[cfg_attr(debug_assertions, track_caller)]
     pub fn contains_key<Q>(&self, q: &Q) -> bool
     where
         K: Borrow<Q>,
         Q: ?Sized + Hash + Eq,
     {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_shared("contains_key");
         let hash = self.make_hash(q);
         self.index
-            .find(hash, |&k| {
-                self.slots
-                    .get(k)
-                    .map(|e| e.key.borrow() == q)
-                    .unwrap_or(false)
-            })
+            .find(hash.index(), probe_eq(&self.slots, hash, q))
             .is_some()
     }
 
-    pub fn insert(&mut self, key: K, value: V) -> Result<Handle, InsertError> {
-        let _g = self.reentrancy.enter();
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn insert(&mut self, key: K, value: V) -> Result<Handle, DuplicateKey<K, V>> {
+        let _g = self.reentrancy.enter_exclusive("insert");
         let hash = self.make_hash(&key);
         let entry = Entry { key, value, hash };
         // Use HashTable::entry to deduplicate or insert.
         match self.index.entry(
-            hash,
-            |&kk| {
-                self.slots
-                    .get(kk)
-                    .map(|e| e.key == entry.key)
-                    .unwrap_or(false)
-            },
-            |&kk| self.slots.get(kk).map(|e| e.hash).unwrap_or(0),
+            hash.index(),
+            probe_eq(&self.slots, entry.hash, &entry.key),
+            probe_hash(&self.slots),
         ) {
-            hashbrown::hash_table::Entry::Occupied(_) => Err(InsertError::DuplicateKey),
+            hashbrown::hash_table::Entry::Occupied(o) => Err(DuplicateKey {
+                existing: Handle::new(*o.get()),
+                key: entry.key,
+                value: entry.value,
+            }),
             hashbrown::hash_table::Entry::Vacant(v) => {
                 let k = self.slots.insert(entry);
                 let _ = v.insert(k);
+                self.order.push(k);
+                self.marks.note_insert(self.slots.len());
                 Ok(Handle::new(k))
             }
         }
     }
 
-    pub fn insert_with<F>(&mut self, key: K, default: F) -> Result<Handle, InsertError>
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn insert_with<F>(&mut self, key: K, default: F) -> Result<Handle, DuplicateKey<K, F>>
     where
         F: FnOnce() -> V,
     {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_exclusive("insert_with");
         let hash = self.make_hash(&key);
         match self.index.entry(
-            hash,
-            |&kk| self.slots.get(kk).map(|e| e.key == key).unwrap_or(false),
-            |&kk| self.slots.get(kk).map(|e| e.hash).unwrap_or(0),
+            hash.index(),
+            probe_eq(&self.slots, hash, &key),
+            probe_hash(&self.slots),
         ) {
-            hashbrown::hash_table::Entry::Occupied(_) => Err(InsertError::DuplicateKey),
+            hashbrown::hash_table::Entry::Occupied(o) => Err(DuplicateKey {
+                existing: Handle::new(*o.get()),
+                key,
+                value: default,
+            }),
             hashbrown::hash_table::Entry::Vacant(v) => {
                 let value = default();
                 let entry = Entry { key, value, hash };
                 let k = self.slots.insert(entry);
                 let _ = v.insert(k);
+                self.order.push(k);
+                self.marks.note_insert(self.slots.len());
+                Ok(Handle::new(k))
+            }
+        }
+    }
+
+    /// Insert using a lazy constructor that borrows the key being inserted.
+    /// `make(&key)` only runs when the key is absent.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn insert_with_key<F>(&mut self, key: K, make: F) -> Result<Handle, InsertError>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        let _g = self.reentrancy.enter_exclusive("insert_with_key");
+        let hash = self.make_hash(&key);
+        match self.index.entry(
+            hash.index(),
+            probe_eq(&self.slots, hash, &key),
+            probe_hash(&self.slots),
+        ) {
+            hashbrown::hash_table::Entry::Occupied(_) => Err(InsertError::DuplicateKey),
+            hashbrown::hash_table::Entry::Vacant(v) => {
+                let value = make(&key);
+                let k = self.slots.insert(Entry { key, value, hash });
+                let _ = v.insert(k);
+                self.order.push(k);
+                self.marks.note_insert(self.slots.len());
                 Ok(Handle::new(k))
             }
         }
     }
 
+    /// Insert using a fallible lazy constructor. `make()` only runs when the
+    /// key is absent; if it fails, the map is left unchanged.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn insert_with_result<F, E>(&mut self, key: K, make: F) -> Result<Handle, TryInsertError<E>>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        let _g = self.reentrancy.enter_exclusive("insert_with_result");
+        let hash = self.make_hash(&key);
+        match self.index.entry(
+            hash.index(),
+            probe_eq(&self.slots, hash, &key),
+            probe_hash(&self.slots),
+        ) {
+            hashbrown::hash_table::Entry::Occupied(_) => Err(TryInsertError::DuplicateKey),
+            hashbrown::hash_table::Entry::Vacant(v) => {
+                let value = make().map_err(TryInsertError::Constructor)?;
+                let k = self.slots.insert(Entry { key, value, hash });
+                let _ = v.insert(k);
+                self.order.push(k);
+                self.marks.note_insert(self.slots.len());
+                Ok(Handle::new(k))
+            }
+        }
+    }
+
+    /// Return the handle for `key`, inserting `default()` first if absent.
+    /// Probes the index once. The flag is true when a new entry was inserted;
+    /// on a hit `default` does not run and `key` is dropped.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn find_or_insert_with<F>(&mut self, key: K, default: F) -> (Handle, bool)
+    where
+        F: FnOnce() -> V,
+    {
+        let _g = self.reentrancy.enter_exclusive("find_or_insert_with");
+        let hash = self.make_hash(&key);
+        match self.index.entry(
+            hash.index(),
+            probe_eq(&self.slots, hash, &key),
+            probe_hash(&self.slots),
+        ) {
+            hashbrown::hash_table::Entry::Occupied(o) => (Handle::new(*o.get()), false),
+            hashbrown::hash_table::Entry::Vacant(v) => {
+                let value = default();
+                let k = self.slots.insert(Entry { key, value, hash });
+                let _ = v.insert(k);
+                self.order.push(k);
+                self.marks.note_insert(self.slots.len());
+                (Handle::new(k), true)
+            }
+        }
+    }
+
+    /// Reserve room for at least `additional` more entries in both the
+    /// index and the slot storage.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn reserve(&mut self, additional: usize) {
+        let _g = self.reentrancy.enter_exclusive("reserve");
+        self.slots.reserve(additional);
+        self.order.reserve(additional, self.slots.capacity());
+        let slots = &self.slots;
+        self.index.reserve(additional, probe_hash(slots));
+    }
+
+    // Reserve index room only. Used by the fixed-capacity map to keep the
+    // table at most half full, so hashbrown clears tombstones by rehashing
+    // in place instead of growing.
+    pub(crate) fn reserve_index(&mut self, additional: usize) {
+        let _g = self.reentrancy.enter_exclusive("reserve_index");
+        let slots = &self.slots;
+        self.index.reserve(additional, probe_hash(slots));
+    }
+
+    /// Insert a batch of entries under a single guard entry, reserving index
+    /// and storage capacity up front from the iterator's lower size bound.
+    /// Results are reported per item in input order; duplicates (including
+    /// duplicates within the batch) are rejected like `insert`.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn insert_many<I>(&mut self, items: I) -> Vec<Result<Handle, InsertError>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let _g = self.reentrancy.enter_exclusive("insert_many");
+        let items = items.into_iter();
+        let (lower, _) = items.size_hint();
+        self.slots.reserve(lower);
+        self.order.reserve(lower, self.slots.capacity());
+        let slots = &self.slots;
+        self.index.reserve(lower, probe_hash(slots));
+
+        let mut out = Vec::with_capacity(lower);
+        for (key, value) in items {
+            let hash = self.make_hash(&key);
+            let res = match self.index.entry(
+                hash.index(),
+                probe_eq(&self.slots, hash, &key),
+                probe_hash(&self.slots),
+            ) {
+                hashbrown::hash_table::Entry::Occupied(_) => Err(InsertError::DuplicateKey),
+                hashbrown::hash_table::Entry::Vacant(v) => {
+                    let k = self.slots.insert(Entry { key, value, hash });
+                    let _ = v.insert(k);
+                    self.order.push(k);
+                    self.marks.note_insert(self.slots.len());
+                    Ok(Handle::new(k))
+                }
+            };
+            out.push(res);
+        }
+        out
+    }
+
+    #[cfg_attr(debug_assertions, track_caller)]
     pub fn remove(&mut self, handle: Handle) -> Option<(K, V)> {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_exclusive("remove");
+        Self::unlink(&mut self.slots, &mut self.index, &mut self.order, handle)
+    }
+
+    // Remove the slot, its index link and its position; callers hold the
+    // reentrancy guard.
+    fn unlink(
+        slots: &mut SlotMap<DefaultKey, Entry<K, V>>,
+        index: &mut HashTable<DefaultKey>,
+        order: &mut Order,
+        handle: Handle,
+    ) -> Option<(K, V)> {
         let k = handle.raw_handle();
 
         // Remove slot
-        let entry = self.slots.remove(k)?;
+        let entry = slots.remove(k)?;
+        order.remove(k);
 
         // Unlink from index via occupied entry removal
-        self.index
-            .find_entry(entry.hash, |&kk| kk == k)
+        index
+            .find_entry(entry.hash.index(), |&kk| kk == k)
             .unwrap()
             .remove();
 
         Some((entry.key, entry.value))
     }
 
+    /// Move the value out of an entry, leaving `V::default()` in its place.
+    /// The entry and its handle stay live. Returns `None` for a stale handle.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn take_value(&mut self, handle: Handle) -> Option<V>
+    where
+        V: Default,
+    {
+        let _g = self.reentrancy.enter_exclusive("take_value");
+        self.slots
+            .get_mut(handle.raw_handle())
+            .map(|e| core::mem::take(&mut e.value))
+    }
+
+    /// Exchange the values stored under two entries; keys and hashes stay
+    /// in place, so both handles remain valid. Returns false (and leaves the
+    /// map unchanged) if either handle is stale.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn swap(&mut self, h1: Handle, h2: Handle) -> bool {
+        let _g = self.reentrancy.enter_exclusive("swap");
+        if h1 == h2 {
+            return self.slots.contains_key(h1.raw_handle());
+        }
+        match self
+            .slots
+            .get_disjoint_mut([h1.raw_handle(), h2.raw_handle()])
+        {
+            Some([e1, e2]) => {
+                core::mem::swap(&mut e1.value, &mut e2.value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a batch of entries under a single guard entry. Returns the
+    /// removed `(K, V)` pairs per handle in input order; stale (or repeated)
+    /// handles yield `None`. All entries are unlinked before the returned
+    /// vector hands ownership back, so `Drop` for `K`/`V` runs after the
+    /// structure is consistent.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn remove_many<I>(&mut self, handles: I) -> Vec<Option<(K, V)>>
+    where
+        I: IntoIterator<Item = Handle>,
+    {
+        let _g = self.reentrancy.enter_exclusive("remove_many");
+        handles
+            .into_iter()
+            .map(|h| Self::unlink(&mut self.slots, &mut self.index, &mut self.order, h))
+            .collect()
+    }
+
+    /// Re-pack live entries into a tightly sized slot array and rebuild the
+    /// index, releasing storage left behind by churn and restoring iteration
+    /// locality. Every handle changes: the returned `(old, new)` pairs must
+    /// be used to translate handles held elsewhere, since an untranslated
+    /// handle may now resolve to a different entry. Positions (`get_index`)
+    /// are kept, and slots are laid out in position order.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn compact(&mut self) -> Vec<(Handle, Handle)> {
+        let _g = self.reentrancy.enter_exclusive("compact");
+        let len = self.slots.len();
+        let mut old = core::mem::replace(&mut self.slots, SlotMap::with_capacity_and_key(len));
+        let old_order = core::mem::take(&mut self.order);
+        let mut index = HashTable::with_capacity(len);
+        let mut moves = Vec::with_capacity(len);
+        for ok in old_order.keys {
+            let e = old.remove(ok).expect("ordered key is live");
+            let hash = e.hash;
+            let nk = self.slots.insert(e);
+            let slots = &self.slots;
+            index.insert_unique(hash.index(), nk, probe_hash(slots));
+            self.order.push(nk);
+            moves.push((Handle::new(ok), Handle::new(nk)));
+        }
+        self.index = index;
+        self.marks.slot_extent = len;
+        moves
+    }
+
+    /// Look up a batch of keys under a single guard entry, reporting a
+    /// handle per key in input order.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn find_many<'q, Q, I>(&self, keys: I) -> Vec<Option<Handle>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        let _g = self.reentrancy.enter_shared("find_many");
+        let keys = keys.into_iter();
+        let mut out = Vec::with_capacity(keys.size_hint().0);
+        for q in keys {
+            let hash = self.make_hash(q);
+            let found = self
+                .index
+                .find(hash.index(), probe_eq(&self.slots, hash, q));
+            out.push(found.map(|&k| Handle::new(k)));
+        }
+        out
+    }
+
+    /// Like `find_many`, but overlaps the slot loads of several keys: for
+    /// each chunk of keys it hashes them, probes the index once per key,
+    /// prefetches every candidate slot, and only then compares keys. The
+    /// index buckets themselves are not prefetched, as `HashTable` does not
+    /// expose their addresses. Prefetching is wired up on x86_64 and
+    /// aarch64 and is a no-op elsewhere. Results are in input order.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn lookup_batch<'q, Q, I>(&self, keys: I) -> Vec<Option<Handle>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        let _g = self.reentrancy.enter_shared("lookup_batch");
+        let mut keys = keys.into_iter();
+        let mut out = Vec::with_capacity(keys.size_hint().0);
+        let mut chunk: Vec<(&Q, StoredHash)> = Vec::with_capacity(LOOKUP_BATCH_CHUNK);
+        // Tag-matching slots of every key in the chunk, and where each
+        // key's run of them ends.
+        let mut cands: Vec<DefaultKey> = Vec::new();
+        let mut ends: Vec<usize> = Vec::with_capacity(LOOKUP_BATCH_CHUNK);
+        loop {
+            chunk.clear();
+            chunk.extend(
+                keys.by_ref()
+                    .take(LOOKUP_BATCH_CHUNK)
+                    .map(|q| (q, self.make_hash(q))),
+            );
+            if chunk.is_empty() {
+                return out;
+            }
+            cands.clear();
+            ends.clear();
+            for &(_, hash) in &chunk {
+                for &k in self.index.iter_hash(hash.index()) {
+                    // Safety: the index only holds keys of live slots.
+                    prefetch_read(unsafe { self.slots.get_unchecked(k) });
+                    cands.push(k);
+                }
+                ends.push(cands.len());
+            }
+            let mut start = 0;
+            for (&(q, hash), &end) in chunk.iter().zip(&ends) {
+                let found = cands[start..end]
+                    .iter()
+                    .find(|k| probe_eq(&self.slots, hash, q)(k));
+                out.push(found.map(|&k| Handle::new(k)));
+                start = end;
+            }
+        }
+    }
+
+    /// Returns true if `handle` still refers to a live entry. Does not
+    /// construct a key or value reference.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn contains_handle(&self, handle: Handle) -> bool {
+        let _g = self.reentrancy.enter_shared("contains_handle");
+        self.slots.contains_key(handle.raw_handle())
+    }
+
+    #[cfg_attr(debug_assertions, track_caller)]
     pub(crate) fn handle_key(&self, h: Handle) -> Option<&K> {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_shared("handle_key");
         self.slots.get(h.raw_handle()).map(|e| &e.key)
     }
 
+    #[cfg_attr(debug_assertions, track_caller)]
     pub(crate) fn handle_value(&self, h: Handle) -> Option<&V> {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_shared("handle_value");
         self.slots.get(h.raw_handle()).map(|e| &e.value)
     }
 
+    #[cfg_attr(debug_assertions, track_caller)]
     pub(crate) fn handle_value_mut(&mut self, h: Handle) -> Option<&mut V> {
-        let _g = self.reentrancy.enter();
+        let _g = self.reentrancy.enter_exclusive("handle_value_mut");
         self.slots.get_mut(h.raw_handle()).map(|e| &mut e.value)
     }
 
+    // Debug builds rehash the key on user-facing handle access: a key whose
+    // hash changed since insert (interior mutability) is unreachable by
+    // lookup, and failing here points at it long before the misses are
+    // noticed. Internal bookkeeping (refcounts, drops) skips the check.
+    #[cfg_attr(debug_assertions, track_caller)]
+    #[inline]
+    pub(crate) fn debug_check_key(&self, h: Handle) {
+        #[cfg(debug_assertions)]
+        {
+            let _g = self.reentrancy.enter_shared("debug_check_key");
+            if let Some(e) = self.slots.get(h.raw_handle()) {
+                if self.make_hash(&e.key) != e.hash {
+                    panic!(
+                        "key of entry {:?} (type {}) hashes differently than when it was \
+                         inserted; keys must not change while in the map",
+                        h,
+                        core::any::type_name::<K>()
+                    );
+                }
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = h;
+    }
+
+    /// Check every key against the hash stored when it was inserted,
+    /// panicking with the first key whose hash has changed (a key mutated
+    /// through interior mutability). Lookups can no longer find such an
+    /// entry. Runs in all builds; O(len) hash computations.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn validate(&self)
+    where
+        K: core::fmt::Debug,
+    {
+        let _g = self.reentrancy.enter_shared("validate");
+        for (k, e) in self.slots.iter() {
+            if self.make_hash(&e.key) != e.hash {
+                panic!(
+                    "key {:?} (entry {:?}) hashes differently than when it was inserted; \
+                     keys must not change while in the map",
+                    e.key,
+                    Handle::new(k)
+                );
+            }
+        }
+    }
+
+    /// Borrow the value behind `handle` without the generation check or
+    /// the reentrancy guard, for hot loops over handles known to be live.
+    ///
+    /// # Safety
+    /// `handle` must refer to a live entry of this map: issued by it and
+    /// not removed since.
+    #[inline]
+    pub unsafe fn get_unchecked(&self, handle: Handle) -> &V {
+        // SAFETY: the caller guarantees the slot is occupied.
+        &unsafe { self.slots.get_unchecked(handle.raw_handle()) }.value
+    }
+
+    /// Mutable counterpart of `get_unchecked`.
+    ///
+    /// # Safety
+    /// As for `get_unchecked`.
+    #[inline]
+    pub unsafe fn get_unchecked_mut(&mut self, handle: Handle) -> &mut V {
+        // SAFETY: the caller guarantees the slot is occupied.
+        &mut unsafe { self.slots.get_unchecked_mut(handle.raw_handle()) }.value
+    }
+
     pub fn iter(&self) -> Iter<'_, K, V, S> {
         let it = self.slots.iter();
         Iter {
@@ -269,6 +1105,109 @@ where
     }
 }
 
+// Snapshots store an entry as `(key, value)`. The hash is recomputed on
+// load, since the restoring map's hasher may be seeded differently.
+#[cfg(feature = "persistence")]
+impl<K: serde::Serialize, V: serde::Serialize> serde::Serialize for Entry<K, V> {
+    fn serialize<Ser: serde::Serializer>(&self, s: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serde::Serialize::serialize(&(&self.key, &self.value), s)
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<'de, K: serde::Deserialize<'de>, V: serde::Deserialize<'de>> serde::Deserialize<'de>
+    for Entry<K, V>
+{
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let (key, value) = <(K, V)>::deserialize(d)?;
+        Ok(Entry {
+            key,
+            value,
+            hash: StoredHash::default(),
+        })
+    }
+}
+
+#[cfg(feature = "persistence")]
+fn snapshot_error(e: bincode::ErrorKind) -> std::io::Error {
+    match e {
+        bincode::ErrorKind::Io(e) => e,
+        e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+    }
+}
+
+/// Snapshots (`persistence` feature).
+#[cfg(feature = "persistence")]
+impl<K, V, S> HandleHashMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Clone + Default,
+{
+    /// Write a snapshot of the map to `w`: every entry together with the
+    /// slot indices and generations behind its `Handle`, so `load` restores
+    /// a map that resolves the same handles (and rejects the same stale
+    /// ones). The encoding is private to this crate and stable within a
+    /// major version.
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn save<W: std::io::Write>(&self, w: W) -> std::io::Result<()>
+    where
+        K: serde::Serialize,
+        V: serde::Serialize,
+    {
+        let _g = self.reentrancy.enter_shared("save");
+        bincode::serialize_into(w, &(self.marks.slot_extent as u64, &self.slots))
+            .map_err(|e| snapshot_error(*e))
+    }
+
+    /// Restore a map written by `save`, using a default hasher.
+    pub fn load<R: std::io::Read>(r: R) -> std::io::Result<Self>
+    where
+        K: serde::de::DeserializeOwned,
+        V: serde::de::DeserializeOwned,
+    {
+        Self::load_with_hasher(r, S::default())
+    }
+
+    /// Restore a map written by `save`, rehashing its keys with `hasher`.
+    /// Fails with `InvalidData` if the snapshot is malformed or holds
+    /// duplicate keys.
+    pub fn load_with_hasher<R: std::io::Read>(r: R, hasher: S) -> std::io::Result<Self>
+    where
+        K: serde::de::DeserializeOwned,
+        V: serde::de::DeserializeOwned,
+    {
+        let (slot_extent, mut slots): (u64, SlotMap<DefaultKey, Entry<K, V>>) =
+            bincode::deserialize_from(r).map_err(|e| snapshot_error(*e))?;
+        for (_, e) in slots.iter_mut() {
+            e.hash = StoredHash::of(&hasher, &e.key);
+        }
+        let mut index = HashTable::with_capacity(slots.len());
+        for (k, e) in slots.iter() {
+            let dup = index
+                .find(e.hash.index(), |&kk: &DefaultKey| slots[kk].key == e.key)
+                .is_some();
+            if dup {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "duplicate key in snapshot",
+                ));
+            }
+            index.insert_unique(e.hash.index(), k, probe_hash(&slots));
+        }
+        Ok(Self {
+            hasher,
+            index,
+            marks: Marks {
+                peak_len: slots.len(),
+                slot_extent: (slot_extent as usize).max(slots.len()),
+            },
+            order: Order::from_keys(slots.keys()),
+            slots,
+            reentrancy: DebugReentrancy::new(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,13 +1215,239 @@ mod tests {
     use std::collections::BTreeSet;
     use std::hash::Hasher;
 
-    /// Invariant: Duplicate keys are rejected and the map remains unchanged.
+    /// Invariant: With `slotmap-interop`, handles round-trip through slotmap
+    /// keys and can index a `SecondaryMap` side table.
+    #[cfg(feature = "slotmap-interop")]
+    #[test]
+    fn slotmap_interop_roundtrip() {
+        let mut m: HandleHashMap<&str, i32> = HandleHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let b = m.insert("b", 2).unwrap();
+        let mut side: slotmap::SecondaryMap<DefaultKey, &str> = slotmap::SecondaryMap::new();
+        side.insert(a.into(), "first");
+        side.insert(b.into(), "second");
+        assert_eq!(side[DefaultKey::from(b)], "second");
+
+        let data: slotmap::KeyData = a.into();
+        assert_eq!(Handle::from(data), a);
+        assert_eq!(Handle::from(DefaultKey::from(a)), a);
+
+        m.remove(a).unwrap();
+        let stale = Handle::from(DefaultKey::from(a));
+        assert!(stale.value(&m).is_none());
+    }
+
+    /// Invariant: A snapshot restores every entry under its original handle;
+    /// handles stale before the save stay stale, and new inserts work.
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn save_load_preserves_handles() {
+        let mut m: HandleHashMap<String, u32> = HandleHashMap::new();
+        let a = m.insert("a".to_string(), 1).unwrap();
+        let gone = m.insert("gone".to_string(), 0).unwrap();
+        m.remove(gone).unwrap();
+        let b = m.insert("b".to_string(), 2).unwrap();
+        let mut buf = Vec::new();
+        m.save(&mut buf).unwrap();
+
+        let mut r: HandleHashMap<String, u32> = HandleHashMap::load(&buf[..]).unwrap();
+        assert_eq!(r.len(), 2);
+        assert_eq!(r.find("a"), Some(a));
+        assert_eq!(r.find("b"), Some(b));
+        assert_eq!(b.value(&r), Some(&2));
+        assert!(gone.value(&r).is_none());
+        let c = r.insert("c".to_string(), 3).unwrap();
+        assert_ne!(c, a);
+        assert_ne!(c, b);
+        assert!(HandleHashMap::<String, u32>::load(&buf[..buf.len() - 1]).is_err());
+    }
+
+    /// Invariant: Serialized handles saved alongside a snapshot resolve to
+    /// the same entries in the restored map.
+    #[cfg(all(feature = "serde", feature = "persistence"))]
+    #[test]
+    fn serialized_handles_survive_snapshot() {
+        let mut m: HandleHashMap<String, u32> = HandleHashMap::new();
+        let a = m.insert("a".to_string(), 1).unwrap();
+        let b = m.insert("b".to_string(), 2).unwrap();
+        let mut buf = Vec::new();
+        m.save(&mut buf).unwrap();
+        let edges = bincode::serialize(&vec![(a, b), (b, a)]).unwrap();
+
+        let r: HandleHashMap<String, u32> = HandleHashMap::load(&buf[..]).unwrap();
+        let edges: Vec<(Handle, Handle)> = bincode::deserialize(&edges).unwrap();
+        assert_eq!(edges, [(a, b), (b, a)]);
+        assert_eq!(edges[0].1.value(&r), Some(&2));
+        assert_eq!(edges[1].1.key(&r).map(String::as_str), Some("a"));
+    }
+
+    /// Invariant: Raw parts round-trip with handles intact, and parts whose
+    /// stored hashes or index disagree with the entries are rejected.
+    #[test]
+    fn raw_parts_roundtrip_and_validation() {
+        let mut m: HandleHashMap<&str, i32> = HandleHashMap::new();
+        let a = m.insert("a", 1).unwrap();
+        let b = m.insert("b", 2).unwrap();
+        let mut parts = m.into_raw_parts();
+        for (_, e) in parts.slots.iter_mut() {
+            e.value *= 10;
+        }
+        let m = HandleHashMap::from_raw_parts(parts).ok().unwrap();
+        assert_eq!(a.value(&m), Some(&10));
+        assert_eq!(m.find("b"), Some(b));
+
+        let mut parts = m.into_raw_parts();
+        let hash_b = parts.slots[b.raw_handle()].hash;
+        parts.slots[b.raw_handle()].hash = parts.slots[a.raw_handle()].hash;
+        let mut parts = HandleHashMap::from_raw_parts(parts).err().unwrap();
+        parts.slots[b.raw_handle()].hash = hash_b;
+        parts.index.clear();
+        let parts = HandleHashMap::from_raw_parts(parts).err().unwrap();
+        assert_eq!(parts.slots.len(), 2);
+    }
+
+    /// Invariant: `allocated_bytes` covers at least one entry per live
+    /// element and grows with capacity.
+    #[test]
+    fn allocated_bytes_tracks_capacity() {
+        let mut m: HandleHashMap<u64, [u8; 32]> = HandleHashMap::new();
+        let empty = m.allocated_bytes();
+        for i in 0..1000 {
+            m.insert(i, [0; 32]).unwrap();
+        }
+        let full = m.allocated_bytes();
+        assert!(full > empty);
+        assert!(full >= 1000 * core::mem::size_of::<Entry<u64, [u8; 32]>>());
+    }
+
+    /// Invariant: A failing constructor leaves the map unchanged; a duplicate
+    /// key is reported without running the constructor.
+    #[test]
+    fn insert_with_result_rolls_back() {
+        let mut m: HandleHashMap<&str, i32> = HandleHashMap::new();
+        let r = m.insert_with_result("a", || Err::<i32, _>("bad"));
+        assert!(matches!(r, Err(TryInsertError::Constructor("bad"))));
+        assert!(m.is_empty());
+
+        let h = m.insert_with_result("a", || Ok::<_, ()>(1)).unwrap();
+        assert_eq!(h.value(&m), Some(&1));
+        let dup = m.insert_with_result("a", || -> Result<i32, ()> { panic!("must not run") });
+        assert!(matches!(dup, Err(TryInsertError::DuplicateKey)));
+        assert_eq!(m.len(), 1);
+    }
+
+    /// Invariant: `peak_len` is the largest `len` since the last reset, and a
+    /// reset restarts from the current `len`.
+    #[test]
+    fn peak_len_tracks_and_resets() {
+        let mut m: HandleHashMap<u32, ()> = HandleHashMap::new();
+        let hs: Vec<_> = (0..5).map(|i| m.insert(i, ()).unwrap()).collect();
+        for h in &hs[..3] {
+            m.remove(*h);
+        }
+        assert_eq!((m.len(), m.peak_len()), (2, 5));
+        m.reset_peak_len();
+        assert_eq!(m.peak_len(), 2);
+        m.insert_with(9, || ()).unwrap();
+        assert_eq!(m.peak_len(), 3);
+    }
+
+    /// Invariant: `any_handle` yields a live handle while the map is
+    /// non-empty, so repeatedly removing it drains the map.
+    #[test]
+    fn any_handle_drains_map() {
+        let mut m: HandleHashMap<u32, u32> = HandleHashMap::new();
+        assert_eq!(m.any_handle(), None);
+        for i in 0..10 {
+            m.insert(i, i * 2).unwrap();
+        }
+        let mut seen = Vec::new();
+        while let Some(h) = m.any_handle() {
+            let (k, v) = m.remove(h).unwrap();
+            assert_eq!(v, k * 2);
+            seen.push(k);
+        }
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+    }
+
+    /// Invariant: `any_handle` does not depend on which slots are vacant:
+    /// after freeing the front of storage it still returns the last
+    /// position's entry.
+    #[test]
+    fn any_handle_ignores_vacant_front() {
+        let mut m: HandleHashMap<u32, ()> = HandleHashMap::new();
+        let hs: Vec<_> = (0..1000).map(|i| m.insert(i, ()).unwrap()).collect();
+        for &h in &hs[..999] {
+            m.remove(h).unwrap();
+        }
+        assert_eq!(m.any_handle(), Some(hs[999]));
+        let h = m.insert(1000, ()).unwrap();
+        assert_eq!(m.any_handle(), Some(h));
+    }
+
+    /// Invariant: positions are dense and in insertion order; a removal
+    /// moves the last entry into the freed position, and `compact` keeps
+    /// every position.
+    #[test]
+    fn positional_access_is_dense_insertion_order() {
+        let mut m: HandleHashMap<u32, u32> = HandleHashMap::new();
+        for i in 0..6 {
+            m.insert(i, i).unwrap();
+        }
+        let gone = m.find(&2).unwrap();
+        m.remove(gone).unwrap();
+        // Reuses the freed slot, but is appended to the positions.
+        m.insert(6, 6).unwrap();
+        let keys = |m: &HandleHashMap<u32, u32>| -> Vec<u32> {
+            (0..m.len())
+                .map(|i| *m.handle_key(m.get_index(i).unwrap()).unwrap())
+                .collect()
+        };
+        assert_eq!(keys(&m), [0, 1, 5, 3, 4, 6]);
+        for (pos, k) in keys(&m).iter().enumerate() {
+            assert_eq!(m.get_index_of(k), Some(pos));
+        }
+        assert_eq!(m.get_index(m.len()), None);
+        assert_eq!(m.get_index_of(&2), None);
+
+        m.compact();
+        assert_eq!(keys(&m), [0, 1, 5, 3, 4, 6]);
+    }
+
+    /// Invariant: Removed slots are counted as free until reused, and
+    /// inserts reuse them before growing.
+    #[test]
+    fn slot_stats_counts_free_slots() {
+        let mut m: HandleHashMap<u32, ()> = HandleHashMap::new();
+        let hs: Vec<_> = (0..10).map(|i| m.insert(i, ()).unwrap()).collect();
+        for h in &hs[..6] {
+            m.remove(*h);
+        }
+        let st = m.slot_stats();
+        assert_eq!((st.live, st.free), (4, 6));
+        assert!(st.capacity >= 10);
+        assert!(st.fragmentation() >= 0.6);
+
+        m.insert(100, ()).unwrap();
+        assert_eq!(m.slot_stats().free, 5);
+    }
+
+    /// Invariant: Duplicate keys are rejected and the map remains unchanged;
+    /// the error returns the rejected pair and the existing entry's handle.
     #[test]
     fn duplicate_insert_rejected() {
         let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
         let handle = m.insert("dup".to_string(), 1).unwrap();
         match m.insert("dup".to_string(), 2) {
-            Err(InsertError::DuplicateKey) => {}
+            Err(DuplicateKey {
+                existing,
+                key,
+                value,
+            }) => {
+                assert_eq!(existing, handle);
+                assert_eq!((key.as_str(), value), ("dup", 2));
+            }
             other => panic!("unexpected result: {:?}", other),
         }
         assert_eq!(*handle.value(&m).unwrap(), 1);
@@ -361,6 +1526,220 @@ mod tests {
         assert!(!m.contains_key("old"));
     }
 
+    /// Invariant: `contains_handle` is true for live handles and false once the
+    /// entry is removed, even if its slot is reused by a later insert.
+    #[test]
+    fn contains_handle_tracks_liveness() {
+        let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
+        let h1 = m.insert("a".to_string(), 1).unwrap();
+        assert!(m.contains_handle(h1));
+        let _ = m.remove(h1).unwrap();
+        assert!(!m.contains_handle(h1));
+        let h2 = m.insert("b".to_string(), 2).unwrap();
+        assert!(m.contains_handle(h2));
+        assert!(!m.contains_handle(h1));
+    }
+
+    /// Invariant: `swap` exchanges values but not keys; lookups by key observe the
+    /// swapped values and a stale handle makes `swap` a no-op.
+    #[test]
+    fn swap_exchanges_values_only() {
+        let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
+        let ha = m.insert("a".to_string(), 1).unwrap();
+        let hb = m.insert("b".to_string(), 2).unwrap();
+        assert!(m.swap(ha, hb));
+        assert_eq!(ha.key(&m), Some(&"a".to_string()));
+        assert_eq!(ha.value(&m), Some(&2));
+        assert_eq!(m.find("b").and_then(|h| h.value(&m)), Some(&1));
+
+        // Swapping an entry with itself is a no-op.
+        assert!(m.swap(ha, ha));
+        assert_eq!(ha.value(&m), Some(&2));
+
+        let _ = m.remove(hb).unwrap();
+        assert!(!m.swap(ha, hb));
+        assert_eq!(ha.value(&m), Some(&2));
+    }
+
+    /// Invariant: `take_value` returns the stored value and leaves the default
+    /// behind without removing the entry; stale handles yield `None`.
+    #[test]
+    fn take_value_leaves_default() {
+        let mut m: HandleHashMap<String, Vec<i32>> = HandleHashMap::new();
+        let h = m.insert("k".to_string(), vec![1, 2, 3]).unwrap();
+        assert_eq!(m.take_value(h), Some(vec![1, 2, 3]));
+        assert_eq!(h.value(&m), Some(&Vec::new()));
+        assert_eq!(m.len(), 1);
+        assert!(m.contains_key("k"));
+
+        let _ = m.remove(h).unwrap();
+        assert_eq!(m.take_value(h), None);
+    }
+
+    /// Invariant: `find_or_insert_with` inserts once, then returns the same
+    /// handle without running the constructor again.
+    #[test]
+    fn find_or_insert_with_single_probe_semantics() {
+        let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
+        let calls = Cell::new(0);
+        let (h1, inserted) = m.find_or_insert_with("k".to_string(), || {
+            calls.set(calls.get() + 1);
+            1
+        });
+        assert!(inserted);
+        let (h2, inserted) = m.find_or_insert_with("k".to_string(), || {
+            calls.set(calls.get() + 1);
+            2
+        });
+        assert!(!inserted);
+        assert_eq!(h1, h2);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(h1.value(&m), Some(&1));
+        assert_eq!(m.len(), 1);
+    }
+
+    /// Invariant: `compact` leaves no free slots, keeps every entry
+    /// reachable by key, and its translation maps old handles to the same
+    /// entries.
+    #[test]
+    fn compact_repacks_and_translates_handles() {
+        let mut m: HandleHashMap<u32, u32> = HandleHashMap::new();
+        let hs: Vec<_> = (0..100).map(|i| m.insert(i, i * 2).unwrap()).collect();
+        for h in &hs[..90] {
+            m.remove(*h);
+        }
+        let moves = m.compact();
+        assert_eq!(moves.len(), 10);
+        let st = m.slot_stats();
+        assert_eq!((st.live, st.free), (10, 0));
+        assert!(st.capacity < 100);
+        for (old, new) in moves {
+            let i = hs.iter().position(|h| *h == old).unwrap() as u32;
+            assert_eq!(new.value(&m), Some(&(i * 2)));
+            assert_eq!(m.find(&i), Some(new));
+        }
+    }
+
+    /// A key whose hash reads a `Cell`, so tests can mutate it in place.
+    #[derive(Debug, PartialEq, Eq)]
+    struct CellKey(core::cell::Cell<u32>);
+
+    impl Hash for CellKey {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.0.get().hash(state)
+        }
+    }
+
+    /// Invariant: `validate` names a key mutated after insert.
+    #[test]
+    #[should_panic(expected = "key CellKey(Cell { value: 9 })")]
+    fn validate_reports_mutated_key() {
+        let mut m: HandleHashMap<CellKey, ()> = HandleHashMap::new();
+        let h = m.insert(CellKey(core::cell::Cell::new(1)), ()).unwrap();
+        m.insert(CellKey(core::cell::Cell::new(2)), ()).unwrap();
+        m.validate();
+        h.key(&m).unwrap().0.set(9);
+        m.validate();
+    }
+
+    /// Invariant: In debug builds, handle access to an entry whose key was
+    /// mutated after insert panics.
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "hashes differently than when it was inserted")]
+    fn handle_access_detects_mutated_key() {
+        let mut m: HandleHashMap<CellKey, u8> = HandleHashMap::new();
+        let h = m.insert(CellKey(core::cell::Cell::new(1)), 0).unwrap();
+        h.key(&m).unwrap().0.set(9);
+        let _ = h.value(&m);
+    }
+
+    /// Invariant: Unchecked accessors agree with the checked ones for live
+    /// handles.
+    #[test]
+    fn unchecked_access_matches_checked() {
+        let mut m: HandleHashMap<u32, u64> = HandleHashMap::new();
+        let hs: Vec<_> = (0..8).map(|i| m.insert(i, u64::from(i)).unwrap()).collect();
+        for &h in &hs {
+            // SAFETY: every handle in `hs` is live.
+            unsafe { *m.get_unchecked_mut(h) += 100 };
+            assert_eq!(Some(unsafe { m.get_unchecked(h) }), h.value(&m));
+        }
+        assert_eq!(hs[3].value(&m), Some(&103));
+    }
+
+    /// Invariant: `lookup_batch` agrees with `find` per key, in input
+    /// order, including under full hash collisions.
+    #[test]
+    fn lookup_batch_matches_find() {
+        let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
+        let a = m.insert("a".to_string(), 1).unwrap();
+        let b = m.insert("b".to_string(), 2).unwrap();
+        let res = m.lookup_batch(["b", "x", "a", "b"]);
+        assert_eq!(res, vec![Some(b), None, Some(a), Some(b)]);
+
+        #[derive(Clone, Default)]
+        struct ConstBuildHasher;
+        struct ConstHasher;
+        impl BuildHasher for ConstBuildHasher {
+            type Hasher = ConstHasher;
+            fn build_hasher(&self) -> Self::Hasher {
+                ConstHasher
+            }
+        }
+        impl core::hash::Hasher for ConstHasher {
+            fn write(&mut self, _bytes: &[u8]) {}
+            fn finish(&self) -> u64 {
+                7
+            }
+        }
+        let mut c: HandleHashMap<u32, (), ConstBuildHasher> =
+            HandleHashMap::with_hasher(ConstBuildHasher);
+        let hs: Vec<_> = (0..5).map(|i| c.insert(i, ()).unwrap()).collect();
+        let keys = [4, 0, 9, 2];
+        let res = c.lookup_batch(&keys);
+        assert_eq!(res, vec![Some(hs[4]), Some(hs[0]), None, Some(hs[2])]);
+    }
+
+    /// Invariant: `find_many` agrees with `find` per key, in input order.
+    #[test]
+    fn find_many_matches_find() {
+        let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
+        let a = m.insert("a".to_string(), 1).unwrap();
+        let b = m.insert("b".to_string(), 2).unwrap();
+        let res = m.find_many(["b", "x", "a", "b"]);
+        assert_eq!(res, vec![Some(b), None, Some(a), Some(b)]);
+    }
+
+    /// Invariant: `insert_many` reports per-item results in order, rejecting
+    /// duplicates within the batch and against existing entries; `remove_many`
+    /// returns owned pairs for live handles and `None` for stale ones.
+    #[test]
+    fn insert_many_and_remove_many() {
+        let mut m: HandleHashMap<String, i32> = HandleHashMap::new();
+        m.insert("x".to_string(), 0).unwrap();
+        let res = m.insert_many(vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 2),
+            ("a".to_string(), 3),
+            ("x".to_string(), 4),
+        ]);
+        assert_eq!(res.len(), 4);
+        assert!(res[0].is_ok() && res[1].is_ok());
+        assert!(matches!(res[2], Err(InsertError::DuplicateKey)));
+        assert!(matches!(res[3], Err(InsertError::DuplicateKey)));
+        assert_eq!(m.len(), 3);
+
+        let ha = *res[0].as_ref().unwrap();
+        let hb = *res[1].as_ref().unwrap();
+        let removed = m.remove_many([ha, hb, ha]);
+        assert_eq!(removed[0], Some(("a".to_string(), 1)));
+        assert_eq!(removed[1], Some(("b".to_string(), 2)));
+        assert_eq!(removed[2], None);
+        assert_eq!(m.len(), 1);
+        assert!(m.contains_key("x"));
+    }
+
     /// Invariant: Iteration yields each live entry exactly once; `iter_mut` updates
     /// values as seen by subsequent lookups.
     #[test]
@@ -392,6 +1771,44 @@ mod tests {
         }
     }
 
+    /// Invariant: `iter`/`iter_mut` report an exact length that shrinks as items
+    /// are consumed, and stay exhausted once they return `None`.
+    #[test]
+    fn iterators_are_exact_size_and_fused() {
+        let mut m: HandleHashMap<u32, u32> = HandleHashMap::new();
+        for i in 0..5 {
+            m.insert(i, i).unwrap();
+        }
+        let mut it = m.iter();
+        assert_eq!(it.len(), 5);
+        it.next();
+        assert_eq!(it.size_hint(), (4, Some(4)));
+        let collected: Vec<_> = it.by_ref().collect();
+        assert_eq!(collected.len(), 4);
+        assert!(it.next().is_none());
+        assert!(it.next().is_none());
+
+        let mut it = m.iter_mut();
+        assert_eq!(it.len(), 5);
+        it.next();
+        assert_eq!(it.len(), 4);
+    }
+
+    /// Invariant: a cloned `Iter` resumes from the original's position and
+    /// advances independently of it.
+    #[test]
+    fn iter_clone_resumes_independently() {
+        let mut m: HandleHashMap<u32, ()> = HandleHashMap::new();
+        for i in 0..4 {
+            m.insert(i, ()).unwrap();
+        }
+        let mut it = m.iter();
+        it.next();
+        let rest: Vec<_> = it.clone().map(|(h, _, _)| h).collect();
+        assert_eq!(rest.len(), 3);
+        assert!(it.map(|(h, _, _)| h).eq(rest));
+    }
+
     /// Invariant: Lookups work under heavy hash collisions; equality resolves to the
     /// correct entry. This also exercises collision probing via `Eq`.
     #[test]
@@ -449,11 +1866,82 @@ mod tests {
         assert!(h1.value(&m).is_none(), "stale handle stays invalid");
     }
 
-    /// Invariant (debug-only): Re-entering `HandleHashMap` from within `K: Eq` during a
-    /// probe panics due to the reentrancy guard; in release builds, this test is skipped.
+    /// Invariant (wide-hash): keys whose 64-bit index hashes all collide
+    /// are told apart by the stored high half, so each lookup calls `Eq`
+    /// only on the matching key.
+    #[cfg(feature = "wide-hash")]
+    #[test]
+    fn wide_hash_skips_eq_on_index_collisions() {
+        use std::cell::Cell;
+        thread_local! {
+            static EQ_CALLS: Cell<usize> = const { Cell::new(0) };
+        }
+        struct Key(u8);
+        impl core::hash::Hash for Key {
+            fn hash<H: core::hash::Hasher>(&self, h: &mut H) {
+                h.write_u8(self.0);
+            }
+        }
+        impl PartialEq for Key {
+            fn eq(&self, o: &Self) -> bool {
+                EQ_CALLS.with(|c| c.set(c.get() + 1));
+                self.0 == o.0
+            }
+        }
+        impl Eq for Key {}
+        // Hashes to 0 unless salted first, as `StoredHash::of`'s high half is.
+        #[derive(Clone, Default)]
+        struct LowCollider;
+        #[derive(Default)]
+        struct LowColliderHasher {
+            salted: Option<bool>,
+            sum: u64,
+        }
+        impl BuildHasher for LowCollider {
+            type Hasher = LowColliderHasher;
+            fn build_hasher(&self) -> Self::Hasher {
+                LowColliderHasher::default()
+            }
+        }
+        impl core::hash::Hasher for LowColliderHasher {
+            fn write(&mut self, bytes: &[u8]) {
+                self.salted.get_or_insert(false);
+                for &b in bytes {
+                    self.sum = self.sum * 31 + u64::from(b) + 1;
+                }
+            }
+            fn write_u64(&mut self, x: u64) {
+                self.salted.get_or_insert(true);
+                self.write(&x.to_le_bytes());
+            }
+            fn finish(&self) -> u64 {
+                if self.salted == Some(true) {
+                    self.sum
+                } else {
+                    0
+                }
+            }
+        }
+
+        let mut m: HandleHashMap<Key, u8, LowCollider> = HandleHashMap::with_hasher(LowCollider);
+        for i in 0..16 {
+            m.insert(Key(i), i).unwrap();
+        }
+        EQ_CALLS.with(|c| c.set(0));
+        for i in 0..16 {
+            let h = m.find(&Key(i)).unwrap();
+            assert_eq!(h.value(&m), Some(&i));
+        }
+        assert!(m.find(&Key(99)).is_none());
+        assert_eq!(EQ_CALLS.with(|c| c.get()), 16);
+    }
+
+    /// Invariant (debug-only): Re-entering `HandleHashMap` for a read from within
+    /// `K: Eq` is allowed during a read-only probe (`find`) but panics during a
+    /// mutating one (`insert`); in release builds, this test is skipped.
     #[cfg(debug_assertions)]
     #[test]
-    fn reentrancy_panics_from_eq_during_find() {
+    fn reentrancy_from_eq_shared_ok_exclusive_panics() {
         #[derive(Clone, Default)]
         struct ConstBuildHasher;
         struct ConstHasher;
@@ -526,8 +2014,12 @@ mod tests {
             map: &m as *const _,
             trigger: true,
         };
+        // Nested read under a read: allowed.
+        assert!(m.find(&query).is_none());
+
+        // Nested read under a write: trapped.
         let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            let _ = m.find(&query);
+            let _ = m.insert(query, 2);
         }));
         assert!(res.is_err(), "expected reentrancy to panic in debug builds");
     }
@@ -552,7 +2044,9 @@ mod tests {
             "v2".to_string()
         });
         match r2 {
-            Err(InsertError::DuplicateKey) => {}
+            Err(DuplicateKey { existing, .. }) => {
+                assert_eq!(existing.value(&m), Some(&"v".to_string()));
+            }
             other => panic!("unexpected result: {:?}", other),
         }
         assert_eq!(calls.get(), 1, "default() must not run on duplicate");
@@ -617,7 +2111,7 @@ mod tests {
 
         // Duplicate insert must not change len/is_empty
         match m.insert("a".to_string(), 2) {
-            Err(InsertError::DuplicateKey) => {}
+            Err(DuplicateKey { existing, .. }) => assert_eq!(existing, h1),
             other => panic!("unexpected result: {:?}", other),
         }
         assert_eq!(m.len(), 1);
@@ -3,7 +3,7 @@
 // Property tests for HandleHashMap kept inside the crate so they do not
 // require feature gates to access internal modules.
 
-use crate::handle_hash_map::{Handle, HandleHashMap, InsertError};
+use crate::handle_hash_map::{DuplicateKey, Handle, HandleHashMap};
 use proptest::prelude::*;
 use proptest::test_runner::TestCaseResult;
 use std::cell::Cell;
@@ -88,8 +88,9 @@ fn run_state_machine<B: std::hash::BuildHasher + Clone + Default>(
                         prop_assert!(prev.is_none());
                         model.insert(k, v);
                     }
-                    Err(InsertError::DuplicateKey) => {
+                    Err(DuplicateKey { existing, .. }) => {
                         prop_assert!(already, "duplicate error only when key exists");
+                        prop_assert_eq!(Some(&existing), live.get(&k));
                     }
                 }
             }
@@ -113,8 +114,9 @@ fn run_state_machine<B: std::hash::BuildHasher + Clone + Default>(
                         prop_assert!(prev.is_none());
                         model.insert(k, v);
                     }
-                    Err(InsertError::DuplicateKey) => {
+                    Err(DuplicateKey { existing, .. }) => {
                         prop_assert!(already, "duplicate error only when key exists");
+                        prop_assert_eq!(Some(&existing), live.get(&k));
                         prop_assert_eq!(
                             default_calls.get(),
                             before,
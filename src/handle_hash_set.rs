@@ -0,0 +1,134 @@
+//! HandleHashSet: structural set with stable handles, built on
+//! `HandleHashMap` with unit values.
+//!
+//! Elements are unique; inserting a duplicate fails. Each element is
+//! addressed by a generational `Handle` that stays valid until the element
+//! is removed, e.g. for deduplicated node registries addressed by handle.
+
+use crate::handle_hash_map::{Handle, HandleHashMap, InsertError};
+use crate::hash::DefaultHashBuilder;
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+
+pub struct HandleHashSet<T, S = DefaultHashBuilder> {
+    map: HandleHashMap<T, (), S>,
+}
+
+impl<T> HandleHashSet<T>
+where
+    T: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(Default::default())
+    }
+}
+
+impl<T> Default for HandleHashSet<T>
+where
+    T: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, S> HandleHashSet<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher + Clone + Default,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            map: HandleHashMap::with_hasher(hasher),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Insert `value`, returning its handle; fails if an equal element is
+    /// already present.
+    pub fn insert(&mut self, value: T) -> Result<Handle, InsertError> {
+        self.map.insert(value, ()).map_err(Into::into)
+    }
+
+    /// Return the handle for `value`, inserting it first if absent. The flag
+    /// is true when a new element was inserted.
+    pub fn find_or_insert(&mut self, value: T) -> (Handle, bool) {
+        self.map.find_or_insert_with(value, || ())
+    }
+
+    pub fn find<Q>(&self, q: &Q) -> Option<Handle>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.find(q)
+    }
+
+    pub fn contains<Q>(&self, q: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.map.contains_key(q)
+    }
+
+    /// Returns true if `handle` still refers to a live element.
+    pub fn contains_handle(&self, handle: Handle) -> bool {
+        self.map.contains_handle(handle)
+    }
+
+    /// Borrow the element behind `handle`, or `None` if it is stale.
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        self.map.handle_key(handle)
+    }
+
+    /// Remove and return the element behind `handle`.
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        self.map.remove(handle).map(|(t, ())| t)
+    }
+
+    /// Re-pack elements after heavy churn, returning `(old, new)` handle
+    /// pairs; see `HandleHashMap::compact`.
+    pub fn compact(&mut self) -> Vec<(Handle, Handle)> {
+        self.map.compact()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle, &T)> + Clone {
+        self.map.iter().map(|(h, t, _)| (h, t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Invariant: Elements are unique, handles resolve until removal, and
+    /// a removed element's handle goes stale.
+    #[test]
+    fn insert_find_remove_and_stale_handles() {
+        let mut s: HandleHashSet<String> = HandleHashSet::new();
+        let a = s.insert("a".to_string()).unwrap();
+        assert!(matches!(
+            s.insert("a".to_string()),
+            Err(InsertError::DuplicateKey)
+        ));
+        assert_eq!(s.find_or_insert("a".to_string()), (a, false));
+        let (b, inserted) = s.find_or_insert("b".to_string());
+        assert!(inserted);
+        assert_eq!(s.find("b"), Some(b));
+        assert_eq!(s.get(a).map(String::as_str), Some("a"));
+        assert_eq!(s.iter().count(), 2);
+
+        assert_eq!(s.remove(a).as_deref(), Some("a"));
+        assert!(!s.contains_handle(a));
+        assert!(s.get(a).is_none());
+        assert!(!s.contains("a"));
+        assert_eq!(s.len(), 1);
+    }
+}
@@ -129,3 +129,135 @@ impl BuildHasher for Xxh3RandomState {
         self.builder.build_hasher()
     }
 }
+
+/// Near-identity hasher for integer keys: `finish` returns the written
+/// integer times an odd constant. The multiply keeps dense IDs spread across
+/// hashbrown's control bytes (which read the high bits) at the cost of one
+/// instruction, instead of a full SipHash/wyhash round. Not DoS-resistant;
+/// use it only for keys the program assigns itself.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IdentityHasher(u64);
+
+impl core::hash::Hasher for IdentityHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    // Non-integer input (length prefixes, byte strings) is folded in so any
+    // `Hash` type still works, just without the speedup.
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0.rotate_left(8) ^ u64::from(b)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, n: u8) {
+        self.write_u64(u64::from(n))
+    }
+    #[inline]
+    fn write_u16(&mut self, n: u16) {
+        self.write_u64(u64::from(n))
+    }
+    #[inline]
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(u64::from(n))
+    }
+    #[inline]
+    fn write_u64(&mut self, n: u64) {
+        self.0 = self.0.rotate_left(32) ^ n;
+    }
+    #[inline]
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64)
+    }
+    #[inline]
+    fn write_i8(&mut self, n: i8) {
+        self.write_u64(n as u64)
+    }
+    #[inline]
+    fn write_i16(&mut self, n: i16) {
+        self.write_u64(n as u64)
+    }
+    #[inline]
+    fn write_i32(&mut self, n: i32) {
+        self.write_u64(n as u64)
+    }
+    #[inline]
+    fn write_i64(&mut self, n: i64) {
+        self.write_u64(n as u64)
+    }
+    #[inline]
+    fn write_isize(&mut self, n: isize) {
+        self.write_u64(n as u64)
+    }
+}
+
+/// `BuildHasher` for `IdentityHasher`; stateless, so every map using it
+/// hashes alike.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IdentityHashBuilder;
+
+impl core::hash::BuildHasher for IdentityHashBuilder {
+    type Hasher = IdentityHasher;
+    #[inline]
+    fn build_hasher(&self) -> IdentityHasher {
+        IdentityHasher::default()
+    }
+}
+
+/// Primitive integer keys, for which `IdentityHashBuilder` is a good fit.
+pub trait IntegerKey: Eq + core::hash::Hash + Copy {}
+
+macro_rules! integer_key {
+    ($($t:ty),*) => { $(impl IntegerKey for $t {})* };
+}
+integer_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// The hash each entry stores: 64 bits, or 128 with the `wide-hash`
+/// feature. The index probes on the low 64 bits either way; the full width
+/// is compared before `K: Eq` runs, so with 128 bits a lookup essentially
+/// never calls `Eq` on a non-matching key, even under adversarial inputs.
+/// The width is private, so the type and its API are the same with or
+/// without the feature.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct StoredHash(Wide);
+
+#[cfg(not(feature = "wide-hash"))]
+type Wide = u64;
+#[cfg(feature = "wide-hash")]
+type Wide = u128;
+
+impl StoredHash {
+    /// Hash `q` with `s` as a map built with `s` stores it. The wide form
+    /// double-hashes: the high half comes from a second pass over a salted
+    /// hasher, so any `BuildHasher` can be used unchanged.
+    #[inline]
+    pub fn of<S, Q>(s: &S, q: &Q) -> Self
+    where
+        S: core::hash::BuildHasher,
+        Q: ?Sized + core::hash::Hash,
+    {
+        #[cfg(not(feature = "wide-hash"))]
+        {
+            StoredHash(s.hash_one(q))
+        }
+        #[cfg(feature = "wide-hash")]
+        {
+            use core::hash::Hasher;
+            let mut h = s.build_hasher();
+            h.write_u64(0x9e37_79b9_7f4a_7c15);
+            q.hash(&mut h);
+            StoredHash((u128::from(h.finish()) << 64) | u128::from(s.hash_one(q)))
+        }
+    }
+
+    /// The part of the hash the index probes on.
+    #[inline]
+    #[allow(clippy::unnecessary_cast)]
+    pub fn index(self) -> u64 {
+        self.0 as u64
+    }
+}
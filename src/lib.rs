@@ -10,19 +10,26 @@
 //!   - HandleHashMap<K, V, S>: structural map that returns stable
 //!     handles for O(1) average access without re-hashing; includes a
 //!     debug-only reentrancy guard to keep internals consistent while
-//!     mutating.
-//!   - CountedHashMap<K, V, S>: wraps HandleHashMap and adds per-entry
+//!     mutating. `HandleHashSet<T, S>` is the same with unit values.
+//!   - CountedHashMap<K, V, S, C>: wraps HandleHashMap and adds per-entry
 //!     reference counting (increments on get/clone, decrements on put).
-//!   - RcHashMap<K, V, S>: public API that exposes `Ref` handles; drops
-//!     free entries when the last `Ref` is dropped.
+//!     `C: tokens::EntryCount` is the per-entry counter, `UsizeCount` by
+//!     default; `CheckedCount` detects overflow instead of aborting and
+//!     `SaturatingCount` makes an entry immortal once its count saturates.
+//!   - RcHashMap<K, V, S, C>: public API that exposes `Ref` handles; drops
+//!     free entries when the last `Ref` is dropped. `RcHashSet<T, S, C>` is
+//!     the same with unit values; `RcHashMapFixed<K, V, N>` caps it at `N`
+//!     entries allocated up front.
 //!
 //! Constraints
 //! - Single-threaded: `!Send`/`!Sync` by design (no atomics).
 //! - No per-entry heap allocations beyond the map’s own storage.
 //! - Stable, generational keys behind small `Handle` wrappers.
 //! - O(1) average lookups with unique keys; duplicate inserts fail.
-//! - Reentrancy: disallowed during critical sections of HandleHashMap
-//!   (only `K: Eq/Hash` may run); allowed elsewhere.
+//! - Reentrancy: during critical sections of HandleHashMap (where only
+//!   `K: Eq/Hash` may run), nested read-only calls are allowed under
+//!   read-only methods; anything nested under a mutating method, and any
+//!   mutation under a read, is disallowed. Allowed elsewhere.
 //!
 //! Why this split?
 //! - Localize invariants: each layer has a small, precise contract.
@@ -36,7 +43,8 @@
 //!   debug-only reentrancy guard at the start of each method to prevent
 //!   nested entry while its internal state can be transiently
 //!   inconsistent. These methods only invoke user code via `K: Eq/Hash`
-//!   during probing.
+//!   during probing. Violations panic unless another policy is installed
+//!   with `set_reentrancy_handler`.
 //! - Upper layers (CountedHashMap, RcHashMap) rely on HandleHashMap’s
 //!   guarantees and do not need their own guard. After
 //!   `HandleHashMap::remove` returns `(K, V)`, the structure is again
@@ -48,34 +56,73 @@
 //!   to any entry; no runtime checks are performed.
 //!
 //! Hasher and rehashing invariants
-//! - Each entry stores a precomputed `u64` hash and indexing always uses
-//!   the stored hash; `K: Hash` is never invoked after insertion. This
-//!   avoids rehash-time calls into user code.
+//! - Each entry stores a precomputed `u64` hash (`u128` with the
+//!   `wide-hash` feature) and indexing always uses the stored hash;
+//!   `K: Hash` is never invoked after insertion. This avoids rehash-time
+//!   calls into user code.
 //!
 //! Notes and non-goals
 //! - Still single-threaded; enforced with marker types on `Ref`/`Inner`.
-//! - No weak handles (could be added later).
+//! - Weak entry references (`WeakRef`) rely on generational handles
+//!   rather than a weak count; `WeakRcHashMap` observes the map as a whole.
 //! - No explicit `clear()`/`remove()`/`drain()` on RcHashMap; removal
 //!   occurs when the last `Ref` is dropped to preserve refcount
-//!   semantics.
+//!   semantics. The exception is `force_remove` for administrative
+//!   invalidation, which leaves outstanding Refs stale.
 //! - RcHashMap does not implement `Clone`.
-//! - Keys are immutable post-insert; there is no `key_mut`.
-//! - Public API surface is `RcHashMap` and its `Ref`; lower layers are
-//!   implementation details.
+//! - Keys are immutable post-insert; there is no `key_mut`. Keys mutated
+//!   through interior mutability are caught by `validate`, and by `Ref`
+//!   key/value access in debug builds.
+//! - Public API surface is `RcHashMap` and its `Ref`, plus the
+//!   `CountedHashMap` layer for manual token-based counting and the
+//!   `Cache` trait for code that wants to swap cache implementations
+//!   (`eviction::EvictingCache` is a capacity-capped one).
+//!   `HandleHashMap` remains an implementation detail.
 //!
 //! Implementation note
 //! - The internal `RcCount<T>` helper (in `tokens`) encapsulates the
-//!   raw-pointer based use of `std::rc::Rc` increment/decrement APIs.
+//!   `std::rc::Rc` strong-count manipulation: increments via
+//!   `Weak::upgrade`, decrements through `Weak::as_ptr`.
 
+mod any_rc_map;
+pub mod cache;
+pub mod canonical;
+pub mod clock;
 pub mod counted_hash_map;
+pub mod eviction;
 pub mod handle_hash_map;
 mod handle_hash_map_proptest;
+pub mod handle_hash_set;
 pub mod hash;
 mod rc_hash_map;
+mod rc_hash_map_fixed;
+mod rc_hash_set;
 mod reentrancy;
+pub mod registry;
 pub mod tokens;
+#[cfg(feature = "lifecycle-trace")]
+pub mod trace;
 
 // Public surface
-pub use handle_hash_map::InsertError;
-pub use hash::DefaultHashBuilder;
-pub use rc_hash_map::{RcHashMap, Ref};
+pub use any_rc_map::{AnyRcMap, AnyRef};
+pub use cache::{Cache, CacheStats};
+pub use clock::{Clock, ManualClock, StdClock};
+pub use counted_hash_map::{
+    ClosedMap, CountedGuard, CountedHandle, CountedHashMap, ItemGuard, ItemGuardMut, PutResult,
+    RefsOutstanding,
+};
+pub use handle_hash_map::{CappedInsertError, Handle, InsertError, SlotStats, TryInsertError};
+pub use hash::{DefaultHashBuilder, IdentityHashBuilder};
+pub use rc_hash_map::{
+    AccessError, Diff, Entry, MapId, MapObserver, MappedRef, OccupiedEntry, PeekMut, RcHashMap,
+    Ref, ScanItem, SnapshotIter, TypedRef, VacantEntry, WeakRcHashMap, WeakRef,
+};
+pub use rc_hash_map_fixed::RcHashMapFixed;
+pub use rc_hash_set::{RcHashSet, SetRef};
+pub use reentrancy::{
+    abort_on_reentrancy, log_reentrancy, panic_on_reentrancy, set_reentrancy_handler,
+    ReentrancyHandler, ReentrancyViolation,
+};
+pub use registry::LocalRegistry;
+#[cfg(feature = "lifecycle-trace")]
+pub use trace::{TraceEvent, TraceOp};
@@ -1,350 +1,2634 @@
-use crate::tokens::{Count, RcCount, Token};
+use crate::tokens::{Count, EntryCount, RcCount, Token, UsizeCount};
 // Keepalive handled via direct Rc strong-count inc/dec per entry.
+use crate::cache::{Cache, CacheStats};
+use crate::clock::Clock;
 use crate::counted_hash_map::{CountedHandle, CountedHashMap, PutResult};
-use crate::handle_hash_map::InsertError;
-use crate::hash::DefaultHashBuilder;
-use core::cell::UnsafeCell;
+use crate::handle_hash_map::{CappedInsertError, Handle, SlotStats, TryInsertError};
+use crate::hash::{DefaultHashBuilder, IdentityHashBuilder, IntegerKey, StoredHash};
+#[cfg(feature = "lifecycle-trace")]
+use crate::trace::{Trace, TraceEvent, TraceOp};
+use core::any::Any;
+#[cfg(feature = "lifecycle-trace")]
+use core::cell::RefCell;
+use core::cell::{Cell, UnsafeCell};
 use core::hash::{Hash, Hasher};
 use core::marker::PhantomData;
 use core::mem::ManuallyDrop;
+use core::time::Duration;
+use slotmap::{DefaultKey, SecondaryMap, SparseSecondaryMap};
+use std::collections::HashMap;
+use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::ptr::NonNull;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
 // Stored value wrapper that holds a keepalive token from `Inner`'s RcCount
 // to keep the allocation alive. The token is returned when the last Ref
-// for this entry is dropped and the entry is removed.
-struct RcVal<K, V, S> {
+// for this entry is dropped and the entry is removed. It sits in a
+// `ManuallyDrop` so an RcVal dropped while unwinding from a user panic
+// leaks its token (and `Inner`) instead of aborting.
+struct RcVal<K, V, S, C: EntryCount> {
     value: V,
-    keepalive_token: Token<'static, RcCount<Inner<K, V, S>>>,
+    keepalive_token: ManuallyDrop<KeepaliveToken<K, V, S, C>>,
 }
 
-struct Inner<K, V, S> {
-    map: UnsafeCell<CountedHashMap<K, RcVal<K, V, S>, S>>, // interior mutability via UnsafeCell
-    keepalive: RcCount<Inner<K, V, S>>,
+// Returns a removed entry's keepalive token if disposing of its user data
+// panics, so unwinding neither aborts nor leaks `Inner`.
+struct KeepaliveGuard<K: 'static, V: 'static, S: 'static, C: EntryCount> {
+    keepalive: RcCount<Inner<K, V, S, C>>,
+    token: Option<KeepaliveToken<K, V, S, C>>,
 }
 
-pub struct RcHashMap<K, V, S = DefaultHashBuilder> {
-    inner: Rc<Inner<K, V, S>>,
+impl<K: 'static, V: 'static, S: 'static, C: EntryCount> Drop for KeepaliveGuard<K, V, S, C> {
+    fn drop(&mut self) {
+        if let Some(t) = self.token.take() {
+            // May free `Inner`; `keepalive` is a clone, not a field of it.
+            self.keepalive.put(t);
+        }
+    }
+}
+
+// The counted map backing an `RcHashMap`.
+type InnerMap<K, V, S, C> = CountedHashMap<K, RcVal<K, V, S, C>, S, C>;
+
+// An entry's hold on `Inner`, returned when the entry goes away.
+type KeepaliveToken<K, V, S, C> = Token<'static, RcCount<Inner<K, V, S, C>>>;
+
+// Per-entry finalizer, run with the key and value when the entry is removed.
+type Finalizer<K, V> = Box<dyn FnOnce(&K, V)>;
+
+// Per-entry timestamps, read from the map's clock.
+#[derive(Copy, Clone)]
+struct Stamps {
+    created: Duration,
+    accessed: Duration,
+}
+
+struct Inner<K, V, S, C: EntryCount> {
+    map: UnsafeCell<InnerMap<K, V, S, C>>, // interior mutability via UnsafeCell
+    // Sparse side table so entries without finalizers pay nothing.
+    finalizers: UnsafeCell<SparseSecondaryMap<DefaultKey, Finalizer<K, V>>>,
+    // Timestamp source; entries are stamped only while one is set. Stamps
+    // of removed entries are left behind and overwritten on slot reuse.
+    clock: UnsafeCell<Option<Rc<dyn Clock>>>,
+    stamps: UnsafeCell<SecondaryMap<DefaultKey, Stamps>>,
+    keepalive: RcCount<Inner<K, V, S, C>>,
+    // Keepalive tokens of force-removed entries, held until their last
+    // stale Ref drops.
+    parked: UnsafeCell<HashMap<Handle, KeepaliveToken<K, V, S, C>>>,
+    // Cleared when the owning `RcHashMap` drops; Refs may outlive it.
+    owner_alive: Cell<bool>,
+    // Live `ScanItem`s and mutable passes. While nonzero, dropping a Ref
+    // parks its handle in `deferred` instead of touching the map, so no
+    // item's borrows can dangle and no live iterator is aliased; the last
+    // section to close releases them.
+    scans: Cell<usize>,
+    // Counted handles held by the map itself for pinned entries, released
+    // when the owning `RcHashMap` drops.
+    pins: UnsafeCell<SparseSecondaryMap<DefaultKey, CountedHandle<'static, C>>>,
+    // `release_pins` for these type parameters. `RcHashMap`'s `Drop` cannot
+    // name the key and hasher bounds it needs, so it calls through this.
+    release_pins: fn(&Inner<K, V, S, C>),
+    deferred: UnsafeCell<Vec<CountedHandle<'static, C>>>,
+    id: MapId,
+    // Most Refs a lookup may leave one entry with; `usize::MAX` means
+    // uncapped.
+    max_refs: usize,
+    #[cfg(feature = "lifecycle-trace")]
+    trace: RefCell<Trace<K>>,
+}
+
+impl<K, V, S, C> Inner<K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    fn map(&self) -> &InnerMap<K, V, S, C> {
+        unsafe { &*self.map.get() }
+    }
+
+    // False if the entry behind `handle` is at the Ref cap.
+    fn admits(&self, handle: Handle) -> bool {
+        self.max_refs == usize::MAX
+            || !matches!(self.map().refcount_of(handle), Some(n) if n >= self.max_refs)
+    }
+
+    // Mint a Ref for a live entry, unless it is at the Ref cap.
+    fn mint(&self, handle: Handle) -> Option<Ref<K, V, S, C>> {
+        if !self.admits(handle) {
+            return None;
+        }
+        self.mint_uncapped(handle)
+    }
+
+    // Mint a Ref for a live entry, ignoring the Ref cap.
+    fn mint_uncapped(&self, handle: Handle) -> Option<Ref<K, V, S, C>> {
+        let ch = self.map().get_by_handle(handle)?;
+        // Wrap the handle before running the user's clock, so a panic there
+        // drops a Ref rather than a bare token.
+        let r = Ref::new(NonNull::from(self), ch);
+        self.touch(handle);
+        Some(r)
+    }
+
+    fn find<Q>(&self, q: &Q) -> Option<Ref<K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        if self.max_refs != usize::MAX {
+            return self.mint(self.map().handle_of(q)?);
+        }
+        self.map().find(q).map(|ch| {
+            let r = Ref::new(NonNull::from(self), ch);
+            self.touch(r.handle.handle);
+            r
+        })
+    }
+
+    fn clock(&self) -> Option<&Rc<dyn Clock>> {
+        unsafe { &*self.clock.get() }.as_ref()
+    }
+
+    // Stamp a newly inserted entry's creation and access times.
+    fn stamp_new(&self, handle: Handle) {
+        if let Some(clock) = self.clock() {
+            let now = clock.now();
+            let stamps = unsafe { &mut *self.stamps.get() };
+            stamps.insert(
+                handle.raw_handle(),
+                Stamps {
+                    created: now,
+                    accessed: now,
+                },
+            );
+        }
+    }
+
+    // Record a lookup of the entry behind `handle`.
+    fn touch(&self, handle: Handle) {
+        if let Some(clock) = self.clock() {
+            let stamps = unsafe { &mut *self.stamps.get() };
+            if let Some(s) = stamps.get_mut(handle.raw_handle()) {
+                s.accessed = clock.now();
+            }
+        }
+    }
+
+    // Record a freshly minted Ref with the trace.
+    #[cfg(feature = "lifecycle-trace")]
+    fn trace_mint(&self, ch: &CountedHandle<'static, C>) {
+        let map = unsafe { &*self.map.get() };
+        let refs = map.refcount(ch);
+        // A Ref minted at refcount 1 belongs to a fresh insert: every other
+        // live entry already has a Ref, or a drop parked in `deferred` while
+        // a scan section (e.g. `for_each_value_mut`) is open.
+        let op = if refs > 1 {
+            TraceOp::Get
+        } else if ch
+            .key_ref(map)
+            .is_some_and(|k| self.trace.borrow().wants(k))
+        {
+            TraceOp::Insert
+        } else {
+            return;
+        };
+        self.trace.borrow_mut().record(ch.handle, op, refs);
+    }
+
+    // Dispose of a removed entry's user data, running its finalizer if one
+    // was attached, and hand back the keepalive token. The caller returns
+    // the token, which may free `Inner`.
+    fn dispose(&self, handle: Handle, key: K, rv: RcVal<K, V, S, C>) -> KeepaliveToken<K, V, S, C>
+    where
+        K: 'static,
+        V: 'static,
+        S: 'static,
+    {
+        #[cfg(feature = "lifecycle-trace")]
+        self.trace.borrow_mut().record(handle, TraceOp::Remove, 0);
+        let RcVal {
+            value,
+            keepalive_token,
+        } = rv;
+        let mut guard = KeepaliveGuard {
+            keepalive: self.keepalive.clone(),
+            token: Some(ManuallyDrop::into_inner(keepalive_token)),
+        };
+        // Take the finalizer out before running user code, which may reenter.
+        let fin = if unsafe { &*self.finalizers.get() }.is_empty() {
+            None
+        } else {
+            unsafe { &mut *self.finalizers.get() }.remove(handle.raw_handle())
+        };
+        match fin {
+            Some(f) => f(&key, value),
+            None => drop(value),
+        }
+        drop(key);
+        guard.token.take().expect("taken only here or on unwind")
+    }
+}
+
+pub struct RcHashMap<K, V, S = DefaultHashBuilder, C: EntryCount = UsizeCount> {
+    inner: Rc<Inner<K, V, S, C>>,
+    stats: CacheStats,
+    // Most entries the map may hold; `usize::MAX` means uncapped.
+    max_entries: usize,
+}
+
+impl<K, V> RcHashMap<K, V>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+
+    /// Create a map holding at most `max_entries` entries. See
+    /// `set_max_entries`.
+    pub fn with_max_entries(max_entries: usize) -> Self {
+        let mut m = Self::new();
+        m.set_max_entries(max_entries);
+        m
+    }
+
+    /// Create a map whose lookups leave each entry with at most `max_refs`
+    /// Refs. See `with_hasher_and_max_refs`.
+    pub fn with_max_refs(max_refs: usize) -> Self {
+        Self::with_hasher_and_max_refs(DefaultHashBuilder::default(), max_refs)
+    }
+}
+
+impl<K, V> RcHashMap<K, V, IdentityHashBuilder>
+where
+    K: IntegerKey + 'static,
+    V: 'static,
+{
+    /// Create a map for integer keys (dense IDs and the like) that skips
+    /// real hashing; see `IdentityHashBuilder`.
+    pub fn new_integer_keyed() -> Self {
+        Self::with_hasher(IdentityHashBuilder)
+    }
+}
+
+impl<K, V, C> Default for RcHashMap<K, V, DefaultHashBuilder, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    C: EntryCount,
+{
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, S, C> RcHashMap<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    // Internal helpers to access the inner map via UnsafeCell in one place.
+    fn map(&self) -> &InnerMap<K, V, S, C> {
+        unsafe { &*self.inner.map.get() }
+    }
+    fn map_mut(&mut self) -> &mut InnerMap<K, V, S, C> {
+        unsafe { &mut *self.inner.map.get() }
+    }
+    #[allow(clippy::type_complexity)]
+    fn map_and_rccount_mut(&mut self) -> (&mut InnerMap<K, V, S, C>, &RcCount<Inner<K, V, S, C>>) {
+        let m = unsafe { &mut *self.inner.map.get() };
+        let rc = &self.inner.keepalive;
+        (m, rc)
+    }
+    fn pins(&self) -> &SparseSecondaryMap<DefaultKey, CountedHandle<'static, C>> {
+        unsafe { &*self.inner.pins.get() }
+    }
+    fn pins_mut(&mut self) -> &mut SparseSecondaryMap<DefaultKey, CountedHandle<'static, C>> {
+        unsafe { &mut *self.inner.pins.get() }
+    }
+    // Remove the pin on `key`'s entry, as a Ref whose drop releases it.
+    fn take_pin(&mut self, key: DefaultKey) -> Option<Ref<K, V, S, C>> {
+        let ch = self.pins_mut().remove(key)?;
+        Some(Ref::from_counted(NonNull::from(self.inner.as_ref()), ch))
+    }
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_hasher_and_max_refs(hasher, usize::MAX)
+    }
+
+    /// Create a map with `hasher` whose entries accept at most `max_refs`
+    /// Refs from lookups, e.g. to bound fan-out in a resource registry.
+    /// Once an entry's count (pins included) reaches the cap, `find` and
+    /// the other lookups return `None` for it (`find_or_default`,
+    /// `Entry::or_insert_with` and `Cache::get_or_insert_with` fail with
+    /// `CapacityExceeded`, `pin` returns false) and `Ref::try_clone`
+    /// returns `None`. `Ref::clone`, iterators and `OccupiedEntry` are
+    /// exempt and may exceed it. Panics if `max_refs` is zero.
+    pub fn with_hasher_and_max_refs(hasher: S, max_refs: usize) -> Self {
+        assert!(
+            max_refs > 0,
+            "max_refs must admit the Ref returned by insert"
+        );
+        Self {
+            inner: Rc::new_cyclic(|weak| Inner {
+                map: UnsafeCell::new(CountedHashMap::with_hasher(hasher)),
+                finalizers: UnsafeCell::new(SparseSecondaryMap::new()),
+                clock: UnsafeCell::new(None),
+                stamps: UnsafeCell::new(SecondaryMap::new()),
+                keepalive: RcCount::from_weak(weak),
+                parked: UnsafeCell::new(HashMap::new()),
+                owner_alive: Cell::new(true),
+                scans: Cell::new(0),
+                pins: UnsafeCell::new(SparseSecondaryMap::new()),
+                release_pins: release_pins::<K, V, S, C>,
+                deferred: UnsafeCell::new(Vec::new()),
+                id: MapId::fresh(),
+                max_refs,
+                #[cfg(feature = "lifecycle-trace")]
+                trace: RefCell::new(Trace::new()),
+            }),
+            stats: CacheStats::default(),
+            max_entries: usize::MAX,
+        }
+    }
+
+    /// Most entries the map may hold; `usize::MAX` unless capped.
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// The per-entry Ref cap set at construction; `usize::MAX` if uncapped.
+    pub fn max_refs(&self) -> usize {
+        self.inner.max_refs
+    }
+
+    /// Cap the map at `max_entries` entries: once full, inserting a new key
+    /// fails with `CappedInsertError::CapacityExceeded` (a present key
+    /// still reports `DuplicateKey`). A hard bound for admission control,
+    /// independent of any eviction. Lowering the cap below `len` evicts
+    /// nothing; it only blocks inserts until entries go away.
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+    }
+
+    // True unless a new `key` would exceed the cap.
+    fn admits_key(&self, key: &K) -> bool {
+        self.len() < self.max_entries || self.contains_key(key)
+    }
+
+    /// The map's hash builder.
+    pub fn hasher(&self) -> &S {
+        self.map().hasher()
+    }
+
+    /// Stamp entries with creation and last-access times from `clock`,
+    /// readable through `Ref::created_at` and `Ref::last_accessed`, e.g. to
+    /// age out entries or report cache residency. Lookups that mint a Ref
+    /// (`find` and friends) count as accesses; clones and iteration do not.
+    /// Entries inserted before a clock was set have no timestamps.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        unsafe { *self.inner.clock.get() = Some(Rc::new(clock)) };
+    }
+
+    /// Reserve room for at least `additional` more entries.
+    pub fn reserve(&mut self, additional: usize) {
+        self.map_mut().reserve(additional)
+    }
+
+    // Preallocate for a map that never holds more than `capacity` entries:
+    // slots for `capacity`, index for twice that (see `reserve_index`).
+    pub(crate) fn reserve_fixed(&mut self, capacity: usize) {
+        let m = self.map_mut();
+        m.reserve(capacity);
+        m.inner.reserve_index(capacity.saturating_mul(2));
+    }
+
+    /// This map's identity, matching `Ref::owner_id` of its Refs.
+    pub fn id(&self) -> MapId {
+        self.inner.id
+    }
+
+    /// A non-owning handle to this map, e.g. for observers that must notice
+    /// when the map is dropped. See `WeakRcHashMap`.
+    pub fn downgrade_map(&self) -> WeakRcHashMap<K, V, S, C> {
+        WeakRcHashMap {
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
+
+    /// Start tracing the entry under `key`, discarding earlier events. Its
+    /// inserts, Ref mints, Ref drops and removal are recorded from now on,
+    /// including after it is removed and re-inserted.
+    #[cfg(feature = "lifecycle-trace")]
+    pub fn trace_key(&mut self, key: K) {
+        let target = self.map().handle_of(&key);
+        self.inner.trace.borrow_mut().select(key, target);
+    }
+
+    /// Events recorded for the key selected by `trace_key`, oldest first.
+    /// At most `trace::TRACE_CAPACITY` are kept.
+    #[cfg(feature = "lifecycle-trace")]
+    pub fn trace_events(&self) -> Vec<TraceEvent> {
+        self.inner.trace.borrow().events()
+    }
+
+    fn mint(&self, handle: Handle) -> Option<Ref<K, V, S, C>> {
+        self.inner.mint(handle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map().is_empty()
+    }
+
+    /// Check every key against the hash stored at insert, panicking with
+    /// the first one mutated since (e.g. through a `Cell`): lookups can no
+    /// longer find it. O(len). Debug builds also check on each `Ref`
+    /// key/value access.
+    pub fn validate(&self)
+    where
+        K: core::fmt::Debug,
+    {
+        self.map().validate()
+    }
+
+    /// Largest `len()` reached since construction or the last reset, for
+    /// sizing caches and eviction thresholds against real peaks.
+    pub fn peak_len(&self) -> usize {
+        self.map().peak_len()
+    }
+
+    /// Restart peak tracking from the current `len()`.
+    pub fn reset_peak_len(&mut self) {
+        self.map_mut().reset_peak_len()
+    }
+
+    /// Free-slot and fragmentation statistics for the entry storage, so
+    /// long-running applications can decide when to compact or shrink.
+    pub fn slot_stats(&self) -> SlotStats {
+        self.map().slot_stats()
+    }
+
+    /// Estimated bytes allocated by the map: entry storage and hash index,
+    /// including each entry's refcount and keepalive, plus the shared `Rc`
+    /// allocation. Heap memory owned by keys and values is not counted.
+    pub fn allocated_bytes(&self) -> usize {
+        // Rc's allocation holds the strong and weak counts next to `Inner`.
+        let rc_box = core::mem::size_of::<Inner<K, V, S, C>>() + 2 * core::mem::size_of::<usize>();
+        self.map().allocated_bytes() + rc_box
+    }
+
+    pub fn contains_key<Q>(&self, q: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map().contains_key(q)
+    }
+
+    /// Insert `value` with a finalizer that receives the key and value when
+    /// this entry is removed at zero refcount, in place of dropping the
+    /// value. Entries without finalizers pay nothing for this.
+    pub fn insert_with_finalizer<F>(
+        &mut self,
+        key: K,
+        value: V,
+        finalizer: F,
+    ) -> Result<Ref<K, V, S, C>, CappedInsertError>
+    where
+        F: FnOnce(&K, V) + 'static,
+    {
+        let r = self.insert(key, value)?;
+        let fins = unsafe { &mut *self.inner.finalizers.get() };
+        fins.insert(r.handle.handle.raw_handle(), Box::new(finalizer));
+        Ok(r)
+    }
+
+    /// Insert with a constructor that borrows the stored key, e.g. to derive
+    /// the value from it. `make(&key)` only runs when the key is absent.
+    pub fn insert_with_key<F>(
+        &mut self,
+        key: K,
+        make: F,
+    ) -> Result<Ref<K, V, S, C>, CappedInsertError>
+    where
+        F: FnOnce(&K) -> V,
+    {
+        if !self.admits_key(&key) {
+            return Err(CappedInsertError::CapacityExceeded);
+        }
+        let (map, keepalive) = self.map_and_rccount_mut();
+        let ch = map.insert_with_key(key, |k| RcVal {
+            value: make(k),
+            keepalive_token: ManuallyDrop::new(keepalive.get()),
+        })?;
+        let r = Ref::new(NonNull::from(self.inner.as_ref()), ch);
+        self.inner.stamp_new(r.handle.handle);
+        Ok(r)
+    }
+
+    /// Insert using a fallible lazy constructor. `make()` only runs when the
+    /// key is absent; if it fails, nothing is inserted and no Ref is minted.
+    pub fn insert_with_result<F, E>(
+        &mut self,
+        key: K,
+        make: F,
+    ) -> Result<Ref<K, V, S, C>, TryInsertError<E>>
+    where
+        F: FnOnce() -> Result<V, E>,
+    {
+        if !self.admits_key(&key) {
+            return Err(TryInsertError::CapacityExceeded);
+        }
+        let (map, keepalive) = self.map_and_rccount_mut();
+        let ch = map.insert_with_result(key, || {
+            make().map(|value| RcVal {
+                value,
+                keepalive_token: ManuallyDrop::new(keepalive.get()),
+            })
+        })?;
+        let r = Ref::new(NonNull::from(self.inner.as_ref()), ch);
+        self.inner.stamp_new(r.handle.handle);
+        Ok(r)
+    }
+
+    /// Remove the entry for `q` now, even while Refs to it are outstanding,
+    /// and return its value (its finalizer, if any, is dropped unrun).
+    /// Outstanding Refs stay safe: their accessors return
+    /// `AccessError::Stale` and dropping them does not affect the map.
+    pub fn force_remove<Q>(&mut self, q: &Q) -> Option<V>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let h = self.map().handle_of(q)?;
+        // Drop the pin only once the entry is gone, so it cannot remove it.
+        let pin = self.take_pin(h.raw_handle());
+        let (key, rv) = self.map_mut().force_remove(h)?;
+        #[cfg(feature = "lifecycle-trace")]
+        self.inner.trace.borrow_mut().record(h, TraceOp::Remove, 0);
+        unsafe { &mut *self.inner.finalizers.get() }.remove(h.raw_handle());
+        let RcVal {
+            value,
+            keepalive_token,
+        } = rv;
+        // Every entry has a Ref outstanding, so stale Refs now exist and
+        // still point into `Inner`: keep it alive until they are gone.
+        let token = ManuallyDrop::into_inner(keepalive_token);
+        unsafe { &mut *self.inner.parked.get() }.insert(h, token);
+        drop(key);
+        drop(pin);
+        Some(value)
+    }
+
+    /// Move the entries matching `pred` that are held only by this map's
+    /// pin into a new map with the same hasher, where they stay
+    /// pinned; e.g. to re-shard a registry by tenant. Entries with external
+    /// Refs outstanding cannot be migrated and stay here. Finalizers move
+    /// with their entries.
+    pub fn split_off_if<F>(&mut self, mut pred: F) -> RcHashMap<K, V, S, C>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut out = Self::with_hasher(self.hasher().clone());
+        if let Some(clock) = self.inner.clock() {
+            unsafe { *out.inner.clock.get() = Some(clock.clone()) };
+        }
+        let movable: Vec<DefaultKey> = self
+            .pins()
+            .iter()
+            .filter(|(_, ch)| self.map().refcount(ch) == 1)
+            .filter(|(_, ch)| {
+                let k = ch.key_ref(self.map()).expect("pinned entry is live");
+                let rv = ch.value_ref(self.map()).expect("pinned entry is live");
+                pred(k, &rv.value)
+            })
+            .map(|(key, _)| key)
+            .collect();
+        for key in movable {
+            let ch = self.pins_mut().remove(key).expect("collected from pins");
+            let h = ch.handle;
+            let Ok((k, rv)) = self.map_mut().try_remove_handle(ch) else {
+                unreachable!("the pin was the only Ref");
+            };
+            #[cfg(feature = "lifecycle-trace")]
+            self.inner.trace.borrow_mut().record(h, TraceOp::Remove, 0);
+            let fin = unsafe { &mut *self.inner.finalizers.get() }.remove(h.raw_handle());
+            let RcVal {
+                value,
+                keepalive_token,
+            } = rv;
+            // `self` still holds `Inner`, so this cannot free it.
+            self.inner
+                .keepalive
+                .put(ManuallyDrop::into_inner(keepalive_token));
+            let r = out.insert(k, value).expect("keys are unique");
+            let new_key = r.handle.handle.raw_handle();
+            if let Some(f) = fin {
+                unsafe { &mut *out.inner.finalizers.get() }.insert(new_key, f);
+            }
+            if let Some(&s) = unsafe { &*self.inner.stamps.get() }.get(h.raw_handle()) {
+                unsafe { &mut *out.inner.stamps.get() }.insert(new_key, s);
+            }
+            out.pins_mut().insert(new_key, r.into_counted());
+        }
+        out
+    }
+
+    /// Have the map hold a Ref to the entry for `q`, so it persists without
+    /// external Refs until `unpin` (registry semantics). Returns false if
+    /// `q` is absent or already pinned.
+    pub fn pin<Q>(&mut self, q: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let Some(r) = self.find(q) else {
+            return false;
+        };
+        let key = r.handle.handle.raw_handle();
+        if self.pins().contains_key(key) {
+            return false;
+        }
+        self.pins_mut().insert(key, r.into_counted());
+        true
+    }
+
+    /// Release the map's own Ref to the entry for `q`. The entry is removed
+    /// if no external Refs remain. Returns false if it was not pinned.
+    pub fn unpin<Q>(&mut self, q: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let Some(h) = self.map().handle_of(q) else {
+            return false;
+        };
+        // Dropping the Ref may remove the entry; nothing else is borrowed.
+        self.take_pin(h.raw_handle()).is_some()
+    }
+
+    // Handle-level pin bookkeeping for wrappers such as `EvictingCache`.
+    pub(crate) fn handle_of<Q>(&self, q: &Q) -> Option<Handle>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map().handle_of(q)
+    }
+    pub(crate) fn pin_ref(&mut self, r: &Ref<K, V, S, C>) -> bool {
+        let key = r.handle.handle.raw_handle();
+        if self.pins().contains_key(key) {
+            return false;
+        }
+        self.pins_mut().insert(key, r.clone().into_counted());
+        true
+    }
+    pub(crate) fn unpin_handle(&mut self, h: Handle) -> bool {
+        self.take_pin(h.raw_handle()).is_some()
+    }
+    // True if the map's pin is the only Ref to the entry behind `h`.
+    pub(crate) fn is_pin_only(&self, h: Handle) -> bool {
+        self.pins().contains_key(h.raw_handle()) && self.map().refcount_of(h) == Some(1)
+    }
+
+    /// Returns true if the entry for `q` is pinned by the map.
+    pub fn is_pinned<Q>(&self, q: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map()
+            .handle_of(q)
+            .is_some_and(|h| self.pins().contains_key(h.raw_handle()))
+    }
+
+    /// Clone the contents into a std `HashMap`, for APIs that expect std
+    /// collections.
+    pub fn to_hash_map(&self) -> std::collections::HashMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.map()
+            .iter()
+            .map(|(_, k, rv)| (k.clone(), rv.value.clone()))
+            .collect()
+    }
+
+    /// Clone the contents into a `Vec` of pairs, in iteration order.
+    pub fn to_vec(&self) -> Vec<(K, V)>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut out = Vec::with_capacity(self.len());
+        out.extend(
+            self.map()
+                .iter()
+                .map(|(_, k, rv)| (k.clone(), rv.value.clone())),
+        );
+        out
+    }
+
+    /// Compare keys with `other`: which are only here and which only there.
+    /// `Diff::changed` stays empty; see `diff_values`.
+    pub fn diff<S2, C2>(&self, other: &RcHashMap<K, V, S2, C2>) -> Diff<K>
+    where
+        K: Clone,
+        S2: core::hash::BuildHasher + Clone + Default + 'static,
+        C2: EntryCount,
+    {
+        self.diff_by(other, |_, _| false)
+    }
+
+    /// Like `diff`, and also report keys present in both maps whose values
+    /// differ, e.g. to compute the delta between old and new registries.
+    pub fn diff_values<S2, C2>(&self, other: &RcHashMap<K, V, S2, C2>) -> Diff<K>
+    where
+        K: Clone,
+        V: PartialEq,
+        S2: core::hash::BuildHasher + Clone + Default + 'static,
+        C2: EntryCount,
+    {
+        self.diff_by(other, |a, b| a != b)
+    }
+
+    fn diff_by<S2, C2, F>(&self, other: &RcHashMap<K, V, S2, C2>, differs: F) -> Diff<K>
+    where
+        K: Clone,
+        S2: core::hash::BuildHasher + Clone + Default + 'static,
+        C2: EntryCount,
+        F: Fn(&V, &V) -> bool,
+    {
+        let mut d = Diff::default();
+        for (_, k, rv) in self.map().iter() {
+            match other.map().get_value(k) {
+                None => d.only_in_self.push(k.clone()),
+                Some(orv) if differs(&rv.value, &orv.value) => d.changed.push(k.clone()),
+                Some(_) => {}
+            }
+        }
+        for (_, k, _) in other.map().iter() {
+            if !self.contains_key(k) {
+                d.only_in_other.push(k.clone());
+            }
+        }
+        d
+    }
+
+    /// Returns true if `r` was issued by this map, so its accessors will
+    /// accept this map. A pointer comparison; the entry is not looked up.
+    pub fn is_owner(&self, r: &Ref<K, V, S, C>) -> bool {
+        r.check_owner(self).is_ok()
+    }
+
+    /// Returns true if `r` was issued by this map and its entry still
+    /// resolves. Does not borrow the value or touch counts.
+    pub fn contains_ref(&self, r: &Ref<K, V, S, C>) -> bool {
+        self.is_owner(r) && r.handle.value_ref(self.map()).is_some()
+    }
+
+    /// Returns true if `handle` still refers to a live entry, without
+    /// minting a Ref. Like `upgrade`, this cannot tell which map the handle
+    /// came from; use `contains_ref` when owner identity matters.
+    pub fn contains_handle(&self, handle: Handle) -> bool {
+        self.map().contains_handle(handle)
+    }
+
+    /// A Ref to the entry behind `handle` (see `Ref::handle`), if it still
+    /// exists. A stale handle never resolves, even
+    /// after its slot is reused; handles carry no owner, so one from another
+    /// map may resolve to an unrelated entry (`WeakRef` checks the owner).
+    pub fn upgrade(&self, handle: Handle) -> Option<Ref<K, V, S, C>> {
+        self.mint(handle)
+    }
+
+    /// Number of Refs to `r`'s entry, `r` and pins included, validating
+    /// owner identity.
+    pub fn ref_count(&self, r: &Ref<K, V, S, C>) -> Result<usize, AccessError> {
+        r.check_owner(self)?;
+        self.map()
+            .refcount_of(r.handle.handle)
+            .ok_or(AccessError::Stale)
+    }
+
+    /// Number of Refs to `q`'s entry (pins included), or `None` if absent.
+    /// Mints no Ref, so the count is not disturbed.
+    pub fn ref_count_of<Q>(&self, q: &Q) -> Option<usize>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let map = self.map();
+        map.refcount_of(map.handle_of(q)?)
+    }
+
+    /// Fails with `CapacityExceeded` if `key` is absent and the map is at
+    /// its `max_entries` cap.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Ref<K, V, S, C>, CappedInsertError> {
+        if !self.admits_key(&key) {
+            return Err(CappedInsertError::CapacityExceeded);
+        }
+        let (map, keepalive) = self.map_and_rccount_mut();
+        let res = map.insert_with(key, || RcVal {
+            value,
+            keepalive_token: ManuallyDrop::new(keepalive.get()),
+        });
+        match res {
+            Ok(ch) => {
+                let r = Ref::new(NonNull::from(self.inner.as_ref()), ch);
+                self.inner.stamp_new(r.handle.handle);
+                Ok(r)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn find<Q>(&self, q: &Q) -> Option<Ref<K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.inner.find(q)
+    }
+
+    /// Mint a Ref to `key`'s entry, inserting `V::default()` if absent; the
+    /// analog of `HashMap::entry(key).or_default()` for accumulation
+    /// patterns. Fails with `CapacityExceeded` if `key` is absent while the
+    /// map is at its entry cap, or present but at its Ref cap.
+    pub fn find_or_default(&mut self, key: K) -> Result<Ref<K, V, S, C>, CappedInsertError>
+    where
+        V: Default,
+    {
+        if let Some(h) = self.map().handle_of(&key) {
+            return self.mint(h).ok_or(CappedInsertError::CapacityExceeded);
+        }
+        self.insert(key, V::default())
+    }
+
+    /// Look up `key` once and return an `Entry` that yields a Ref to the
+    /// existing entry or inserts a new one, without hashing `key` again.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, S, C> {
+        let hash = self.map().inner.make_hash(&key);
+        match self.map().inner.find_hashed(hash, &key) {
+            Some(h) => Entry::Occupied(OccupiedEntry {
+                r: self.inner.mint_uncapped(h).expect("key was just looked up"),
+                map: self,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                hash,
+            }),
+        }
+    }
+
+    /// Resolve a batch of keys, e.g. the dozens a request handler needs,
+    /// with one guard entry and one allocation. Results are in input order.
+    pub fn find_many<'q, Q, I>(&self, keys: I) -> Vec<Option<Ref<K, V, S, C>>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq + 'q,
+        I: IntoIterator<Item = &'q Q>,
+    {
+        if self.inner.max_refs != usize::MAX {
+            return keys.into_iter().map(|q| self.inner.find(q)).collect();
+        }
+        let owner_ptr = NonNull::from(self.inner.as_ref());
+        self.map()
+            .find_many(keys)
+            .into_iter()
+            .map(|ch| ch.map(|ch| Ref::new(owner_ptr, ch)))
+            .collect()
+    }
+
+    /// Mint a Ref to some live entry, without hashing, for work-stealing
+    /// or drain loops ("process any remaining item"). O(1); see
+    /// `HandleHashMap::any_handle`.
+    pub fn any_entry(&self) -> Option<Ref<K, V, S, C>> {
+        self.mint(self.map().any_handle()?)
+    }
+
+    /// Mint a Ref to the entry at position `i`, for list-style UIs and
+    /// pagination. Positions run `0..len` in insertion order; a removal
+    /// moves the last entry into the freed position. See
+    /// `HandleHashMap::get_index`. O(1); `None` past the end.
+    pub fn get_index(&self, i: usize) -> Option<Ref<K, V, S, C>> {
+        self.mint(self.map().get_index(i)?)
+    }
+
+    /// Position of `q`'s entry; see `get_index`.
+    pub fn get_index_of<Q>(&self, q: &Q) -> Option<usize>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map().get_index_of(q)
+    }
+
+    /// Mint a Ref to the first entry, in iteration order, matching `pred`;
+    /// a linear scan for occasional value-based lookups (e.g. the session
+    /// bound to a socket) not worth a secondary index.
+    pub fn find_by<F>(&self, mut pred: F) -> Option<Ref<K, V, S, C>>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.scan()
+            .filter(|it| pred(it.key(), it.value()))
+            .find_map(|it| self.mint(it.handle))
+    }
+
+    /// Like `find_by`, but mints a Ref to every matching entry.
+    pub fn find_all_by<F>(&self, mut pred: F) -> Vec<Ref<K, V, S, C>>
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.scan()
+            .filter(|it| pred(it.key(), it.value()))
+            .filter_map(|it| self.mint(it.handle))
+            .collect()
+    }
+
+    /// Borrow the value for `q` without minting a Ref: no refcount traffic
+    /// and no access stamp, for hot read paths. The returned `ScanItem`
+    /// derefs to the value and, like `scan`'s items, defers the entry's
+    /// removal while alive, so the borrow cannot dangle.
+    pub fn peek<Q>(&self, q: &Q) -> Option<ScanItem<'_, K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let map = self.map();
+        let h = map.handle_of(q)?;
+        let k = map.inner.handle_key(h)?;
+        let rv = map.inner.handle_value(h)?;
+        Some(ScanItem::new(&self.inner, h, k, &rv.value.value))
+    }
+
+    /// Mutable counterpart of `peek`; the returned `PeekMut` derefs to the
+    /// value.
+    pub fn peek_mut<Q>(&mut self, q: &Q) -> Option<PeekMut<'_, K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let inner: &Inner<K, V, S, C> = &self.inner;
+        let map = unsafe { &mut *inner.map.get() };
+        let rv = map.get_value_mut(q)?;
+        inner.scans.set(inner.scans.get() + 1);
+        Some(PeekMut {
+            inner,
+            v: &mut rv.value,
+        })
+    }
+
+    /// Look up `q` and return both a Ref and mutable access to the value,
+    /// with a single probe and no owner check.
+    #[allow(clippy::type_complexity)]
+    pub fn find_mut<Q>(&mut self, q: &Q) -> Option<(Ref<K, V, S, C>, &mut V)>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        if self.inner.max_refs != usize::MAX && !self.inner.admits(self.map().handle_of(q)?) {
+            return None;
+        }
+        let inner: &Inner<K, V, S, C> = &self.inner;
+        let owner_ptr = NonNull::from(inner);
+        let map = unsafe { &mut *inner.map.get() };
+        map.find_mut(q).map(|(ch, rcv)| {
+            let r = Ref::new(owner_ptr, ch);
+            inner.touch(r.handle.handle);
+            (r, &mut rcv.value)
+        })
+    }
+
+    /// Visit every entry mutably without minting a `Ref` per item; the
+    /// fastest path for whole-map update passes. Refs dropped inside `f` are
+    /// honored: an entry whose last Ref goes away is removed after the pass.
+    pub fn for_each_value_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V),
+    {
+        let inner: &Inner<K, V, S, C> = &self.inner;
+        // Refs dropped by `f` defer into `Inner::deferred` rather than touch
+        // the map under the live iterator; they are released when the
+        // section closes, after the pass or while unwinding from `f`.
+        let _section = ScanSection::new(inner);
+        let map = unsafe { &mut *inner.map.get() };
+        for (_, k, c) in map.inner.iter_mut() {
+            f(k, &mut c.value.value);
+        }
+    }
+
+    /// Drop a batch of Refs, returning all their counts before unlinking the
+    /// entries that reached zero in one pass. Faster than dropping each Ref
+    /// individually. Refs from another map are dropped normally, after the
+    /// batch.
+    pub fn drop_refs<I>(&mut self, refs: I)
+    where
+        I: IntoIterator<Item = Ref<K, V, S, C>>,
+    {
+        // The batch path reports no per-Ref counts, so trace one at a time.
+        #[cfg(feature = "lifecycle-trace")]
+        if self.inner.trace.borrow().is_active() {
+            refs.into_iter().for_each(drop);
+            return;
+        }
+        let owner_ptr = NonNull::from(self.inner.as_ref());
+        let parked = unsafe { &*self.inner.parked.get() };
+        let mut stale = Vec::new();
+        // Foreign values may hold Refs into this map, so their drops must
+        // wait until `put_many_with` releases its borrow.
+        let mut foreign = Vec::new();
+        let handles = refs.into_iter().filter_map(|r| {
+            if r.owner_ptr != owner_ptr {
+                foreign.push(r);
+                return None;
+            }
+            // Stale Refs may release a parked keepalive; drop them after.
+            if parked.contains_key(&r.handle.handle) {
+                stale.push(r);
+                return None;
+            }
+            let mut r = ManuallyDrop::new(r);
+            // Safety: `r` is never used or dropped again.
+            Some(unsafe { ManuallyDrop::take(&mut r.handle) })
+        });
+        let inner: &Inner<K, V, S, C> = &self.inner;
+        let map = unsafe { &mut *inner.map.get() };
+        let mut dead = Vec::new();
+        map.put_many_with(handles, |h, key, rv| dead.push((h, key, rv)));
+        // The structure is consistent again; user drops may now reenter.
+        for (h, key, rv) in dead {
+            let token = inner.dispose(h, key, rv);
+            // `self` still holds `Inner`, so this cannot free it.
+            inner.keepalive.put(token);
+        }
+        drop(stale);
+        drop(foreign);
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, S, C> {
+        let owner_ptr = NonNull::from(self.inner.as_ref());
+        let inner = self.map().iter_raw();
+        Iter { owner_ptr, inner }
+    }
+
+    /// Like `iter`, but each `Item` also borrows the entry's key and value,
+    /// saving the per-item `Ref::key`/`Ref::value` lookup. The borrows are
+    /// tied to the item, whose Ref keeps the entry alive.
+    pub fn entries(&self) -> Entries<'_, K, V, S, C> {
+        let owner_ptr = NonNull::from(self.inner.as_ref());
+        let inner = self.map().iter_raw();
+        Entries { owner_ptr, inner }
+    }
+
+    /// Like `entries`, but mints no Refs: items borrow the key and value and
+    /// mint a Ref only on `ScanItem::to_ref`, so scan-only passes leave every
+    /// entry's count untouched. While any item is alive, an entry whose last
+    /// Ref drops stays in place until the items are gone.
+    pub fn scan(&self) -> Scan<'_, K, V, S, C> {
+        Scan {
+            inner: &self.inner,
+            it: self.map().iter_raw(),
+        }
+    }
+
+    /// Entries with their Ref counts (pins included), most referenced
+    /// first, e.g. for a "hottest entries" debug view or to decide what to
+    /// pin. Mints no Refs, so the counts are not disturbed.
+    pub fn iter_by_refcount(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.map()
+            .iter_by_refcount()
+            .map(|(_, k, rv, n)| (k, &rv.value, n))
+    }
+
+    /// Live entries with their current Ref counts (pins included), in
+    /// iteration order, e.g. for a debug dashboard of what keeps the map
+    /// populated. Mints no Refs; each `ScanItem` borrows the key and value
+    /// and keeps its entry in place while alive, as with `scan`.
+    #[allow(clippy::type_complexity)]
+    pub fn iter_counts(&self) -> impl Iterator<Item = (Handle, ScanItem<'_, K, V, S, C>, usize)> {
+        self.scan().map(|it| {
+            let n = self
+                .map()
+                .refcount_of(it.handle)
+                .expect("scanned entry stays live while its item exists");
+            (it.handle, it, n)
+        })
+    }
+
+    /// Entries whose current Ref count (pins included) satisfies `pred`,
+    /// e.g. `|n| n == 1` for uniquely held entries or `|n| n > 8` for
+    /// heavily shared ones. Mints no Refs; items behave as in `scan`.
+    pub fn iter_where_count<F>(&self, mut pred: F) -> impl Iterator<Item = ScanItem<'_, K, V, S, C>>
+    where
+        F: FnMut(usize) -> bool,
+    {
+        self.iter_counts()
+            .filter(move |(_, _, n)| pred(*n))
+            .map(|(_, it, _)| it)
+    }
+
+    /// Iterate over a snapshot of the live entries: handles are collected
+    /// up front and each is re-resolved when reached, so Refs may be dropped
+    /// freely mid-iteration. Entries removed before they are reached are
+    /// skipped; entries inserted after the snapshot are not visited.
+    pub fn snapshot_iter(&self) -> SnapshotIter<'_, K, V, S, C> {
+        let handles: Vec<_> = self.map().iter().map(|(h, _, _)| h).collect();
+        SnapshotIter {
+            map: self,
+            handles: handles.into_iter(),
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, S, C> {
+        let owner_ptr = NonNull::from(self.inner.as_ref());
+        let inner = self.map_mut().iter_mut_raw();
+        IterMut { owner_ptr, inner }
+    }
+}
+
+/// Typed access for maps of heterogeneous values, e.g. plugin registries.
+impl<K, S, C> RcHashMap<K, Box<dyn Any>, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// Box `value` and insert it under `key`.
+    pub fn insert_any<T: 'static>(
+        &mut self,
+        key: K,
+        value: T,
+    ) -> Result<Ref<K, Box<dyn Any>, S, C>, CappedInsertError> {
+        self.insert(key, Box::new(value))
+    }
+
+    /// Look up `q` and, if its value is a `T`, mint a Ref typed as it.
+    /// The value may later be replaced with another type through
+    /// `Ref::value_mut`; `TypedRef::get` checks the type on every access.
+    pub fn get_as<T: 'static, Q>(&self, q: &Q) -> Option<TypedRef<K, T, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        let r = self.find(q)?;
+        if !r.value(self).ok()?.is::<T>() {
+            return None;
+        }
+        Some(TypedRef {
+            r,
+            _ty: PhantomData,
+        })
+    }
+}
+
+impl<K, V, S, C> Cache<K, V> for RcHashMap<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    type Ref = Ref<K, V, S, C>;
+
+    fn get_or_insert_with<F>(&mut self, key: K, f: F) -> Result<Ref<K, V, S, C>, CappedInsertError>
+    where
+        F: FnOnce() -> V,
+    {
+        if let Some(h) = self.map().handle_of(&key) {
+            self.stats.hits += 1;
+            return self.mint(h).ok_or(CappedInsertError::CapacityExceeded);
+        }
+        self.stats.misses += 1;
+        self.insert(key, f())
+    }
+
+    /// Release the map's pin on `key`, if any (see `pin`); the entry goes
+    /// away once no outside Refs remain.
+    fn invalidate<Q>(&mut self, key: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.unpin(key);
+        !self.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        RcHashMap::len(self)
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+/// Key-level difference between two maps, from `RcHashMap::diff` or
+/// `diff_values`. Keys are listed in each map's iteration order.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diff<K> {
+    pub only_in_self: Vec<K>,
+    pub only_in_other: Vec<K>,
+    /// Keys in both maps with differing values (`diff_values` only).
+    pub changed: Vec<K>,
+}
+
+impl<K> Default for Diff<K> {
+    fn default() -> Self {
+        Self {
+            only_in_self: Vec::new(),
+            only_in_other: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+}
+
+impl<K> Diff<K> {
+    /// True if the maps have the same keys (and values, for `diff_values`).
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<K, V, S, C: EntryCount> Drop for RcHashMap<K, V, S, C> {
+    fn drop(&mut self) {
+        self.inner.owner_alive.set(false);
+        (self.inner.release_pins)(&self.inner);
+    }
+}
+
+// Drop the map's pins. `RcHashMap` still holds `Inner`, so this cannot
+// free it.
+fn release_pins<K, V, S, C>(inner: &Inner<K, V, S, C>)
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    let pins = core::mem::take(unsafe { &mut *inner.pins.get() });
+    let owner_ptr = NonNull::from(inner);
+    // Wrap them all before dropping any, as in `end_scan`.
+    let refs: Vec<_> = pins
+        .into_iter()
+        .map(|(_, ch)| Ref::from_counted(owner_ptr, ch))
+        .collect();
+    drop(refs);
+}
+
+// Unwind safety. The auto traits are lost to the `UnsafeCell`s in `Inner`,
+// but a panic from user code (`K: Hash/Eq`, `V: Drop`, finalizers,
+// `for_each_value_mut` closures) never leaves the map half-updated:
+// removal completes before user data is dropped, and tokens held across
+// user code are returned on unwind. So these types are unwind safe when
+// the user types are. `IterMut` and `ItemMut` stay `!UnwindSafe`, like
+// any `&mut`: a panic may leave a value partly written.
+macro_rules! unwind_safe {
+    ($($ty:ident$(<$lt:lifetime>)?),*) => {$(
+        impl<$($lt,)? K, V, S, C> UnwindSafe for $ty<$($lt,)? K, V, S, C>
+        where
+            K: Eq + core::hash::Hash + RefUnwindSafe + 'static,
+            V: RefUnwindSafe + 'static,
+            S: core::hash::BuildHasher + Clone + Default + RefUnwindSafe + 'static,
+            C: EntryCount,
+        {
+        }
+        impl<$($lt,)? K, V, S, C> RefUnwindSafe for $ty<$($lt,)? K, V, S, C>
+        where
+            K: Eq + core::hash::Hash + RefUnwindSafe + 'static,
+            V: RefUnwindSafe + 'static,
+            S: core::hash::BuildHasher + Clone + Default + RefUnwindSafe + 'static,
+            C: EntryCount,
+        {
+        }
+    )*};
+}
+unwind_safe!(
+    RcHashMap,
+    Ref,
+    Iter<'a>,
+    Entries<'a>,
+    Scan<'a>,
+    SnapshotIter<'a>
+);
+
+impl<K, V, T: ?Sized, S, C> UnwindSafe for MappedRef<K, V, T, S, C>
+where
+    K: Eq + core::hash::Hash + RefUnwindSafe + 'static,
+    V: RefUnwindSafe + 'static,
+    S: core::hash::BuildHasher + Clone + Default + RefUnwindSafe + 'static,
+    C: EntryCount,
+{
+}
+impl<K, V, T: ?Sized, S, C> RefUnwindSafe for MappedRef<K, V, T, S, C>
+where
+    K: Eq + core::hash::Hash + RefUnwindSafe + 'static,
+    V: RefUnwindSafe + 'static,
+    S: core::hash::BuildHasher + Clone + Default + RefUnwindSafe + 'static,
+    C: EntryCount,
+{
+}
+
+/// Non-owning handle to an `RcHashMap`, from `RcHashMap::downgrade_map`.
+/// It does not keep the map alive: once the owning `RcHashMap` is dropped,
+/// `upgrade` fails, even while Refs to its entries are still outstanding.
+pub struct WeakRcHashMap<K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    inner: Weak<Inner<K, V, S, C>>,
+}
+
+impl<K, V, S, C> WeakRcHashMap<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// Returns true while the owning `RcHashMap` exists.
+    pub fn is_alive(&self) -> bool {
+        self.inner
+            .upgrade()
+            .is_some_and(|inner| inner.owner_alive.get())
+    }
+
+    /// Observe the map if its owner still exists. The observer keeps the
+    /// map's storage alive like a Ref does, but not the owner.
+    pub fn upgrade(&self) -> Option<MapObserver<K, V, S, C>> {
+        let inner = self.inner.upgrade()?;
+        inner.owner_alive.get().then_some(MapObserver { inner })
+    }
+}
+
+impl<K, V, S, C> Clone for WeakRcHashMap<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Read-only view of an `RcHashMap` from `WeakRcHashMap::upgrade`. It can
+/// look entries up and mint Refs to them; reading through those Refs still
+/// goes through the owning map.
+pub struct MapObserver<K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    inner: Rc<Inner<K, V, S, C>>,
+}
+
+impl<K, V, S, C> MapObserver<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// Returns true while the owning `RcHashMap` exists.
+    pub fn is_alive(&self) -> bool {
+        self.inner.owner_alive.get()
+    }
+
+    /// The observed map's `RcHashMap::id`.
+    pub fn id(&self) -> MapId {
+        self.inner.id
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.map().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.map().is_empty()
+    }
+
+    pub fn contains_key<Q>(&self, q: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.inner.map().contains_key(q)
+    }
+
+    /// Like `RcHashMap::find`.
+    pub fn find<Q>(&self, q: &Q) -> Option<Ref<K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.inner.find(q)
+    }
+}
+
+/// A reference to an entry inside RcHashMap. Clone increments per-entry count;
+/// dropping decrements and removes the entry when it reaches zero.
+pub struct Ref<K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    owner_ptr: NonNull<Inner<K, V, S, C>>,
+    handle: ManuallyDrop<CountedHandle<'static, C>>,
+    _nosend: PhantomData<*mut ()>,
+}
+
+/// Opaque identity of an `RcHashMap` instance, unique within the process
+/// and never reused, for correlating Refs with maps in logs, metrics or
+/// routing tables.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct MapId(u64);
+
+impl MapId {
+    fn fresh() -> Self {
+        use core::sync::atomic::{AtomicU64, Ordering};
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        MapId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Error from Ref accessors.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccessError {
+    /// The Ref was issued by a different map: a caller bug.
+    WrongOwner,
+    /// The Ref belongs to this map but its entry no longer resolves.
+    Stale,
+}
+
+impl<K, V, S, C> Ref<K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    fn new(owner_ptr: NonNull<Inner<K, V, S, C>>, handle: CountedHandle<'static, C>) -> Self {
+        #[cfg(feature = "lifecycle-trace")]
+        unsafe { owner_ptr.as_ref() }.trace_mint(&handle);
+        Self {
+            owner_ptr,
+            handle: ManuallyDrop::new(handle),
+            _nosend: PhantomData,
+        }
+    }
+
+    // Adopt a counted handle whose token was taken out of a Ref (see
+    // `into_counted`) without recording a new mint.
+    fn from_counted(owner_ptr: NonNull<Inner<K, V, S, C>>, ch: CountedHandle<'static, C>) -> Self {
+        Self {
+            owner_ptr,
+            handle: ManuallyDrop::new(ch),
+            _nosend: PhantomData,
+        }
+    }
+
+    // Give up the Ref's counted handle without returning its token.
+    fn into_counted(self) -> CountedHandle<'static, C> {
+        let mut this = ManuallyDrop::new(self);
+        // Safety: `this` is never used or dropped again.
+        unsafe { ManuallyDrop::take(&mut this.handle) }
+    }
+
+    #[inline]
+    fn check_owner<'a>(&'a self, map: &'a RcHashMap<K, V, S, C>) -> Result<(), AccessError> {
+        // Safety: owner_ptr is created from Rc::as_ref; compare raw pointers for identity.
+        let ptr = NonNull::from(map.inner.as_ref());
+        if ptr == self.owner_ptr {
+            Ok(())
+        } else {
+            Err(AccessError::WrongOwner)
+        }
+    }
+
+    /// The entry's plain `Handle`: `Copy`, and holds no liveness, for side
+    /// tables and FFI structs. Re-acquire a Ref with `RcHashMap::upgrade`.
+    pub fn handle(&self) -> Handle {
+        self.handle.handle
+    }
+
+    /// Identity of the map that issued this Ref; see `RcHashMap::id`.
+    pub fn owner_id(&self) -> MapId {
+        // Safety: the Ref keeps `Inner` alive.
+        unsafe { self.owner_ptr.as_ref() }.id
+    }
+
+    /// Borrow the entry's key, validating owner identity.
+    pub fn key<'a>(&'a self, map: &'a RcHashMap<K, V, S, C>) -> Result<&'a K, AccessError> {
+        self.check_owner(map)?;
+        self.handle.key_ref(map.map()).ok_or(AccessError::Stale)
+    }
+
+    /// Borrow the entry's value, validating owner identity.
+    pub fn value<'a>(&'a self, map: &'a RcHashMap<K, V, S, C>) -> Result<&'a V, AccessError> {
+        self.check_owner(map)?;
+        self.handle
+            .value_ref(map.map())
+            .map(|rcv| &rcv.value)
+            .ok_or(AccessError::Stale)
+    }
+
+    /// Mutably borrow the entry's value, validating owner identity.
+    pub fn value_mut<'a>(
+        &'a self,
+        map: &'a mut RcHashMap<K, V, S, C>,
+    ) -> Result<&'a mut V, AccessError> {
+        if NonNull::from(map.inner.as_ref()) != self.owner_ptr {
+            return Err(AccessError::WrongOwner);
+        }
+        // SAFETY: owner validated and we have &mut map, so exclusive access for 'a
+        self.check_owner(map)?; // ensure owner match
+        self.handle
+            .value_mut(map.map_mut())
+            .map(|rcv| &mut rcv.value)
+            .ok_or(AccessError::Stale)
+    }
+
+    // The entry's timestamps, if it was inserted while the map had a clock.
+    fn stamps(&self, map: &RcHashMap<K, V, S, C>) -> Result<Option<Stamps>, AccessError> {
+        self.key(map)?;
+        let stamps = unsafe { &*map.inner.stamps.get() };
+        Ok(stamps.get(self.handle.handle.raw_handle()).copied())
+    }
+
+    /// When the entry was inserted, by the map's clock (see
+    /// `RcHashMap::set_clock`), validating owner identity.
+    pub fn created_at(&self, map: &RcHashMap<K, V, S, C>) -> Result<Option<Duration>, AccessError> {
+        Ok(self.stamps(map)?.map(|s| s.created))
+    }
+
+    /// When a lookup last minted a Ref to the entry (or its insertion, if
+    /// none has), by the map's clock; validates owner identity.
+    pub fn last_accessed(
+        &self,
+        map: &RcHashMap<K, V, S, C>,
+    ) -> Result<Option<Duration>, AccessError> {
+        Ok(self.stamps(map)?.map(|s| s.accessed))
+    }
+
+    /// Maximum number of simultaneous Refs this entry has had, validating
+    /// owner identity. `Ok(None)` unless the map counts entries with a
+    /// peak-tracking counter such as `tokens::HighWaterCount`.
+    pub fn max_ref_count(&self, map: &RcHashMap<K, V, S, C>) -> Result<Option<usize>, AccessError> {
+        self.check_owner(map)?;
+        Ok(map.map().high_water(&self.handle))
+    }
+
+    /// True if this is the entry's only Ref (a pin counts as one), e.g. to
+    /// mutate in place only when no one else can observe it. Validates
+    /// owner identity.
+    pub fn is_unique(&self, map: &RcHashMap<K, V, S, C>) -> Result<bool, AccessError> {
+        Ok(map.ref_count(self)? == 1)
+    }
+
+    /// Remove the entry and return its key and value if this is its only
+    /// Ref (a pin counts as one), like `Rc::try_unwrap`. Otherwise, or if
+    /// the Ref is stale or from another map, gives it back. A finalizer
+    /// attached to the entry is discarded unrun, since the value is handed
+    /// out.
+    pub fn try_unwrap(self, map: &mut RcHashMap<K, V, S, C>) -> Result<(K, V), Self>
+    where
+        K: 'static,
+        V: 'static,
+        S: 'static,
+    {
+        if self.check_owner(map).is_err() || map.map().refcount_of(self.handle.handle) != Some(1) {
+            return Err(self);
+        }
+        let mut this = ManuallyDrop::new(self);
+        // Safety: `this` is never used or dropped again.
+        let ch = unsafe { ManuallyDrop::take(&mut this.handle) };
+        let h = ch.handle;
+        let Ok((key, rv)) = map.map_mut().try_remove_handle(ch) else {
+            unreachable!("this was the only Ref");
+        };
+        #[cfg(feature = "lifecycle-trace")]
+        map.inner.trace.borrow_mut().record(h, TraceOp::Remove, 0);
+        unsafe { &mut *map.inner.finalizers.get() }.remove(h.raw_handle());
+        let RcVal {
+            value,
+            keepalive_token,
+        } = rv;
+        // `map` still holds `Inner`, so this cannot free it.
+        map.inner
+            .keepalive
+            .put(ManuallyDrop::into_inner(keepalive_token));
+        Ok((key, value))
+    }
+
+    /// A `WeakRef` to this entry, which does not keep it alive.
+    pub fn downgrade(&self) -> WeakRef<K, V, S, C> {
+        WeakRef {
+            owner: self.owner_id(),
+            handle: self.handle.handle,
+            _pd: PhantomData,
+        }
+    }
+
+    /// Clone this Ref, or return `None` if its entry is already at the
+    /// map's Ref cap. `Clone` ignores the cap.
+    pub fn try_clone(&self) -> Option<Self> {
+        let inner = unsafe { self.owner_ptr.as_ref() };
+        inner.admits(self.handle.handle).then(|| self.clone())
+    }
+
+    /// Project this Ref onto part of its value, like `cell::Ref::map`. The
+    /// result keeps the entry alive but only exposes the projected field.
+    /// `f` may capture state; it is shared by clones and rerun on every
+    /// `get`, since the value can be replaced through `value_mut`.
+    pub fn map_value<T: ?Sized, F>(self, f: F) -> MappedRef<K, V, T, S, C>
+    where
+        K: 'static,
+        V: 'static,
+        S: 'static,
+        F: for<'v> Fn(&'v V) -> &'v T + 'static,
+    {
+        MappedRef {
+            r: self,
+            project: Rc::new(f),
+        }
+    }
+}
+
+/// A view of a single key in an `RcHashMap`, from `RcHashMap::entry`.
+pub enum Entry<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    Occupied(OccupiedEntry<'a, K, V, S, C>),
+    Vacant(VacantEntry<'a, K, V, S, C>),
+}
+
+impl<'a, K, V, S, C> Entry<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(o) => o.key(),
+            Entry::Vacant(v) => v.key(),
+        }
+    }
+
+    /// A Ref to the existing entry, or to a new one holding `default()`.
+    /// Fails if the key is absent and the map is at its entry cap, or
+    /// present and at its Ref cap.
+    pub fn or_insert_with<F>(self, default: F) -> Result<Ref<K, V, S, C>, CappedInsertError>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(o) => {
+                // The view's own Ref is the one handed out, so it may bring
+                // the count up to the cap but not past it.
+                let n = o.map.map().refcount_of(o.r.handle.handle);
+                if matches!(n, Some(n) if n > o.map.inner.max_refs) {
+                    return Err(CappedInsertError::CapacityExceeded);
+                }
+                Ok(o.into_ref())
+            }
+            Entry::Vacant(v) => v.insert(default()),
+        }
+    }
+}
+
+/// An `Entry` for a key already in the map. Holds a Ref, so the entry
+/// stays alive while the view exists.
+pub struct OccupiedEntry<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    map: &'a mut RcHashMap<K, V, S, C>,
+    r: Ref<K, V, S, C>,
+}
+
+impl<'a, K, V, S, C> OccupiedEntry<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn key(&self) -> &K {
+        self.r.key(self.map).expect("entry Ref is live")
+    }
+
+    pub fn get(&self) -> &V {
+        self.r.value(self.map).expect("entry Ref is live")
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.r.value_mut(self.map).expect("entry Ref is live")
+    }
+
+    pub fn into_ref(self) -> Ref<K, V, S, C> {
+        self.r
+    }
+}
+
+/// An `Entry` for an absent key; remembers the key's hash for `insert`.
+pub struct VacantEntry<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    map: &'a mut RcHashMap<K, V, S, C>,
+    key: K,
+    hash: StoredHash,
+}
+
+impl<'a, K, V, S, C> VacantEntry<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Insert `value` under the key and return a Ref to the new entry.
+    /// Fails with `CapacityExceeded` if the map is at its entry cap.
+    pub fn insert(self, value: V) -> Result<Ref<K, V, S, C>, CappedInsertError> {
+        let VacantEntry { map, key, hash } = self;
+        if map.len() >= map.max_entries {
+            return Err(CappedInsertError::CapacityExceeded);
+        }
+        // Inserting takes `&mut self`, which this entry holds, so the key is
+        // still absent; Refs dropped since `entry` can only remove entries.
+        let (m, keepalive) = map.map_and_rccount_mut();
+        let rv = RcVal {
+            value,
+            keepalive_token: ManuallyDrop::new(keepalive.get()),
+        };
+        let ch = m.insert_unique_hashed(hash, key, rv);
+        let r = Ref::new(NonNull::from(map.inner.as_ref()), ch);
+        map.inner.stamp_new(r.handle.handle);
+        Ok(r)
+    }
+}
+
+/// A non-owning reference to an entry, made by `Ref::downgrade`: it does
+/// not keep the entry alive, so values can hold back-edges (parent
+/// pointers in a DAG) without forming cycles. No per-entry weak count is
+/// needed: the entry's handle is generational, so once the entry is gone
+/// the handle never resolves again, even if its slot is reused.
+pub struct WeakRef<K, V, S = DefaultHashBuilder, C = UsizeCount> {
+    owner: MapId,
+    handle: Handle,
+    #[allow(clippy::type_complexity)]
+    _pd: PhantomData<fn() -> (K, V, S, C)>,
+}
+
+impl<K, V, S, C> WeakRef<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// A Ref to the entry, if it still exists in `map` (the map that issued
+    /// it).
+    pub fn upgrade(&self, map: &RcHashMap<K, V, S, C>) -> Option<Ref<K, V, S, C>> {
+        if map.id() != self.owner {
+            return None;
+        }
+        map.mint(self.handle)
+    }
+
+    /// Identity of the map that issued the originating Ref.
+    pub fn owner_id(&self) -> MapId {
+        self.owner
+    }
+}
+
+impl<K, V, S, C> Clone for WeakRef<K, V, S, C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V, S, C> Copy for WeakRef<K, V, S, C> {}
+
+impl<K, V, S, C> core::fmt::Debug for WeakRef<K, V, S, C> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WeakRef")
+            .field("owner", &self.owner)
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+impl<K, V, S, C> PartialEq for WeakRef<K, V, S, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.owner == other.owner && self.handle == other.handle
+    }
+}
+
+impl<K, V, S, C> Eq for WeakRef<K, V, S, C> {}
+
+impl<K, V, S, C> Hash for WeakRef<K, V, S, C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.owner.hash(state);
+        self.handle.hash(state);
+    }
+}
+
+/// A `Ref` projected onto a sub-field of its value by `Ref::map_value`.
+pub struct MappedRef<K, V, T: ?Sized, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    r: Ref<K, V, S, C>,
+    project: Rc<dyn for<'v> Fn(&'v V) -> &'v T>,
+}
+
+impl<K, V, T: ?Sized, S, C> MappedRef<K, V, T, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// Borrow the projected field, validating owner identity.
+    pub fn get<'a>(&'a self, map: &'a RcHashMap<K, V, S, C>) -> Result<&'a T, AccessError> {
+        self.r.value(map).map(|v| (self.project)(v))
+    }
+
+    /// Recover the underlying Ref, regaining access to the whole value.
+    pub fn into_ref(self) -> Ref<K, V, S, C> {
+        self.r
+    }
+}
+
+impl<K, V, T: ?Sized, S, C> Clone for MappedRef<K, V, T, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            r: self.r.clone(),
+            project: Rc::clone(&self.project),
+        }
+    }
+}
+
+/// A `Ref` to a `Box<dyn Any>` value expected to be a `T`, from
+/// `RcHashMap::get_as`.
+pub struct TypedRef<K, T, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    r: Ref<K, Box<dyn Any>, S, C>,
+    _ty: PhantomData<fn() -> T>,
+}
+
+impl<K, T: 'static, S, C> TypedRef<K, T, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    /// Borrow the value as a `T`, validating owner identity. `Ok(None)` if
+    /// it has been replaced with a value of another type.
+    pub fn get<'a>(
+        &'a self,
+        map: &'a RcHashMap<K, Box<dyn Any>, S, C>,
+    ) -> Result<Option<&'a T>, AccessError> {
+        self.r.value(map).map(|v| v.downcast_ref::<T>())
+    }
+
+    /// Recover the underlying Ref, regaining access to the boxed value.
+    pub fn into_ref(self) -> Ref<K, Box<dyn Any>, S, C> {
+        self.r
+    }
+}
+
+impl<K, T, S, C> Clone for TypedRef<K, T, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            r: self.r.clone(),
+            _ty: PhantomData,
+        }
+    }
+}
+
+impl<K, V, S, C> Clone for Ref<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
+        // Increment per-entry count via counted handle API.
+        let inner = unsafe { self.owner_ptr.as_ref() };
+        let handle = unsafe { &*inner.map.get() }.get(&self.handle);
+        Ref::new(self.owner_ptr, handle)
+    }
+}
+
+impl<K, V, S, C> Drop for Ref<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn drop(&mut self) {
+        let inner = unsafe { &mut *(self.owner_ptr.as_ptr()) };
+        // Move out the handle without running its destructor.
+        let ch = unsafe { ManuallyDrop::take(&mut self.handle) };
+        if inner.scans.get() > 0 {
+            // A `ScanItem` may borrow this entry, or a mutable pass may be
+            // iterating the map; see `Inner::scans`.
+            inner.deferred.get_mut().push(ch);
+            return;
+        }
+        let handle = ch.handle;
+        let res = unsafe { &mut *inner.map.get() }.put(ch);
+        match res {
+            #[cfg(feature = "lifecycle-trace")]
+            PutResult::Live { remaining } => {
+                inner
+                    .trace
+                    .borrow_mut()
+                    .record(handle, TraceOp::Put, remaining);
+            }
+            #[cfg(not(feature = "lifecycle-trace"))]
+            PutResult::Live { .. } => {}
+            PutResult::Stale { remaining } => {
+                if remaining == 0 {
+                    let parked = unsafe { &mut *inner.parked.get() };
+                    let token = parked
+                        .remove(&handle)
+                        .expect("force-removed entry must have a parked keepalive");
+                    // May drop `Inner`; see below.
+                    let keepalive = inner.keepalive.clone();
+                    keepalive.put(token);
+                }
+            }
+            PutResult::Removed { key, value } => {
+                // Drop user data first while keepalive still holds Inner alive via strong count
+                let keepalive_token = inner.dispose(handle, key, value);
+                // Return the keepalive token to decrement the strong count.
+                // This may drop `Inner`, so release through a local clone of
+                // the counter rather than through a reference into `Inner`.
+                let keepalive = inner.keepalive.clone();
+                keepalive.put(keepalive_token);
+            }
+        }
+    }
+}
+
+impl<K, V, S, C> PartialEq for Ref<K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.owner_ptr == other.owner_ptr && self.handle.handle == other.handle.handle
+    }
+}
+
+impl<K, V, S, C> Eq for Ref<K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+}
+
+impl<K, V, S, C> Hash for Ref<K, V, S, C>
+where
+    K: Eq + core::hash::Hash,
+    S: core::hash::BuildHasher + Clone + Default,
+    C: EntryCount,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.owner_ptr.as_ptr() as usize).hash(state);
+        self.handle.handle.hash(state);
+    }
+}
+/// Item yielded by `RcHashMap::iter_mut`: a fresh `Ref` plus borrows of the
+/// entry's key and value. Derefs to the value, so `*item += 1` works.
+pub struct ItemMut<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    r: Ref<K, V, S, C>,
+    k: &'a K,
+    v: &'a mut V,
+}
+impl<'a, K, V, S, C> ItemMut<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn r#ref(&self) -> &Ref<K, V, S, C> {
+        &self.r
+    }
+    pub fn key(&self) -> &K {
+        self.k
+    }
+    pub fn value_mut(&mut self) -> &mut V {
+        self.v
+    }
+    /// Drop the borrows and keep the `Ref`.
+    pub fn into_ref(self) -> Ref<K, V, S, C> {
+        self.r
+    }
+}
+
+impl<K, V, S, C> core::ops::Deref for ItemMut<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.v
+    }
+}
+
+impl<K, V, S, C> core::ops::DerefMut for ItemMut<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn deref_mut(&mut self) -> &mut V {
+        self.v
+    }
+}
+
+/// Immutable iterator for RcHashMap yielding `Ref`. Exact-sized and fused;
+/// a clone continues from the same position.
+pub struct Iter<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    owner_ptr: NonNull<Inner<K, V, S, C>>,
+    inner: crate::counted_hash_map::Iter<'a, K, RcVal<K, V, S, C>, S, C>,
+}
+
+impl<'a, K, V, S, C> Iterator for Iter<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    type Item = Ref<K, V, S, C>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|(ch, _k, _rv)| Ref::new(self.owner_ptr, ch))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<K, V, S, C> ExactSizeIterator for Iter<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+}
+
+impl<K, V, S, C> core::iter::FusedIterator for Iter<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+}
+
+impl<K, V, S, C> Clone for Iter<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            owner_ptr: self.owner_ptr,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Item yielded by `RcHashMap::entries`: a fresh `Ref` plus borrows of the
+/// entry's key and value. Derefs to the value.
+pub struct Item<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    r: Ref<K, V, S, C>,
+    k: &'a K,
+    v: &'a V,
+}
+
+impl<K, V, S, C> Item<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn r#ref(&self) -> &Ref<K, V, S, C> {
+        &self.r
+    }
+    pub fn key(&self) -> &K {
+        self.k
+    }
+    pub fn value(&self) -> &V {
+        self.v
+    }
+    /// Drop the borrows and keep the `Ref`.
+    pub fn into_ref(self) -> Ref<K, V, S, C> {
+        self.r
+    }
 }
 
-impl<K, V> RcHashMap<K, V>
+impl<K, V, S, C> core::ops::Deref for Item<'_, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    pub fn new() -> Self {
-        Self {
-            inner: Rc::new_cyclic(|weak| Inner {
-                map: UnsafeCell::new(CountedHashMap::new()),
-                keepalive: RcCount::from_weak(weak),
-            }),
-        }
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.v
     }
 }
 
-impl<K, V> Default for RcHashMap<K, V>
+/// Iterator for RcHashMap yielding `Item`; see `entries`.
+/// Exact-sized and fused; a clone continues from the same position.
+pub struct Entries<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    fn default() -> Self {
-        Self::new()
-    }
+    owner_ptr: NonNull<Inner<K, V, S, C>>,
+    inner: crate::counted_hash_map::Iter<'a, K, RcVal<K, V, S, C>, S, C>,
 }
 
-impl<K, V, S> RcHashMap<K, V, S>
+impl<'a, K, V, S, C> Iterator for Entries<'a, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    // Internal helpers to access the inner map via UnsafeCell in one place.
-    fn map(&self) -> &CountedHashMap<K, RcVal<K, V, S>, S> {
-        unsafe { &*self.inner.map.get() }
-    }
-    fn map_mut(&mut self) -> &mut CountedHashMap<K, RcVal<K, V, S>, S> {
-        unsafe { &mut *self.inner.map.get() }
+    type Item = Item<'a, K, V, S, C>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(ch, k, rv)| Item {
+            r: Ref::new(self.owner_ptr, ch),
+            k,
+            v: &rv.value,
+        })
     }
-    #[allow(clippy::type_complexity)]
-    fn map_and_rccount_mut(
-        &mut self,
-    ) -> (
-        &mut CountedHashMap<K, RcVal<K, V, S>, S>,
-        &RcCount<Inner<K, V, S>>,
-    ) {
-        let m = unsafe { &mut *self.inner.map.get() };
-        let rc = &self.inner.keepalive;
-        (m, rc)
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
-    pub fn with_hasher(hasher: S) -> Self {
+}
+
+impl<K, V, S, C> ExactSizeIterator for Entries<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+}
+
+impl<K, V, S, C> core::iter::FusedIterator for Entries<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+}
+
+impl<K, V, S, C> Clone for Entries<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
         Self {
-            inner: Rc::new_cyclic(|weak| Inner {
-                map: UnsafeCell::new(CountedHashMap::with_hasher(hasher)),
-                keepalive: RcCount::from_weak(weak),
-            }),
+            owner_ptr: self.owner_ptr,
+            inner: self.inner.clone(),
         }
     }
+}
 
-    pub fn len(&self) -> usize {
-        self.map().len()
-    }
-    pub fn is_empty(&self) -> bool {
-        self.map().is_empty()
-    }
-
-    pub fn contains_key<Q>(&self, q: &Q) -> bool
-    where
-        K: core::borrow::Borrow<Q>,
-        Q: ?Sized + core::hash::Hash + Eq,
-    {
-        self.map().contains_key(q)
-    }
+/// Item yielded by `RcHashMap::scan`: borrows of an entry's key and value,
+/// with a Ref minted only on request. Derefs to the value.
+pub struct ScanItem<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    inner: &'a Inner<K, V, S, C>,
+    handle: Handle,
+    k: &'a K,
+    v: &'a V,
+}
 
-    pub fn insert(&mut self, key: K, value: V) -> Result<Ref<K, V, S>, InsertError> {
-        let (map, keepalive) = self.map_and_rccount_mut();
-        let res = map.insert_with(key, || RcVal {
-            value,
-            keepalive_token: keepalive.get(),
-        });
-        match res {
-            Ok(ch) => Ok(Ref::new(NonNull::from(self.inner.as_ref()), ch)),
-            Err(e) => Err(e),
+impl<'a, K, V, S, C> ScanItem<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn new(inner: &'a Inner<K, V, S, C>, handle: Handle, k: &'a K, v: &'a V) -> Self {
+        inner.scans.set(inner.scans.get() + 1);
+        Self {
+            inner,
+            handle,
+            k,
+            v,
         }
     }
 
-    pub fn find<Q>(&self, q: &Q) -> Option<Ref<K, V, S>>
-    where
-        K: core::borrow::Borrow<Q>,
-        Q: ?Sized + core::hash::Hash + Eq,
-    {
-        self.map()
-            .find(q)
-            .map(|ch| Ref::new(NonNull::from(self.inner.as_ref()), ch))
+    pub fn key(&self) -> &K {
+        self.k
     }
-
-    pub fn iter(&self) -> Iter<'_, K, V, S> {
-        let owner_ptr = NonNull::from(self.inner.as_ref());
-        let inner = self.map().iter_raw();
-        Iter { owner_ptr, inner }
+    pub fn value(&self) -> &V {
+        self.v
     }
+    /// Mint a Ref to the entry.
+    pub fn to_ref(&self) -> Ref<K, V, S, C> {
+        let ch = self
+            .inner
+            .map()
+            .get_by_handle(self.handle)
+            .expect("scanned entry stays live while its item exists");
+        Ref::new(NonNull::from(self.inner), ch)
+    }
+}
 
-    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, S> {
-        let owner_ptr = NonNull::from(self.inner.as_ref());
-        let inner = self.map_mut().iter_mut_raw();
-        IterMut { owner_ptr, inner }
+impl<K, V, S, C> core::ops::Deref for ScanItem<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.v
     }
 }
 
-/// A reference to an entry inside RcHashMap. Clone increments per-entry count;
-/// dropping decrements and removes the entry when it reaches zero.
-pub struct Ref<K, V, S = DefaultHashBuilder>
+impl<K, V, S, C> Drop for ScanItem<'_, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    owner_ptr: NonNull<Inner<K, V, S>>,
-    handle: ManuallyDrop<CountedHandle<'static>>,
-    _nosend: PhantomData<*mut ()>,
+    fn drop(&mut self) {
+        end_scan(self.inner);
+    }
 }
 
-/// Owner-mismatch error for Ref accessors.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct WrongMap;
+// An `Inner::scans` section with no item attached, closed on drop.
+struct ScanSection<'a, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    inner: &'a Inner<K, V, S, C>,
+}
 
-impl<K, V, S> Ref<K, V, S>
+impl<'a, K, V, S, C> ScanSection<'a, K, V, S, C>
 where
-    K: Eq + core::hash::Hash,
-    S: core::hash::BuildHasher + Clone + Default,
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    fn new(owner_ptr: NonNull<Inner<K, V, S>>, handle: CountedHandle<'static>) -> Self {
-        Self {
-            owner_ptr,
-            handle: ManuallyDrop::new(handle),
-            _nosend: PhantomData,
-        }
+    fn new(inner: &'a Inner<K, V, S, C>) -> Self {
+        inner.scans.set(inner.scans.get() + 1);
+        Self { inner }
     }
+}
 
-    #[inline]
-    fn check_owner<'a>(&'a self, map: &'a RcHashMap<K, V, S>) -> Result<(), WrongMap> {
-        // Safety: owner_ptr is created from Rc::as_ref; compare raw pointers for identity.
-        let ptr = NonNull::from(map.inner.as_ref());
-        if ptr == self.owner_ptr {
-            Ok(())
-        } else {
-            Err(WrongMap)
-        }
+impl<K, V, S, C> Drop for ScanSection<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn drop(&mut self) {
+        end_scan(self.inner);
     }
+}
 
-    /// Borrow the entry's key, validating owner identity.
-    pub fn key<'a>(&'a self, map: &'a RcHashMap<K, V, S>) -> Result<&'a K, WrongMap> {
-        self.check_owner(map)?;
-        self.handle.key_ref(map.map()).ok_or(WrongMap)
+// Close one `Inner::scans` section; the last to close releases the Refs
+// deferred meanwhile.
+fn end_scan<K, V, S, C>(inner: &Inner<K, V, S, C>)
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    let n = inner.scans.get() - 1;
+    inner.scans.set(n);
+    if n > 0 {
+        return;
     }
+    // Release deferred Refs. The map is borrowed, so `Inner` outlives
+    // this; user drops that start another scan defer anew.
+    let owner_ptr = NonNull::from(inner);
+    let deferred = core::mem::take(unsafe { &mut *inner.deferred.get() });
+    // Wrap them all before dropping any, so a panicking drop unwinds
+    // through Refs rather than bare handles.
+    let refs: Vec<_> = deferred
+        .into_iter()
+        .map(|ch| Ref {
+            owner_ptr,
+            handle: ManuallyDrop::new(ch),
+            _nosend: PhantomData,
+        })
+        .collect();
+    drop(refs);
+}
 
-    /// Borrow the entry's value, validating owner identity.
-    pub fn value<'a>(&'a self, map: &'a RcHashMap<K, V, S>) -> Result<&'a V, WrongMap> {
-        self.check_owner(map)?;
-        self.handle
-            .value_ref(map.map())
-            .map(|rcv| &rcv.value)
-            .ok_or(WrongMap)
-    }
+/// Mutable access to a value from `RcHashMap::peek_mut`. Like `ScanItem`,
+/// it keeps the entry in place while alive: dropping the entry's last Ref
+/// meanwhile removes it only once this guard drops.
+pub struct PeekMut<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    inner: &'a Inner<K, V, S, C>,
+    v: &'a mut V,
+}
 
-    /// Mutably borrow the entry's value, validating owner identity.
-    pub fn value_mut<'a>(&'a self, map: &'a mut RcHashMap<K, V, S>) -> Result<&'a mut V, WrongMap> {
-        if NonNull::from(map.inner.as_ref()) != self.owner_ptr {
-            return Err(WrongMap);
-        }
-        // SAFETY: owner validated and we have &mut map, so exclusive access for 'a
-        self.check_owner(map)?; // ensure owner match
-        self.handle
-            .value_mut(map.map_mut())
-            .map(|rcv| &mut rcv.value)
-            .ok_or(WrongMap)
+impl<K, V, S, C> core::ops::Deref for PeekMut<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.v
     }
 }
 
-impl<K, V, S> Clone for Ref<K, V, S>
+impl<K, V, S, C> core::ops::DerefMut for PeekMut<'_, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    fn clone(&self) -> Self {
-        // Increment per-entry count via counted handle API.
-        let inner = unsafe { self.owner_ptr.as_ref() };
-        let handle = unsafe { &*inner.map.get() }.get(&self.handle);
-        Ref::new(self.owner_ptr, handle)
+    fn deref_mut(&mut self) -> &mut V {
+        self.v
     }
 }
 
-impl<K, V, S> Drop for Ref<K, V, S>
+impl<K, V, S, C> Drop for PeekMut<'_, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
     fn drop(&mut self) {
-        let inner = unsafe { &mut *(self.owner_ptr.as_ptr()) };
-        // Move out the handle without running its destructor.
-        let ch = unsafe { ManuallyDrop::take(&mut self.handle) };
-        let res = unsafe { &mut *inner.map.get() }.put(ch);
-        match res {
-            PutResult::Live => {}
-            PutResult::Removed { key, value } => {
-                // Drop user data first while keepalive still holds Inner alive via strong count
-                let RcVal {
-                    value: user_value,
-                    keepalive_token,
-                } = value;
-                drop(key);
-                drop(user_value);
-                // Return the keepalive token to decrement the strong count.
-                inner.keepalive.put(keepalive_token);
-            }
-        }
+        end_scan(self.inner);
     }
 }
 
-impl<K, V, S> PartialEq for Ref<K, V, S>
+/// Iterator for RcHashMap yielding `ScanItem`; see `scan`. Exact-sized and
+/// fused; a clone continues from the same position.
+pub struct Scan<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
 where
-    K: Eq + core::hash::Hash,
-    S: core::hash::BuildHasher + Clone + Default,
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    fn eq(&self, other: &Self) -> bool {
-        self.owner_ptr == other.owner_ptr && self.handle.handle == other.handle.handle
-    }
+    inner: &'a Inner<K, V, S, C>,
+    it: crate::counted_hash_map::Iter<'a, K, RcVal<K, V, S, C>, S, C>,
 }
 
-impl<K, V, S> Eq for Ref<K, V, S>
+impl<'a, K, V, S, C> Iterator for Scan<'a, K, V, S, C>
 where
-    K: Eq + core::hash::Hash,
-    S: core::hash::BuildHasher + Clone + Default,
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
+    type Item = ScanItem<'a, K, V, S, C>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.it
+            .next_borrowed()
+            .map(|(h, k, rv)| ScanItem::new(self.inner, h, k, &rv.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.it.size_hint()
+    }
 }
 
-impl<K, V, S> Hash for Ref<K, V, S>
+impl<K, V, S, C> ExactSizeIterator for Scan<'_, K, V, S, C>
 where
-    K: Eq + core::hash::Hash,
-    S: core::hash::BuildHasher + Clone + Default,
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        (self.owner_ptr.as_ptr() as usize).hash(state);
-        self.handle.handle.hash(state);
-    }
 }
-/// Placeholder for future mutable iterator item (see design docs).
-pub struct ItemMut<'a, K, V, S = DefaultHashBuilder>
+
+impl<K, V, S, C> core::iter::FusedIterator for Scan<'_, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    r: Ref<K, V, S>,
-    k: &'a K,
-    v: &'a mut V,
 }
-impl<'a, K, V, S> ItemMut<'a, K, V, S>
+
+impl<K, V, S, C> Clone for Scan<'_, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    pub fn r#ref(&self) -> &Ref<K, V, S> {
-        &self.r
-    }
-    pub fn key(&self) -> &K {
-        self.k
-    }
-    pub fn value_mut(&mut self) -> &mut V {
-        self.v
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner,
+            it: self.it.clone(),
+        }
     }
 }
 
-/// Immutable iterator for RcHashMap yielding `Ref`.
-pub struct Iter<'a, K, V, S = DefaultHashBuilder>
+/// Snapshot iterator for RcHashMap yielding `Ref`; see `snapshot_iter`.
+pub struct SnapshotIter<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    owner_ptr: NonNull<Inner<K, V, S>>,
-    inner: crate::counted_hash_map::Iter<'a, K, RcVal<K, V, S>, S>,
+    map: &'a RcHashMap<K, V, S, C>,
+    handles: std::vec::IntoIter<Handle>,
 }
 
-impl<'a, K, V, S> Iterator for Iter<'a, K, V, S>
+impl<'a, K, V, S, C> Iterator for SnapshotIter<'a, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    type Item = Ref<K, V, S>;
+    type Item = Ref<K, V, S, C>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner
-            .next()
-            .map(|(ch, _k, _rv)| Ref::new(self.owner_ptr, ch))
+        let owner_ptr = NonNull::from(self.map.inner.as_ref());
+        self.handles
+            .by_ref()
+            .find_map(|h| self.map.map().get_by_handle(h))
+            .map(|ch| Ref::new(owner_ptr, ch))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.handles.len()))
+    }
+}
+
+impl<K, V, S, C> Clone for SnapshotIter<'_, K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map,
+            handles: self.handles.clone(),
+        }
     }
 }
 
 /// Mutable iterator for RcHashMap yielding ItemMut.
-pub struct IterMut<'a, K, V, S = DefaultHashBuilder>
+pub struct IterMut<'a, K, V, S = DefaultHashBuilder, C = UsizeCount>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    owner_ptr: NonNull<Inner<K, V, S>>,
-    inner: crate::counted_hash_map::IterMut<'a, K, RcVal<K, V, S>, S>,
+    owner_ptr: NonNull<Inner<K, V, S, C>>,
+    inner: crate::counted_hash_map::IterMut<'a, K, RcVal<K, V, S, C>, S, C>,
 }
 
-impl<'a, K, V, S> Iterator for IterMut<'a, K, V, S>
+impl<'a, K, V, S, C> Iterator for IterMut<'a, K, V, S, C>
 where
     K: Eq + core::hash::Hash + 'static,
     V: 'static,
     S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
 {
-    type Item = ItemMut<'a, K, V, S>;
+    type Item = ItemMut<'a, K, V, S, C>;
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next().map(|(ch, k, rv)| {
             let r = Ref::new(self.owner_ptr, ch);
@@ -0,0 +1,121 @@
+//! RcHashMapFixed: an `RcHashMap` capped at `N` entries whose storage is
+//! allocated once, at construction.
+//!
+//! For embedded and latency-critical code that forbids heap growth after
+//! init: `new` preallocates room for `N` entries and sets the map's
+//! `max_entries` to `N`, so `insert` fails with
+//! `CappedInsertError::CapacityExceeded` instead of growing.
+//!
+//! Only the allocation-free subset of the map API is exposed: no pins,
+//! finalizers or `force_remove`, which keep side tables of their own. Read
+//! through `as_map` (Ref accessors take the underlying `RcHashMap`).
+
+use crate::handle_hash_map::CappedInsertError;
+use crate::hash::DefaultHashBuilder;
+use crate::rc_hash_map::{AccessError, Iter, RcHashMap, Ref};
+use crate::tokens::{EntryCount, UsizeCount};
+
+/// An `RcHashMap` that holds at most `N` entries and never reallocates.
+///
+/// Storage is not inline: `new` heap-allocates room for `N` entries up
+/// front (via `reserve`), the same storage `RcHashMap` uses, and inserts
+/// past `N` fail rather than grow it. `N` bounds the entry count, not the
+/// type's size.
+pub struct RcHashMapFixed<K, V, const N: usize, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    map: RcHashMap<K, V, S, C>,
+}
+
+impl<K, V, const N: usize> RcHashMapFixed<K, V, N>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+{
+    pub fn new() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, const N: usize, C> Default for RcHashMapFixed<K, V, N, DefaultHashBuilder, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    C: EntryCount,
+{
+    fn default() -> Self {
+        Self::with_hasher(DefaultHashBuilder::default())
+    }
+}
+
+impl<K, V, const N: usize, S, C> RcHashMapFixed<K, V, N, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        let mut map = RcHashMap::with_hasher(hasher);
+        map.reserve_fixed(N);
+        map.set_max_entries(N);
+        Self { map }
+    }
+
+    /// The underlying map, for Ref accessors and read-only queries.
+    pub fn as_map(&self) -> &RcHashMap<K, V, S, C> {
+        &self.map
+    }
+
+    /// Maximum number of entries, `N`.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// True if `insert` would fail with `CapacityExceeded`.
+    pub fn is_full(&self) -> bool {
+        self.map.len() >= N
+    }
+
+    pub fn contains_key<Q>(&self, q: &Q) -> bool
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map.contains_key(q)
+    }
+
+    /// Insert `key`/`value`; fails with `DuplicateKey` if the key is
+    /// present, else with `CapacityExceeded` if the map holds `N` entries.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Ref<K, V, S, C>, CappedInsertError> {
+        self.map.insert(key, value)
+    }
+
+    pub fn find<Q>(&self, q: &Q) -> Option<Ref<K, V, S, C>>
+    where
+        K: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map.find(q)
+    }
+
+    /// Mutably borrow the value behind `r`, validating owner identity.
+    pub fn value_mut<'a>(&'a mut self, r: &'a Ref<K, V, S, C>) -> Result<&'a mut V, AccessError> {
+        r.value_mut(&mut self.map)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, S, C> {
+        self.map.iter()
+    }
+}
@@ -0,0 +1,113 @@
+//! RcHashSet: a ref-counted set built on `RcHashMap` with unit values.
+//!
+//! `insert` returns a `Ref` to the element; the element is removed when its
+//! last `Ref` drops. Useful for interning and other keyed-liveness uses that
+//! carry no per-element value.
+
+use crate::handle_hash_map::{CappedInsertError, InsertError};
+use crate::hash::DefaultHashBuilder;
+use crate::rc_hash_map::{AccessError, Iter, RcHashMap, Ref};
+use crate::tokens::{EntryCount, UsizeCount};
+
+/// A `Ref` to an element of an `RcHashSet`.
+pub type SetRef<T, S = DefaultHashBuilder, C = UsizeCount> = Ref<T, (), S, C>;
+
+pub struct RcHashSet<T, S = DefaultHashBuilder, C: EntryCount = UsizeCount> {
+    map: RcHashMap<T, (), S, C>,
+}
+
+impl<T> RcHashSet<T>
+where
+    T: Eq + core::hash::Hash + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            map: RcHashMap::new(),
+        }
+    }
+}
+
+impl<T, C> Default for RcHashSet<T, DefaultHashBuilder, C>
+where
+    T: Eq + core::hash::Hash + 'static,
+    C: EntryCount,
+{
+    fn default() -> Self {
+        Self {
+            map: RcHashMap::default(),
+        }
+    }
+}
+
+impl<T, S, C> RcHashSet<T, S, C>
+where
+    T: Eq + core::hash::Hash + 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            map: RcHashMap::with_hasher(hasher),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains<Q>(&self, q: &Q) -> bool
+    where
+        T: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map.contains_key(q)
+    }
+
+    /// Returns true if `r` was issued by this set and still resolves.
+    pub fn contains_ref(&self, r: &SetRef<T, S, C>) -> bool {
+        self.map.contains_ref(r)
+    }
+
+    /// Insert `value`; fails if an equal element is already present.
+    pub fn insert(&mut self, value: T) -> Result<SetRef<T, S, C>, InsertError> {
+        self.map
+            .insert(value, ())
+            .map_err(CappedInsertError::uncapped)
+    }
+
+    /// Return a Ref to the element equal to `value`, inserting it if absent.
+    pub fn get_or_insert(&mut self, value: T) -> SetRef<T, S, C> {
+        if let Some(r) = self.map.find(&value) {
+            return r;
+        }
+        match self.map.insert(value, ()) {
+            Ok(r) => r,
+            Err(_) => unreachable!("value was just looked up"),
+        }
+    }
+
+    pub fn find<Q>(&self, q: &Q) -> Option<SetRef<T, S, C>>
+    where
+        T: core::borrow::Borrow<Q>,
+        Q: ?Sized + core::hash::Hash + Eq,
+    {
+        self.map.find(q)
+    }
+
+    /// Borrow the element behind `r`, validating owner identity.
+    pub fn get<'a>(&'a self, r: &'a SetRef<T, S, C>) -> Result<&'a T, AccessError> {
+        r.key(&self.map)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, (), S, C> {
+        self.map.iter()
+    }
+
+    /// Estimated bytes allocated by the set. See `RcHashMap::allocated_bytes`.
+    pub fn allocated_bytes(&self) -> usize {
+        self.map.allocated_bytes()
+    }
+}
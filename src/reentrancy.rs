@@ -1,18 +1,103 @@
 //! Debug-only reentrancy guard.
 //!
 //! Single-threaded structure to detect accidental reentrancy into a data
-//! structure. In debug builds, entering twice without dropping the guard
-//! panics. In release builds, this compiles to a zero-cost no-op.
+//! structure. Sections are either shared (read-only methods taking `&self`)
+//! or exclusive (methods taking `&mut self`). In debug builds, nested shared
+//! sections are allowed, while a shared section inside an exclusive one, or
+//! an exclusive section inside anything, is a violation. Violations go to
+//! the process-wide handler (`set_reentrancy_handler`), which panics by
+//! default; the report names both the colliding method and the outermost
+//! active one, with their call locations. In release builds, this compiles
+//! to a zero-cost no-op.
+//!
+//! Embedders that cannot unwind (plugins, FFI callbacks) can install
+//! `abort_on_reentrancy` instead, or `log_reentrancy` to report and carry
+//! on as a release build would.
 
+#[cfg(debug_assertions)]
 use core::cell::Cell;
+use core::fmt;
 use core::marker::PhantomData;
+use core::panic::Location;
+use std::sync::RwLock;
+
+/// A detected reentrancy, as passed to the reentrancy handler.
+#[derive(Copy, Clone, Debug)]
+pub struct ReentrancyViolation {
+    /// `"read access"` (shared under exclusive) or `"nested entry"`.
+    pub what: &'static str,
+    /// The method that collided, and where it was called.
+    pub method: &'static str,
+    pub location: &'static Location<'static>,
+    /// The outermost active method, and where it was entered.
+    pub outer: Option<(&'static str, &'static Location<'static>)>,
+}
+
+impl fmt::Display for ReentrancyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "reentrancy detected: {} via `{}` at {}",
+            self.what, self.method, self.location
+        )?;
+        if let Some((m, l)) = self.outer {
+            write!(f, " while `{m}` (entered at {l}) is active")?;
+        }
+        Ok(())
+    }
+}
+
+/// Called on every reentrancy violation. If it returns, the colliding
+/// section proceeds unguarded, exactly as in a release build.
+pub type ReentrancyHandler = fn(&ReentrancyViolation);
+
+static HANDLER: RwLock<ReentrancyHandler> = RwLock::new(panic_on_reentrancy);
+
+/// Install the process-wide reentrancy handler, returning the previous one.
+/// Only debug builds detect reentrancy, so release builds never call it.
+pub fn set_reentrancy_handler(handler: ReentrancyHandler) -> ReentrancyHandler {
+    let mut h = HANDLER.write().unwrap_or_else(|e| e.into_inner());
+    core::mem::replace(&mut *h, handler)
+}
+
+/// The default handler: panic with the violation report.
+pub fn panic_on_reentrancy(v: &ReentrancyViolation) {
+    panic!("{v}");
+}
+
+/// Print the report to stderr and abort the process.
+pub fn abort_on_reentrancy(v: &ReentrancyViolation) {
+    eprintln!("{v}");
+    std::process::abort();
+}
+
+/// Print the report to stderr and continue.
+pub fn log_reentrancy(v: &ReentrancyViolation) {
+    eprintln!("{v}");
+}
+
+// The outermost active section, reported when a nested entry collides.
+#[cfg(debug_assertions)]
+#[derive(Copy, Clone, Debug)]
+struct Entered {
+    method: &'static str,
+    location: &'static Location<'static>,
+}
 
-/// Per-instance reentrancy tracker. Embed this in structs to guard public
-/// entry-points with `let _g = self.reentrancy.enter();`.
+/// Per-instance reentrancy tracker. Embed this in structs and guard public
+/// entry-points with `let _g = self.reentrancy.enter_shared("find");` (for
+/// `&self` methods) or `let _g = self.reentrancy.enter_exclusive("insert");`
+/// (for `&mut self` methods). Both are `#[track_caller]`; mark the guarded
+/// method `#[cfg_attr(debug_assertions, track_caller)]` so the reported
+/// location is the caller's.
 #[derive(Debug)]
 pub struct DebugReentrancy {
     #[cfg(debug_assertions)]
-    depth: Cell<u32>,
+    readers: Cell<u32>,
+    #[cfg(debug_assertions)]
+    writer: Cell<bool>,
+    #[cfg(debug_assertions)]
+    outer: Cell<Option<Entered>>,
     // Keep !Send + !Sync in line with single-threaded design.
     _nosend: PhantomData<*mut ()>,
 }
@@ -22,28 +107,94 @@ impl DebugReentrancy {
     pub const fn new() -> Self {
         Self {
             #[cfg(debug_assertions)]
-            depth: Cell::new(0),
+            readers: Cell::new(0),
+            #[cfg(debug_assertions)]
+            writer: Cell::new(false),
+            #[cfg(debug_assertions)]
+            outer: Cell::new(None),
             _nosend: PhantomData,
         }
     }
 
-    /// Enter a guarded section. In debug builds, panics if already entered.
+    /// Enter a read-only section. In debug builds, reports a violation if
+    /// an exclusive section is active; nesting inside other shared sections
+    /// is allowed.
+    #[inline]
+    #[track_caller]
+    pub fn enter_shared(&self, _method: &'static str) -> ReentrancyGuard<'_> {
+        #[cfg(debug_assertions)]
+        {
+            if self.writer.get() {
+                return self.collide("read access", _method);
+            }
+            if self.readers.get() == 0 {
+                self.record(_method);
+            }
+            self.readers.set(self.readers.get() + 1);
+            ReentrancyGuard {
+                owner: Some(self),
+                exclusive: false,
+            }
+        }
+
+        #[cfg(not(debug_assertions))]
+        {
+            ReentrancyGuard { _z: PhantomData }
+        }
+    }
+
+    /// Enter a mutating section. In debug builds, reports a violation if
+    /// any section, shared or exclusive, is already active.
     #[inline]
-    pub fn enter(&self) -> ReentrancyGuard<'_> {
+    #[track_caller]
+    pub fn enter_exclusive(&self, _method: &'static str) -> ReentrancyGuard<'_> {
         #[cfg(debug_assertions)]
         {
-            let d = self.depth.get();
-            assert!(
-                d == 0,
-                "reentrancy detected: nested entry into data structure"
-            );
-            self.depth.set(d + 1);
-            ReentrancyGuard { owner: self }
+            if self.writer.get() || self.readers.get() > 0 {
+                return self.collide("nested entry", _method);
+            }
+            self.record(_method);
+            self.writer.set(true);
+            ReentrancyGuard {
+                owner: Some(self),
+                exclusive: true,
+            }
         }
 
         #[cfg(not(debug_assertions))]
         {
-            return ReentrancyGuard { _z: PhantomData };
+            ReentrancyGuard { _z: PhantomData }
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl DebugReentrancy {
+    #[track_caller]
+    fn record(&self, method: &'static str) {
+        self.outer.set(Some(Entered {
+            method,
+            location: Location::caller(),
+        }));
+    }
+
+    // Report a violation; if the handler returns, hand back a guard that
+    // leaves the tracking state to the sections already active.
+    #[cold]
+    #[track_caller]
+    fn collide(&self, what: &'static str, method: &'static str) -> ReentrancyGuard<'_> {
+        let v = ReentrancyViolation {
+            what,
+            method,
+            location: Location::caller(),
+            outer: self.outer.get().map(|o| (o.method, o.location)),
+        };
+        // Copy the handler out so it may itself call `set_reentrancy_handler`.
+        let handler = *HANDLER.read().unwrap_or_else(|e| e.into_inner());
+        handler(&v);
+        ReentrancyGuard {
+            owner: None,
+            exclusive: false,
         }
     }
 }
@@ -54,10 +205,13 @@ impl Default for DebugReentrancy {
     }
 }
 
-/// RAII guard returned by `DebugReentrancy::enter`.
+/// RAII guard returned by `DebugReentrancy::enter_shared`/`enter_exclusive`.
 pub struct ReentrancyGuard<'a> {
+    // None for a section let through by a returning handler.
+    #[cfg(debug_assertions)]
+    owner: Option<&'a DebugReentrancy>,
     #[cfg(debug_assertions)]
-    owner: &'a DebugReentrancy,
+    exclusive: bool,
     #[cfg(not(debug_assertions))]
     _z: PhantomData<&'a ()>,
 }
@@ -65,10 +219,19 @@ pub struct ReentrancyGuard<'a> {
 impl<'a> Drop for ReentrancyGuard<'a> {
     fn drop(&mut self) {
         #[cfg(debug_assertions)]
-        {
-            let d = self.owner.depth.get();
-            debug_assert!(d > 0);
-            self.owner.depth.set(d - 1);
+        if let Some(owner) = self.owner {
+            if self.exclusive {
+                debug_assert!(owner.writer.get());
+                owner.writer.set(false);
+                owner.outer.set(None);
+            } else {
+                let r = owner.readers.get();
+                debug_assert!(r > 0);
+                owner.readers.set(r - 1);
+                if r == 1 {
+                    owner.outer.set(None);
+                }
+            }
         }
     }
 }
@@ -80,28 +243,111 @@ mod tests {
     #[test]
     fn enter_and_exit_is_ok() {
         let r = DebugReentrancy::new();
-        let _g = r.enter();
+        {
+            let _g = r.enter_exclusive("w");
+        }
+        let _g = r.enter_shared("r");
+    }
+
+    #[test]
+    fn nested_shared_is_ok() {
+        let r = DebugReentrancy::new();
+        let _g1 = r.enter_shared("r");
+        let _g2 = r.enter_shared("r");
     }
 
     #[cfg(debug_assertions)]
     #[test]
     fn reentrancy_panics_in_debug() {
         let r = DebugReentrancy::new();
+        // Exclusive under exclusive.
         let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            let _g1 = r.enter();
-            // Re-entering should panic in debug builds
-            let _g2 = r.enter();
-            let _ = _g2; // silence unused
+            let _g1 = r.enter_exclusive("w");
+            let _g2 = r.enter_exclusive("w");
         }));
         assert!(res.is_err(), "expected reentrancy to panic in debug builds");
+
+        // Shared under exclusive and exclusive under shared.
+        let r = DebugReentrancy::new();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _g1 = r.enter_exclusive("w");
+            let _g2 = r.enter_shared("r");
+        }));
+        assert!(res.is_err(), "read under write must panic");
+        let r = DebugReentrancy::new();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _g1 = r.enter_shared("r");
+            let _g2 = r.enter_exclusive("w");
+        }));
+        assert!(res.is_err(), "write under read must panic");
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn reentrancy_panic_names_both_entries() {
+        let r = DebugReentrancy::new();
+        let outer_line = line!() + 2;
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _g0 = r.enter_shared("find");
+            let _g1 = r.enter_shared("contains_key");
+            let _g2 = r.enter_exclusive("insert");
+        }));
+        let msg = res.unwrap_err().downcast::<String>().unwrap();
+        assert!(msg.contains("`insert`"), "{msg}");
+        assert!(msg.contains("`find`"), "{msg}");
+        assert!(
+            msg.contains(&format!("{}:{}", file!(), outer_line)),
+            "{msg}"
+        );
+    }
+
+    /// Invariant: if the handler returns, the colliding section proceeds
+    /// and leaves the outer section's tracking intact.
+    #[cfg(debug_assertions)]
+    #[test]
+    fn returning_handler_lets_section_proceed() {
+        use super::{panic_on_reentrancy, set_reentrancy_handler, ReentrancyViolation};
+        use std::cell::{Cell, RefCell};
+        thread_local! {
+            static SEEN: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+        }
+        // Process-wide: other test threads keep the default behavior.
+        fn record(v: &ReentrancyViolation) {
+            let mine = SEEN.with(|s| s.borrow_mut().as_mut().map(|s| s.push(v.to_string())));
+            if mine.is_none() {
+                panic_on_reentrancy(v);
+            }
+        }
+        SEEN.with(|s| *s.borrow_mut() = Some(Vec::new()));
+        let prev = set_reentrancy_handler(record);
+
+        let r = DebugReentrancy::new();
+        let ran = Cell::new(false);
+        {
+            let _g1 = r.enter_exclusive("insert");
+            {
+                let _g2 = r.enter_shared("find");
+                ran.set(true);
+            }
+            let _g3 = r.enter_exclusive("remove");
+        }
+        let _g = r.enter_exclusive("insert");
+        drop(_g);
+
+        set_reentrancy_handler(prev);
+        let seen = SEEN.with(|s| s.borrow_mut().take().unwrap());
+        assert!(ran.get());
+        assert_eq!(seen.len(), 2);
+        assert!(seen[0].contains("`find`") && seen[0].contains("`insert`"));
+        assert!(seen[1].contains("`remove`") && seen[1].contains("`insert`"));
     }
 
     #[cfg(not(debug_assertions))]
     #[test]
     fn reentrancy_noop_in_release() {
         let r = DebugReentrancy::new();
-        let _g1 = r.enter();
-        let _g2 = r.enter();
+        let _g1 = r.enter_exclusive("w");
+        let _g2 = r.enter_exclusive("w");
         let (_g1, _g2) = (_g1, _g2);
     }
 }
@@ -0,0 +1,96 @@
+//! LocalRegistry: a per-thread singleton `RcHashMap`.
+//!
+//! Process-wide registries (interned names, loaded assets, …) usually end up
+//! as a `thread_local!` `RefCell<RcHashMap>` plus `with`/`borrow_mut`
+//! plumbing at every call site. `static_rc_hashmap!` declares one in a line
+//! and `LocalRegistry` provides the accessors.
+//!
+//! ```rust
+//! use rc_hashmap::static_rc_hashmap;
+//!
+//! static_rc_hashmap! {
+//!     static NAMES: String => u32;
+//! }
+//!
+//! let r = NAMES.with_mut(|m| m.insert("alice".to_string(), 1).unwrap());
+//! assert_eq!(NAMES.with(|m| *r.value(m).unwrap()), 1);
+//! drop(r);
+//! assert!(NAMES.with(|m| m.is_empty()));
+//! ```
+//!
+//! `with` and `with_mut` borrow the map through a `RefCell`: nesting
+//! `with_mut` inside either accessor on the same registry panics. Dropping a
+//! `Ref` never touches the `RefCell`, so it is fine anywhere.
+
+use crate::hash::DefaultHashBuilder;
+use crate::rc_hash_map::RcHashMap;
+use crate::tokens::{EntryCount, UsizeCount};
+use core::cell::RefCell;
+use std::thread::LocalKey;
+
+/// Accessors for a thread-local `RcHashMap`; declare one with
+/// `static_rc_hashmap!`.
+pub struct LocalRegistry<K, V, S = DefaultHashBuilder, C = UsizeCount>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount + 'static,
+{
+    key: &'static LocalKey<RefCell<RcHashMap<K, V, S, C>>>,
+}
+
+impl<K, V, S, C> LocalRegistry<K, V, S, C>
+where
+    K: Eq + core::hash::Hash + 'static,
+    V: 'static,
+    S: core::hash::BuildHasher + Clone + Default + 'static,
+    C: EntryCount + 'static,
+{
+    /// Wrap a `thread_local!` map. Usually called by `static_rc_hashmap!`.
+    pub const fn new(key: &'static LocalKey<RefCell<RcHashMap<K, V, S, C>>>) -> Self {
+        Self { key }
+    }
+
+    /// Run `f` with shared access to this thread's map.
+    pub fn with<R>(&'static self, f: impl FnOnce(&RcHashMap<K, V, S, C>) -> R) -> R {
+        self.key.with(|m| f(&m.borrow()))
+    }
+
+    /// Run `f` with exclusive access to this thread's map.
+    pub fn with_mut<R>(&'static self, f: impl FnOnce(&mut RcHashMap<K, V, S, C>) -> R) -> R {
+        self.key.with(|m| f(&mut m.borrow_mut()))
+    }
+}
+
+/// Declare a thread-local `RcHashMap<K, V>` behind a `LocalRegistry`. A
+/// hasher and count type may follow the value type, as in `RcHashMap<K, V,
+/// S, C>`; the map is then built with `S::default()`.
+///
+/// ```rust
+/// use rc_hashmap::tokens::CheckedCount;
+/// use rc_hashmap::IdentityHashBuilder;
+///
+/// rc_hashmap::static_rc_hashmap! {
+///     /// Interned symbols.
+///     pub static SYMBOLS: String => ();
+/// }
+/// rc_hashmap::static_rc_hashmap! {
+///     static IDS: u64 => String, IdentityHashBuilder, CheckedCount;
+/// }
+/// ```
+#[macro_export]
+macro_rules! static_rc_hashmap {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $k:ty => $v:ty $(, $s:ty $(, $c:ty)?)?;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::LocalRegistry<$k, $v $(, $s $(, $c)?)?> = {
+            ::std::thread_local! {
+                static MAP: ::core::cell::RefCell<$crate::RcHashMap<$k, $v $(, $s $(, $c)?)?>> =
+                    ::core::cell::RefCell::new($crate::RcHashMap::with_hasher(
+                        ::core::default::Default::default(),
+                    ));
+            }
+            $crate::LocalRegistry::new(&MAP)
+        };
+    };
+}
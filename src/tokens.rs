@@ -22,12 +22,27 @@
 //!
 //! Unwinding and Drop panics
 //! - Panicking in `Token::drop` during another unwind aborts. Tokens are an internal mechanism, so this fail-fast behavior is acceptable for this crate.
+//! - Debugging leaks: with the `token-provenance` feature each token boxes the location that minted it (the caller of `get`, `get_n` or `adopt_raw`) and, when `RUST_BACKTRACE` is set, a backtrace; the drop panic reports both. Tokens are no longer zero-sized under this feature, so keep it out of release builds.
+//!
+//! Bulk operations
+//! - `Count::get_n`/`put_n` acquire or release `n` units at once, for callers implementing weighted references. The defaults loop over `get`/`put`; `UsizeCount`, `CheckedCount` and `SaturatingCount` override them with a single counter update, and `RcCount` checks liveness once per batch.
+//!
+//! Crossing boundaries
+//! - `Token::into_raw` converts a token into a `RawToken` that does not panic on drop, for FFI or type-erased layers that cannot carry a linear type. `Count::adopt_raw` converts it back. Both steps are explicit so every leak/adopt pair is easy to audit; a `RawToken` that is dropped instead of adopted leaks its unit.
+//!
+//! Trait objects
+//! - `Count` has a generic associated token type and is therefore not dyn-compatible. `DynCount` is a blanket-implemented, object-safe view of every `EntryCount` whose tokens are erased to `ErasedToken`, so counters of different types can be stored as `Box<dyn DynCount>`. An erased token records its counter's address, checked on return.
 //!
 //! Patterns
 //! - Owned-token pattern: When a function owns the token and can consume it by value (i.e., not in a `Drop` impl), prefer moving the token directly into `Count::put` without `ManuallyDrop`.
 //!   For example, `CountedHandle` owns `Token<'_, UsizeCount>`. `CountedHashMap::put(self, handle)` consumes `handle`, moves out its token by value, and calls `entry.refcount.put(token)`.
 //! - Branch-free Drop with `ManuallyDrop`: If the token must be held inside a type that implements `Drop` and the token isn’t owned by value at drop time, store it in `core::mem::ManuallyDrop<Token<...>>` and move it out in `Drop` via `ManuallyDrop::take` to avoid implicit drops and extra branches.
 //!
+//! - Scoped pattern: when the acquisition is lexical, `ScopedToken::new(&counter)` acquires a unit and returns it on drop, so no `put` can be forgotten.
+//!
+//! Custom counters
+//! - Users can plug their own per-entry counter (weighted, instrumented, …) into `CountedHashMap`/`RcHashMap` by implementing `Count` and `EntryCount`. An implementation mints its tokens with `Token::mint` after acquiring a unit and disposes of returned tokens with `Token::retire` after releasing one. `mint` is `unsafe` because tokens of some counters (`RcCount`) guard memory; minting one without acquiring its unit could free a live allocation.
+//!
 //! Owned + destructuring example
 //! ```rust
 //! use rc_hashmap::tokens::{Count, Token, UsizeCount};
@@ -77,13 +92,20 @@
 //!
 //! Implementation variants
 //! - UsizeCount: single-threaded counter using `Cell<usize>` to track outstanding user-facing references to an entry. Increment uses `wrapping_add` and aborts on wrap to 0 (matching `Rc`). Decrement asserts nonzero before subtracting. An `is_zero()` helper checks whether the current count is zero.
+//! - HighWaterCount: `UsizeCount` plus the highest count ever reached (`EntryCount::high_water`), at the cost of one extra `usize`.
+//! - CheckedCount: like `UsizeCount`, but overflow is detected before any update. `try_get` returns `Err(Overflow)`; `Count::get` panics with a deterministic message.
+//! - SaturatingCount: like `UsizeCount`, but the count sticks at `usize::MAX`; after saturation `put` no longer decrements, so the entry is never removed.
+//! - AtomicCount: `AtomicUsize`-backed counter with `Arc`-style orderings, for multi-threaded code. Its tokens are `Send + Sync` (tokens follow their counter's `Sync`).
 //! - RcCount<T>: encapsulates raw `Rc` strong-count inc/dec behind the `Count` interface. Unsafety is internal; callers only manipulate `Token`s. Construct via `RcCount::new(&rc)` or `RcCount::from_weak(&weak)`.
 //!
 //! Notes
 //! - Observing zero: `UsizeCount::put` returns a bool indicating whether the count reached zero. `RcCount::put` returns true iff the strong count was 1 before the decrement (typically false when the map itself also holds a strong `Rc`).
-//! - Single-threaded only: `UsizeCount` is not `Sync`, and `RcCount` inherits `Rc`’s `!Send + !Sync` semantics.
-//! - Overflow behavior (same as Rc): `UsizeCount::get` performs `wrapping_add(1)`, stores it, then aborts the process if the result is 0.
-//! - Debug-only behavior: `RcCount::{get,put}` include debug assertions on liveness via `Weak::strong_count()`. These checks are compiled out in release builds.
+//! - Single-threaded counters: `UsizeCount`, `CheckedCount` and `SaturatingCount` are not `Sync`, and `RcCount` inherits `Rc`’s `!Send + !Sync` semantics; their tokens are `!Send`. `AtomicCount` is the thread-safe building block.
+//! - Overflow behavior (same as Rc): `UsizeCount::get` performs `wrapping_add(1)`, stores it, then aborts the process if the result is 0. Maps that must not abort can use `CheckedCount` (panic or `Err(Overflow)`) or `SaturatingCount` (entry becomes immortal) as their per-entry counter instead.
+//! - Per-entry counters: `CountedHashMap` and `RcHashMap` are generic over an `EntryCount` (default `UsizeCount`), which adds construction and `count()` to `Count`.
+//! - Weak tokens: `RcCount::get_weak`/`put_weak` mint and return `Token<'_, Weak<T>>`, which hold a weak reference to the target. They keep the allocation but not the value alive, for non-owning references.
+//! - Liveness: `RcCount::get` acquires its strong reference with `Weak::upgrade` and panics if the target is already gone; `put` debug-asserts liveness via `Weak::strong_count()`.
+//! - Provenance: `RcCount` keeps no separate raw pointer; releases go through `Weak::as_ptr`, so it is strict-provenance clean. CI runs the token tests under Miri with `-Zmiri-strict-provenance`.
 //!
 //! Alternatives considered
 //! - Plain `usize` counts without tokens: relies on discipline and is easy to misuse (double `put`, missing `put` on early return). Tokens significantly reduce misuse by construction, but do not enforce per-instance branding.
@@ -91,29 +113,145 @@
 
 use core::cell::Cell;
 use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
 use std::rc::{Rc, Weak};
 
 /// Zero-sized, linear token tied to its originating counter via lifetime.
+///
+/// With the `token-provenance` feature, each token also carries a boxed
+/// record of where it was minted, reported if it is dropped without `put`.
 pub struct Token<'a, C: ?Sized> {
     // Lifetime is tracked separately from the counter type to avoid
     // imposing `'a` bounds on `C` (useful for generic counters).
     _lt: PhantomData<&'a ()>,
     _ctr: PhantomData<*const C>,
+    #[cfg(feature = "token-provenance")]
+    origin: Box<Provenance>,
+}
+
+/// Where a token was minted: the caller of the counter's `get` (or
+/// `get_n`/`adopt_raw`), plus a backtrace when `RUST_BACKTRACE` enables it.
+#[cfg(feature = "token-provenance")]
+struct Provenance {
+    location: &'static core::panic::Location<'static>,
+    backtrace: std::backtrace::Backtrace,
 }
 
 impl<'a, C: ?Sized> Token<'a, C> {
     #[inline]
+    #[track_caller]
     pub(crate) fn new() -> Self {
         Self {
             _lt: PhantomData,
             _ctr: PhantomData,
+            #[cfg(feature = "token-provenance")]
+            origin: Box::new(Provenance {
+                location: core::panic::Location::caller(),
+                backtrace: std::backtrace::Backtrace::capture(),
+            }),
         }
     }
+
+    /// Mint `n` tokens attributed to the same caller.
+    #[track_caller]
+    pub(crate) fn new_n(n: usize) -> Vec<Self> {
+        let mut v = Vec::with_capacity(n);
+        for _ in 0..n {
+            v.push(Self::new());
+        }
+        v
+    }
+
+    /// Dispose of a token whose unit its counter has already accounted for,
+    /// without running the panicking `Drop`.
+    #[inline]
+    pub(crate) fn consume(self) {
+        #[cfg(feature = "token-provenance")]
+        // SAFETY: `self` is forgotten right after, so `origin` is moved out
+        // exactly once.
+        drop(unsafe { core::ptr::read(&self.origin) });
+        core::mem::forget(self);
+    }
+}
+
+impl<'a, C: ?Sized> Token<'a, C> {
+    /// Mint a token for a unit the caller's counter has just acquired. For
+    /// `Count` implementations outside this crate.
+    ///
+    /// # Safety
+    /// `C` must be the calling counter's own type, and the unit must already
+    /// be accounted for: other counters (e.g. `RcCount`) release memory when
+    /// their tokens come back, so a fabricated token can cause use-after-free.
+    #[inline]
+    #[track_caller]
+    pub unsafe fn mint() -> Self {
+        Self::new()
+    }
+
+    /// Dispose of a token whose unit the counter has just released, without
+    /// the panicking `Drop`. For `Count::put` implementations outside this
+    /// crate; anywhere else it leaks the unit, like `into_raw`.
+    #[inline]
+    pub fn retire(self) {
+        self.consume();
+    }
+}
+
+// SAFETY: a token is a zero-sized marker with no access to its counter;
+// sending or sharing it is only a concern when the counter itself cannot be
+// used from another thread, so tokens follow their counter's `Sync`.
+unsafe impl<C: ?Sized + Sync> Send for Token<'_, C> {}
+unsafe impl<C: ?Sized + Sync> Sync for Token<'_, C> {}
+
+impl<'a, C: ?Sized> Token<'a, C> {
+    /// Detach the token from its lifetime and drop check, e.g. to carry it
+    /// through an FFI boundary or a type-erased callback. The unit stays
+    /// acquired; hand the `RawToken` back via `Count::adopt_raw` to recover
+    /// a `Token` and eventually `put` it.
+    #[inline]
+    pub fn into_raw(self) -> RawToken<C> {
+        self.consume();
+        RawToken { _ctr: PhantomData }
+    }
+}
+
+/// A token that left the linear-type discipline via `Token::into_raw`.
+/// Still branded to its counter type and neither `Copy` nor `Clone`, so
+/// each one can be adopted back at most once. Unlike `Token`, dropping it
+/// does not panic: it silently leaks the acquired unit.
+#[must_use = "a RawToken still holds a counted unit; adopt it back via Count::adopt_raw"]
+pub struct RawToken<C: ?Sized> {
+    _ctr: PhantomData<*const C>,
+}
+
+impl<C: ?Sized> RawToken<C> {
+    // The body of every in-crate `Count::adopt_raw`: the unit is still
+    // acquired, so only the linear token is restored.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn adopt(self) -> Token<'static, C> {
+        let RawToken { .. } = self;
+        Token::new()
+    }
 }
 
 impl<'a, C: ?Sized> Drop for Token<'a, C> {
     fn drop(&mut self) {
         // Intentional fail-fast on misuse: token must be consumed by Count::put.
+        #[cfg(feature = "token-provenance")]
+        {
+            use std::backtrace::BacktraceStatus;
+            let Provenance {
+                location,
+                backtrace,
+            } = &*self.origin;
+            if backtrace.status() == BacktraceStatus::Captured {
+                panic!("Token dropped without Count::put (minted at {location})\n{backtrace}");
+            }
+            panic!("Token dropped without Count::put (minted at {location})");
+        }
+        #[cfg(not(feature = "token-provenance"))]
         panic!("Token dropped without Count::put");
     }
 }
@@ -130,11 +268,169 @@ pub trait Count {
     /// We mint tokens with a 'static lifetime parameter. The token itself is
     /// still branded to this counter via its type parameter, and can be
     /// covariantly shortened when returning it via `put`.
+    #[track_caller]
     fn get(&self) -> Self::Token<'static>;
 
     /// Return (consume) a previously acquired token.
     /// Returns true if the count is now zero.
     fn put<'a>(&'a self, t: Self::Token<'a>) -> bool;
+
+    /// Turn a `RawToken` produced by `Token::into_raw` back into a token.
+    /// The count is not touched: the unit was never released.
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static>;
+
+    /// Returns true if exactly one unit is outstanding, i.e. the caller's
+    /// token is the only one. Upper layers use this for unique-access
+    /// operations without reaching into counter internals.
+    fn is_unique(&self) -> bool;
+
+    /// Acquire `n` tokens at once, e.g. for weighted references. The default
+    /// calls `get` `n` times; counters override it with a single update.
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        let mut v = Vec::with_capacity(n);
+        for _ in 0..n {
+            v.push(self.get());
+        }
+        v
+    }
+
+    /// Return a batch of tokens. Returns true if the batch took the count
+    /// to zero, so an empty batch always returns false, whatever the count.
+    /// Every implementation must follow this. The default calls `put` per
+    /// token.
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        tokens.into_iter().fold(false, |_, t| self.put(t))
+    }
+}
+
+/// A token that returns itself to its counter when dropped, for the common
+/// case where the acquisition scope is lexical. Holds the counter borrowed.
+/// Takes counters whose tokens are plain `Token`s, so the `'static` token
+/// from `get` can be returned through the borrow.
+pub struct ScopedToken<'a, C>
+where
+    C: for<'t> Count<Token<'t> = Token<'t, C>> + 'static,
+{
+    counter: &'a C,
+    token: ManuallyDrop<C::Token<'static>>,
+}
+
+impl<'a, C> ScopedToken<'a, C>
+where
+    C: for<'t> Count<Token<'t> = Token<'t, C>> + 'static,
+{
+    /// Acquire one unit from `counter` for the lifetime of the guard.
+    #[inline]
+    #[track_caller]
+    pub fn new(counter: &'a C) -> Self {
+        Self {
+            counter,
+            token: ManuallyDrop::new(counter.get()),
+        }
+    }
+
+    /// Detach the token; the caller takes over the `put` obligation.
+    #[inline]
+    pub fn into_token(self) -> C::Token<'static> {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so the token is moved out exactly once.
+        unsafe { ManuallyDrop::take(&mut this.token) }
+    }
+}
+
+impl<C> Drop for ScopedToken<'_, C>
+where
+    C: for<'t> Count<Token<'t> = Token<'t, C>> + 'static,
+{
+    fn drop(&mut self) {
+        // SAFETY: `token` is only taken here or in `into_token`, which
+        // suppresses this destructor.
+        let t = unsafe { ManuallyDrop::take(&mut self.token) };
+        let _ = self.counter.put(t);
+    }
+}
+
+/// Token minted through a `DynCount` trait object. Erasure loses the
+/// type-level brand, so the token records the address of the counter that
+/// minted it instead, and `put_erased` checks it. Like `Token`, it panics
+/// on drop.
+pub struct ErasedToken {
+    token: Token<'static, dyn DynCount>,
+    counter: *const (),
+}
+
+/// Dyn-compatible view of an `EntryCount`, for managing heterogeneous
+/// counters (e.g. a registry of differently typed maps sharing accounting)
+/// through trait objects. Blanket-implemented for every `EntryCount`.
+/// `RcCount` is deliberately excluded: its tokens guard memory, and a
+/// runtime check is no substitute for the type-level brand there.
+///
+/// A counter must stay at the same address (e.g. boxed) while erased
+/// tokens it minted are outstanding.
+pub trait DynCount {
+    /// Acquire one unit, returning an erased token.
+    fn get_erased(&self) -> ErasedToken;
+
+    /// Return an erased token. Returns true if the count is now zero.
+    /// Panics if `t` was minted by a different counter; that counter's
+    /// unit is then leaked rather than released here.
+    fn put_erased(&self, t: ErasedToken) -> bool;
+}
+
+impl<C: EntryCount> DynCount for C {
+    #[inline]
+    #[track_caller]
+    fn get_erased(&self) -> ErasedToken {
+        // Swap the concrete token for an erased one; the unit stays acquired.
+        let RawToken { .. } = self.get().into_raw();
+        ErasedToken {
+            token: Token::new(),
+            counter: (self as *const C).cast(),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn put_erased(&self, t: ErasedToken) -> bool {
+        let ErasedToken { token, counter } = t;
+        // Dispose of the erased token first, so a mismatch panics once
+        // instead of again from `Token::drop` while unwinding.
+        token.consume();
+        assert!(
+            core::ptr::eq(counter, (self as *const C).cast()),
+            "ErasedToken returned to a counter that did not mint it"
+        );
+        self.put(self.adopt_raw(RawToken { _ctr: PhantomData }))
+    }
+}
+
+/// A `Count` that can serve as the per-entry refcount of a `CountedHashMap`
+/// (and thus of `RcHashMap`). Adds construction and observation on top of
+/// the token flow.
+///
+/// Its tokens are `Token<'a, Self>`, which are covariant in `'a`, so generic
+/// code can return a `'static` token through `put`'s shorter borrow.
+pub trait EntryCount: for<'a> Count<Token<'a> = Token<'a, Self>> + Sized + 'static {
+    /// Create a counter with `initial` outstanding units.
+    fn with_count(initial: usize) -> Self;
+
+    /// Current number of outstanding tokens.
+    fn count(&self) -> usize;
+
+    /// Returns true if no tokens are outstanding.
+    #[inline]
+    fn is_zero(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Highest count ever observed, for counters that track it
+    /// (`HighWaterCount`); `None` otherwise.
+    #[inline]
+    fn high_water(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// Single-threaded reference counter for entries.
@@ -155,6 +451,12 @@ impl UsizeCount {
     pub fn is_zero(&self) -> bool {
         self.count.get() == 0
     }
+
+    /// Returns the current number of outstanding tokens.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count.get()
+    }
 }
 
 impl Count for UsizeCount {
@@ -164,6 +466,7 @@ impl Count for UsizeCount {
         Self: 'a;
 
     #[inline]
+    #[track_caller]
     fn get(&self) -> Self::Token<'static> {
         let c = self.count.get();
         let n = c.wrapping_add(1);
@@ -181,38 +484,528 @@ impl Count for UsizeCount {
         assert!(c > 0, "UsizeCount underflow");
         let n = c - 1;
         self.count.set(n);
-        core::mem::forget(t);
+        t.consume();
+        n == 0
+    }
+
+    #[inline]
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static> {
+        raw.adopt()
+    }
+
+    #[inline]
+    fn is_unique(&self) -> bool {
+        self.count.get() == 1
+    }
+
+    /// Single counter update; aborts on overflow like `get`.
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        let c = self.count.get();
+        let Some(next) = c.checked_add(n) else {
+            // Same policy as `get`: abort rather than continue unsafely.
+            std::process::abort();
+        };
+        self.count.set(next);
+        Token::new_n(n)
+    }
+
+    /// Single counter update.
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        let n = tokens.len();
+        let c = self.count.get();
+        assert!(c >= n, "UsizeCount underflow");
+        self.count.set(c - n);
+        for t in tokens {
+            t.consume();
+        }
+        n > 0 && c == n
+    }
+}
+
+impl EntryCount for UsizeCount {
+    #[inline]
+    fn with_count(initial: usize) -> Self {
+        Self::new(initial)
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        UsizeCount::count(self)
+    }
+
+    #[inline]
+    fn is_zero(&self) -> bool {
+        UsizeCount::is_zero(self)
+    }
+}
+
+/// `UsizeCount` that also records the highest count it ever reached, so
+/// diagnostics can report an entry's maximum fan-out. Costs one extra
+/// `usize` per counter; use it as a map's entry counter to opt in.
+#[derive(Debug)]
+pub struct HighWaterCount {
+    count: Cell<usize>,
+    peak: Cell<usize>,
+}
+
+impl HighWaterCount {
+    pub fn new(initial: usize) -> Self {
+        Self {
+            count: Cell::new(initial),
+            peak: Cell::new(initial),
+        }
+    }
+
+    #[inline]
+    fn set(&self, n: usize) {
+        self.count.set(n);
+        if n > self.peak.get() {
+            self.peak.set(n);
+        }
+    }
+}
+
+impl Count for HighWaterCount {
+    type Token<'a>
+        = Token<'a, Self>
+    where
+        Self: 'a;
+
+    #[inline]
+    #[track_caller]
+    fn get(&self) -> Self::Token<'static> {
+        let n = self.count.get().wrapping_add(1);
+        if n == 0 {
+            // Same policy as `UsizeCount`.
+            std::process::abort();
+        }
+        self.set(n);
+        Token::new()
+    }
+
+    #[inline]
+    fn put<'a>(&'a self, t: Self::Token<'a>) -> bool {
+        let c = self.count.get();
+        assert!(c > 0, "HighWaterCount underflow");
+        self.count.set(c - 1);
+        t.consume();
+        c == 1
+    }
+
+    #[inline]
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static> {
+        raw.adopt()
+    }
+
+    #[inline]
+    fn is_unique(&self) -> bool {
+        self.count.get() == 1
+    }
+
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        let Some(next) = self.count.get().checked_add(n) else {
+            std::process::abort();
+        };
+        self.set(next);
+        Token::new_n(n)
+    }
+
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        let n = tokens.len();
+        let c = self.count.get();
+        assert!(c >= n, "HighWaterCount underflow");
+        self.count.set(c - n);
+        for t in tokens {
+            t.consume();
+        }
+        n > 0 && c == n
+    }
+}
+
+impl EntryCount for HighWaterCount {
+    #[inline]
+    fn with_count(initial: usize) -> Self {
+        Self::new(initial)
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.count.get()
+    }
+
+    #[inline]
+    fn high_water(&self) -> Option<usize> {
+        Some(self.peak.get())
+    }
+}
+
+/// Error returned by `CheckedCount::try_get` when the count is saturated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Overflow;
+
+/// Single-threaded counter that detects overflow instead of relying on
+/// `UsizeCount`'s abort. `try_get` reports it as `Err(Overflow)`;
+/// `Count::get` panics deterministically. Either way the count is left
+/// unchanged and no token is minted, so nothing leaks.
+#[derive(Debug)]
+pub struct CheckedCount {
+    count: Cell<usize>,
+}
+
+impl CheckedCount {
+    pub fn new(initial: usize) -> Self {
+        Self {
+            count: Cell::new(initial),
+        }
+    }
+
+    /// Acquire one token, or `Err(Overflow)` if the count is at `usize::MAX`.
+    #[inline]
+    #[track_caller]
+    pub fn try_get(&self) -> Result<Token<'static, Self>, Overflow> {
+        let n = self.count.get().checked_add(1).ok_or(Overflow)?;
+        self.count.set(n);
+        Ok(Token::new())
+    }
+}
+
+impl Count for CheckedCount {
+    type Token<'a>
+        = Token<'a, Self>
+    where
+        Self: 'a;
+
+    #[inline]
+    #[track_caller]
+    fn get(&self) -> Self::Token<'static> {
+        self.try_get().expect("CheckedCount overflow")
+    }
+
+    #[inline]
+    fn put<'a>(&'a self, t: Self::Token<'a>) -> bool {
+        let c = self.count.get();
+        assert!(c > 0, "CheckedCount underflow");
+        let n = c - 1;
+        self.count.set(n);
+        t.consume();
         n == 0
     }
+
+    #[inline]
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static> {
+        raw.adopt()
+    }
+
+    #[inline]
+    fn is_unique(&self) -> bool {
+        self.count.get() == 1
+    }
+
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        let next = self
+            .count
+            .get()
+            .checked_add(n)
+            .expect("CheckedCount overflow");
+        self.count.set(next);
+        Token::new_n(n)
+    }
+
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        let n = tokens.len();
+        let c = self.count.get();
+        assert!(c >= n, "CheckedCount underflow");
+        self.count.set(c - n);
+        for t in tokens {
+            t.consume();
+        }
+        n > 0 && c == n
+    }
+}
+
+impl EntryCount for CheckedCount {
+    #[inline]
+    fn with_count(initial: usize) -> Self {
+        Self::new(initial)
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.count.get()
+    }
 }
 
-/// Rc-backed manual counter. Uses raw-pointer strong count manipulation.
+/// Single-threaded counter that saturates instead of overflowing. Once the
+/// count reaches `usize::MAX` it stays there: `get` keeps minting tokens and
+/// `put` no longer decrements, so an entry using it becomes immortal (never
+/// reported as reaching zero).
+#[derive(Debug)]
+pub struct SaturatingCount {
+    count: Cell<usize>,
+}
+
+impl SaturatingCount {
+    pub fn new(initial: usize) -> Self {
+        Self {
+            count: Cell::new(initial),
+        }
+    }
+
+    /// Returns true once the count has stuck at `usize::MAX`.
+    #[inline]
+    pub fn is_saturated(&self) -> bool {
+        self.count.get() == usize::MAX
+    }
+}
+
+impl Count for SaturatingCount {
+    type Token<'a>
+        = Token<'a, Self>
+    where
+        Self: 'a;
+
+    #[inline]
+    #[track_caller]
+    fn get(&self) -> Self::Token<'static> {
+        self.count.set(self.count.get().saturating_add(1));
+        Token::new()
+    }
+
+    #[inline]
+    fn put<'a>(&'a self, t: Self::Token<'a>) -> bool {
+        t.consume();
+        let c = self.count.get();
+        if c == usize::MAX {
+            return false;
+        }
+        assert!(c > 0, "SaturatingCount underflow");
+        self.count.set(c - 1);
+        c == 1
+    }
+
+    #[inline]
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static> {
+        raw.adopt()
+    }
+
+    #[inline]
+    fn is_unique(&self) -> bool {
+        // A saturated count is immortal, never unique.
+        self.count.get() == 1
+    }
+
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        self.count.set(self.count.get().saturating_add(n));
+        Token::new_n(n)
+    }
+
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        let n = tokens.len();
+        for t in tokens {
+            t.consume();
+        }
+        let c = self.count.get();
+        if c == usize::MAX {
+            return false;
+        }
+        assert!(c >= n, "SaturatingCount underflow");
+        self.count.set(c - n);
+        n > 0 && c == n
+    }
+}
+
+impl EntryCount for SaturatingCount {
+    #[inline]
+    fn with_count(initial: usize) -> Self {
+        Self::new(initial)
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.count.get()
+    }
+}
+
+/// Thread-safe counter backed by an `AtomicUsize`, with the same token
+/// discipline as `UsizeCount`. Its tokens are `Send + Sync`, so a unit can
+/// be acquired on one thread and returned on another. Follows `Arc`'s
+/// orderings: relaxed increments, release decrements, and an acquire fence
+/// when the count reaches zero.
+#[derive(Debug)]
+pub struct AtomicCount {
+    count: AtomicUsize,
+}
+
+impl AtomicCount {
+    pub fn new(initial: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(initial),
+        }
+    }
+
+    // Release `n` units, refusing to go below zero so an underflow panic
+    // leaves the count as it was. Returns true if the count reached zero.
+    #[inline]
+    fn release(&self, n: usize) -> bool {
+        let old = self
+            .count
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |c| c.checked_sub(n))
+            .unwrap_or_else(|_| panic!("AtomicCount underflow"));
+        if old == n {
+            fence(Ordering::Acquire);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Like `Arc`, abort well before wrapping so concurrent increments racing
+// past the check still cannot reach `usize::MAX`.
+const ATOMIC_COUNT_MAX: usize = isize::MAX as usize;
+
+impl Count for AtomicCount {
+    type Token<'a>
+        = Token<'a, Self>
+    where
+        Self: 'a;
+
+    #[inline]
+    #[track_caller]
+    fn get(&self) -> Self::Token<'static> {
+        if self.count.fetch_add(1, Ordering::Relaxed) > ATOMIC_COUNT_MAX {
+            std::process::abort();
+        }
+        Token::new()
+    }
+
+    #[inline]
+    fn put<'a>(&'a self, t: Self::Token<'a>) -> bool {
+        t.consume();
+        self.release(1)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static> {
+        raw.adopt()
+    }
+
+    #[inline]
+    fn is_unique(&self) -> bool {
+        self.count.load(Ordering::Acquire) == 1
+    }
+
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        if n > ATOMIC_COUNT_MAX || self.count.fetch_add(n, Ordering::Relaxed) > ATOMIC_COUNT_MAX - n
+        {
+            std::process::abort();
+        }
+        Token::new_n(n)
+    }
+
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        let n = tokens.len();
+        for t in tokens {
+            t.consume();
+        }
+        n > 0 && self.release(n)
+    }
+}
+
+impl EntryCount for AtomicCount {
+    #[inline]
+    fn with_count(initial: usize) -> Self {
+        Self::new(initial)
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+}
+
+/// Rc-backed manual counter. Each token stands for one strong reference
+/// obtained with `Weak::upgrade` and leaked with `mem::forget`; `put` gives
+/// it back through the pointer returned by `Weak::as_ptr`, so every raw
+/// pointer keeps the provenance of the original allocation.
 pub struct RcCount<T> {
-    ptr: *const T,
     weak: Weak<T>,
     _nosend: PhantomData<*mut ()>,
 }
 
 impl<T> RcCount<T> {
     pub fn new(rc: &Rc<T>) -> Self {
-        let weak = Rc::downgrade(rc);
-        let raw = Rc::into_raw(rc.clone());
-        unsafe { Rc::decrement_strong_count(raw) };
         Self {
-            ptr: raw,
-            weak,
+            weak: Rc::downgrade(rc),
             _nosend: PhantomData,
         }
     }
 
     pub fn from_weak(weak: &Weak<T>) -> Self {
-        let raw = weak.as_ptr();
         Self {
-            ptr: raw,
             weak: weak.clone(),
             _nosend: PhantomData,
         }
     }
+
+    /// Acquire a weak reference to the target: it keeps the allocation (not
+    /// the value) alive, for non-owning handles that must not extend the
+    /// target's lifetime. Return it with `put_weak`.
+    #[inline]
+    #[track_caller]
+    pub fn get_weak(&self) -> Token<'static, Weak<T>> {
+        let _ = Weak::into_raw(self.weak.clone());
+        Token::new()
+    }
+
+    /// Return a weak token acquired by `get_weak`.
+    #[inline]
+    pub fn put_weak(&self, t: Token<'_, Weak<T>>) {
+        t.consume();
+        // SAFETY: `t` proves one weak reference was leaked via
+        // `Weak::into_raw` in `get_weak`; `as_ptr` yields the same pointer.
+        drop(unsafe { Weak::from_raw(self.weak.as_ptr()) });
+    }
+
+    // Acquire one strong reference without touching raw pointers.
+    #[inline]
+    #[track_caller]
+    fn leak_strong(&self) {
+        let rc = self
+            .weak
+            .upgrade()
+            .expect("RcCount used after its Rc was dropped");
+        core::mem::forget(rc);
+    }
+
+    // Give back one strong reference acquired by `leak_strong`.
+    //
+    // SAFETY: the caller must hold a token for this counter, i.e. a strong
+    // reference previously leaked by `leak_strong` and not yet released.
+    #[inline]
+    unsafe fn release_strong(&self) {
+        Rc::decrement_strong_count(self.weak.as_ptr());
+    }
+}
+
+/// Clones share the same target. When a `put` may release the last strong
+/// reference and the counter itself lives inside that allocation, return
+/// the token through a local clone so no reference into the allocation is
+/// live while it is dropped.
+impl<T> Clone for RcCount<T> {
+    fn clone(&self) -> Self {
+        Self::from_weak(&self.weak)
+    }
 }
 
 impl<T: 'static> Count for RcCount<T> {
@@ -222,9 +1015,9 @@ impl<T: 'static> Count for RcCount<T> {
         Self: 'a;
 
     #[inline]
+    #[track_caller]
     fn get(&self) -> Self::Token<'static> {
-        debug_assert!(self.weak.strong_count() > 0);
-        unsafe { Rc::increment_strong_count(self.ptr) };
+        self.leak_strong();
         Token::<'static, Self>::new()
     }
 
@@ -232,10 +1025,52 @@ impl<T: 'static> Count for RcCount<T> {
     fn put<'a>(&'a self, t: Self::Token<'a>) -> bool {
         debug_assert!(self.weak.strong_count() > 0);
         let was_one = self.weak.strong_count() == 1;
-        unsafe { Rc::decrement_strong_count(self.ptr) };
-        core::mem::forget(t);
+        t.consume();
+        // SAFETY: `t` proves one strong reference was leaked by `get`.
+        unsafe { self.release_strong() };
         was_one
     }
+
+    #[inline]
+    #[track_caller]
+    fn adopt_raw(&self, raw: RawToken<Self>) -> Self::Token<'static> {
+        raw.adopt()
+    }
+
+    #[inline]
+    fn is_unique(&self) -> bool {
+        self.weak.strong_count() == 1
+    }
+
+    /// `Rc` has no bulk adjustment, so this still increments `n` times, but
+    /// upgrades the `Weak` only once.
+    #[track_caller]
+    fn get_n(&self, n: usize) -> Vec<Self::Token<'static>> {
+        if n > 0 {
+            let rc = self
+                .weak
+                .upgrade()
+                .expect("RcCount used after its Rc was dropped");
+            for _ in 1..n {
+                core::mem::forget(Rc::clone(&rc));
+            }
+            core::mem::forget(rc);
+        }
+        Token::new_n(n)
+    }
+
+    /// Returns true iff the strong count was exactly `n` before the batch.
+    fn put_n<'a>(&'a self, tokens: Vec<Self::Token<'a>>) -> bool {
+        let n = tokens.len();
+        let strong = self.weak.strong_count();
+        debug_assert!(n == 0 || strong >= n);
+        for t in tokens {
+            t.consume();
+            // SAFETY: each token proves one strong reference was leaked.
+            unsafe { self.release_strong() };
+        }
+        n > 0 && strong == n
+    }
 }
 
 #[cfg(test)]
@@ -253,6 +1088,19 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[cfg(feature = "token-provenance")]
+    #[test]
+    /// Invariant: with `token-provenance`, the drop panic names the place
+    /// the token was minted.
+    fn token_drop_panic_reports_origin() {
+        let c = UsizeCount::new(0);
+        let line = line!() + 1;
+        let t = c.get();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(t)));
+        let msg = res.unwrap_err().downcast::<String>().unwrap();
+        assert!(msg.contains(&format!("{}:{}", file!(), line)), "{msg}");
+    }
+
     #[test]
     /// Invariant: `UsizeCount` reflects the exact number of outstanding
     /// tokens, and `put` returns true when the count reaches zero.
@@ -266,6 +1114,296 @@ mod tests {
         assert!(c.is_zero());
     }
 
+    #[test]
+    /// Invariant: `get_n`/`put_n` move the count by exactly `n` and `put_n`
+    /// reports reaching zero like `put`.
+    fn usizecount_bulk_get_put() {
+        let c = UsizeCount::new(0);
+        let t = c.get();
+        let batch = c.get_n(4);
+        assert_eq!(batch.len(), 4);
+        assert_eq!(c.count(), 5);
+        assert!(!c.put_n(batch));
+        assert_eq!(c.count(), 1);
+        assert!(c.put_n(vec![t]));
+        assert!(!c.put_n(Vec::new()));
+    }
+
+    #[test]
+    /// Invariant: every counter's `put_n` returns false for an empty batch,
+    /// even at zero count.
+    fn put_n_empty_batch_is_false_everywhere() {
+        fn check<C: EntryCount>() {
+            let c = C::with_count(0);
+            assert!(!c.put_n(Vec::new()));
+            assert!(c.is_zero());
+        }
+        check::<UsizeCount>();
+        check::<HighWaterCount>();
+        check::<CheckedCount>();
+        check::<SaturatingCount>();
+        check::<AtomicCount>();
+    }
+
+    #[test]
+    /// Invariant: `CheckedCount` refuses to mint past `usize::MAX`, leaving
+    /// the count unchanged, and `get` panics instead of wrapping.
+    fn checkedcount_reports_overflow() {
+        let c = CheckedCount::new(usize::MAX - 1);
+        let t = c.try_get().expect("one below max");
+        assert!(matches!(c.try_get(), Err(Overflow)));
+        assert_eq!(EntryCount::count(&c), usize::MAX);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| c.put(c.get())));
+        assert!(res.is_err());
+        assert!(!c.put(t));
+        assert_eq!(EntryCount::count(&c), usize::MAX - 1);
+    }
+
+    #[test]
+    /// Invariant: `SaturatingCount` sticks at `usize::MAX`; once saturated,
+    /// returning tokens never decrements or reports zero.
+    fn saturatingcount_sticks_at_max() {
+        let c = SaturatingCount::new(usize::MAX - 1);
+        let t1 = c.get();
+        let t2 = c.get();
+        assert!(c.is_saturated());
+        assert!(!c.put(t1));
+        assert!(!c.put(t2));
+        assert!(c.is_saturated());
+
+        let c = SaturatingCount::new(0);
+        let t = c.get();
+        assert!(c.put(t));
+    }
+
+    #[test]
+    /// Invariant: `into_raw`/`adopt_raw` round-trips a token without
+    /// touching the count or triggering the drop panic.
+    fn raw_token_roundtrip() {
+        let c = UsizeCount::new(0);
+        let raw = c.get().into_raw();
+        assert_eq!(c.count(), 1);
+        let t = c.adopt_raw(raw);
+        assert_eq!(c.count(), 1);
+        assert!(c.put(t));
+    }
+
+    #[test]
+    /// Invariant: `RcCount::get_n`/`put_n` move the strong count by `n`.
+    fn rccount_bulk_get_put() {
+        let rc = Rc::new(());
+        let weak = Rc::downgrade(&rc);
+        let c = RcCount::new(&rc);
+        let batch = c.get_n(3);
+        assert_eq!(weak.strong_count(), 4);
+        assert!(!c.put_n(batch));
+        assert_eq!(weak.strong_count(), 1);
+    }
+
+    #[test]
+    /// Invariant: counters of different types can be driven through
+    /// `dyn DynCount`, and erased tokens keep each count balanced.
+    fn dyn_count_heterogeneous() {
+        let counters: Vec<Box<dyn DynCount>> = vec![
+            Box::new(UsizeCount::new(0)),
+            Box::new(CheckedCount::new(1)),
+            Box::new(AtomicCount::new(0)),
+        ];
+        let tokens: Vec<ErasedToken> = counters.iter().map(|c| c.get_erased()).collect();
+        let zeroed: Vec<bool> = counters
+            .iter()
+            .zip(tokens)
+            .map(|(c, t)| c.put_erased(t))
+            .collect();
+        assert_eq!(zeroed, vec![true, false, true]);
+    }
+
+    #[test]
+    #[should_panic(expected = "did not mint it")]
+    /// Invariant: an erased token cannot be returned to another counter.
+    fn dyn_count_rejects_foreign_token() {
+        let a: Box<dyn DynCount> = Box::new(UsizeCount::new(0));
+        let b: Box<dyn DynCount> = Box::new(UsizeCount::new(1));
+        let t = a.get_erased();
+        b.put_erased(t);
+    }
+
+    #[test]
+    /// Invariant: `AtomicCount` tokens can be returned from other threads,
+    /// and exactly one `put` observes the transition to zero.
+    fn atomiccount_cross_thread_balance() {
+        let c = AtomicCount::new(0);
+        let batches: Vec<Vec<Token<'static, AtomicCount>>> = (0..4).map(|_| c.get_n(8)).collect();
+        assert_eq!(c.count(), 32);
+        let counter = &c;
+        let zeroes: usize = std::thread::scope(|s| {
+            let workers: Vec<_> = batches
+                .into_iter()
+                .map(|batch| {
+                    s.spawn(move || {
+                        batch
+                            .into_iter()
+                            .map(|t| counter.put(t))
+                            .filter(|&z| z)
+                            .count()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).sum()
+        });
+        assert_eq!(zeroes, 1);
+        assert!(counter.is_zero());
+    }
+
+    #[test]
+    /// Invariant: an `AtomicCount` underflow panics without wrapping, so
+    /// the count stays at zero.
+    fn atomiccount_underflow_leaves_count_unchanged() {
+        let c = AtomicCount::new(0);
+        let t = c.adopt_raw(RawToken { _ctr: PhantomData });
+        let res = std::panic::catch_unwind(|| c.put(t));
+        assert!(res.is_err());
+        assert_eq!(c.count(), 0);
+        let t = c.get();
+        let extra = c.adopt_raw(RawToken { _ctr: PhantomData });
+        let res = std::panic::catch_unwind(|| c.put_n(vec![t, extra]));
+        assert!(res.is_err());
+        assert_eq!(c.count(), 1);
+        let t = c.adopt_raw(RawToken { _ctr: PhantomData });
+        assert!(c.put(t));
+    }
+
+    #[test]
+    /// Invariant: `ScopedToken` returns its unit on drop; `into_token`
+    /// hands the obligation back to the caller instead.
+    fn scoped_token_returns_on_drop() {
+        let c = UsizeCount::new(0);
+        {
+            let _a = ScopedToken::new(&c);
+            let _b = ScopedToken::new(&c);
+            assert_eq!(c.count(), 2);
+        }
+        assert!(c.is_zero());
+        let t = ScopedToken::new(&c).into_token();
+        assert_eq!(c.count(), 1);
+        assert!(c.put(t));
+    }
+
+    #[test]
+    /// Invariant: `HighWaterCount` remembers the maximum simultaneous count
+    /// across single and bulk acquisitions; plain counters report `None`.
+    fn highwatercount_tracks_peak() {
+        let c = HighWaterCount::new(0);
+        let a = c.get();
+        let batch = c.get_n(3);
+        assert!(!c.put_n(batch));
+        let b = c.get();
+        assert_eq!(c.high_water(), Some(4));
+        assert!(!c.put(a));
+        assert!(c.put(b));
+        assert_eq!(c.high_water(), Some(4));
+        assert_eq!(UsizeCount::new(0).high_water(), None);
+    }
+
+    #[test]
+    /// Invariant: `RcCount::get` acquires through `Weak::upgrade`, so it
+    /// refuses to resurrect a target whose last strong reference is gone.
+    fn rccount_get_after_drop_panics() {
+        let rc = Rc::new(5);
+        let c = RcCount::new(&rc);
+        drop(rc);
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| c.get().into_raw()));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    /// Invariant: weak tokens move only the weak count and stay valid after
+    /// the last strong reference is gone.
+    fn rccount_weak_tokens() {
+        let rc = Rc::new(());
+        let weak = Rc::downgrade(&rc);
+        let c = RcCount::new(&rc);
+        let w = c.get_weak();
+        assert_eq!(weak.weak_count(), 3);
+        assert_eq!(weak.strong_count(), 1);
+        drop(rc);
+        assert!(weak.upgrade().is_none());
+        c.put_weak(w);
+        assert_eq!(Weak::weak_count(&weak), 0);
+    }
+
+    #[test]
+    /// Invariant: `is_unique` holds exactly when one unit is outstanding.
+    fn is_unique_means_single_unit() {
+        let c = UsizeCount::new(0);
+        assert!(!c.is_unique());
+        let a = c.get();
+        assert!(c.is_unique());
+        let b = c.get();
+        assert!(!c.is_unique());
+        assert!(c.put_n(vec![a, b]));
+
+        let rc = Rc::new(());
+        let rcc = RcCount::new(&rc);
+        assert!(rcc.is_unique());
+        let t = rcc.get();
+        assert!(!rcc.is_unique());
+        rcc.put(t);
+    }
+
+    /// Invariant: a counter defined outside the crate can be used as a
+    /// map's per-entry count through `Token::mint`/`retire`.
+    #[test]
+    fn custom_counter_drives_counted_map() {
+        use crate::counted_hash_map::{CountedHashMap, PutResult};
+        use crate::hash::DefaultHashBuilder;
+
+        // Counts every get, e.g. to find hot entries.
+        std::thread_local!(static GETS: Cell<usize> = const { Cell::new(0) });
+        struct Instrumented(Cell<usize>);
+        impl Count for Instrumented {
+            type Token<'a> = Token<'a, Self>;
+            fn get(&self) -> Token<'static, Self> {
+                self.0.set(self.0.get() + 1);
+                GETS.with(|g| g.set(g.get() + 1));
+                // SAFETY: the unit was just acquired by this counter.
+                unsafe { Token::mint() }
+            }
+            fn put<'a>(&'a self, t: Token<'a, Self>) -> bool {
+                self.0.set(self.0.get() - 1);
+                t.retire();
+                self.0.get() == 0
+            }
+            fn adopt_raw(&self, raw: RawToken<Self>) -> Token<'static, Self> {
+                let _ = raw;
+                // SAFETY: the raw token's unit is still acquired.
+                unsafe { Token::mint() }
+            }
+            fn is_unique(&self) -> bool {
+                self.0.get() == 1
+            }
+        }
+        impl EntryCount for Instrumented {
+            fn with_count(initial: usize) -> Self {
+                Self(Cell::new(initial))
+            }
+            fn count(&self) -> usize {
+                self.0.get()
+            }
+        }
+
+        let mut m: CountedHashMap<&str, i32, DefaultHashBuilder, Instrumented> =
+            CountedHashMap::default();
+        let a = m.insert("a", 1).unwrap();
+        let b = m.find(&"a").unwrap();
+        let c = m.get(&b);
+        assert_eq!(m.refcount(&c), 3);
+        assert_eq!(GETS.with(|g| g.get()), 3);
+        assert!(matches!(m.put(a), PutResult::Live { .. }));
+        assert!(matches!(m.put(b), PutResult::Live { .. }));
+        assert!(matches!(m.put(c), PutResult::Removed { .. }));
+    }
+
     #[test]
     /// Invariant: `RcCount` increments the underlying `Rc` strong count on
     /// `get` and decrements it on `put`. For a live `Rc`, `put` never
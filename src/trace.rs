@@ -0,0 +1,104 @@
+//! Entry lifecycle tracing (`lifecycle-trace` feature).
+//!
+//! Debugging aid for "why is this entry still alive?" (or "why did it go
+//! away?"): `RcHashMap::trace_key` selects one key, and every insert, Ref
+//! mint, Ref drop and removal of the entry under that key is appended to a
+//! bounded ring buffer that `RcHashMap::trace_events` returns. The selection
+//! survives removal, so a re-inserted entry under the same key is traced too.
+//!
+//! Events carry the entry's refcount after the operation and a sequence
+//! number; a gap in sequence numbers means older events were overwritten.
+//! Pair with `token-provenance` to also learn where each Ref was minted.
+
+use crate::handle_hash_map::Handle;
+use std::collections::VecDeque;
+
+/// Number of events kept; older events are overwritten.
+pub const TRACE_CAPACITY: usize = 256;
+
+/// What happened to the traced entry.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TraceOp {
+    /// The entry was inserted; its first Ref was minted.
+    Insert,
+    /// A Ref was minted by a lookup, clone or iterator.
+    Get,
+    /// A Ref was dropped and the entry stayed live.
+    Put,
+    /// The last Ref was dropped and the entry was removed.
+    Remove,
+}
+
+/// One recorded operation on the traced entry.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// Position in the trace, counting from 0 at `trace_key`.
+    pub seq: u64,
+    pub op: TraceOp,
+    /// Refcount of the entry after the operation.
+    pub refs: usize,
+}
+
+// Selected key, the live entry it resolves to, and the recorded events.
+pub(crate) struct Trace<K> {
+    key: Option<K>,
+    target: Option<Handle>,
+    next_seq: u64,
+    events: VecDeque<TraceEvent>,
+}
+
+impl<K: Eq> Trace<K> {
+    pub(crate) fn new() -> Self {
+        Self {
+            key: None,
+            target: None,
+            next_seq: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    // Select `key`, resolved to `target` if it is currently present, and
+    // discard earlier events.
+    pub(crate) fn select(&mut self, key: K, target: Option<Handle>) {
+        *self = Self {
+            key: Some(key),
+            target,
+            ..Self::new()
+        };
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // True if a newly inserted entry under `key` should become the target.
+    pub(crate) fn wants(&self, key: &K) -> bool {
+        self.target.is_none() && self.key.as_ref() == Some(key)
+    }
+
+    pub(crate) fn is_target(&self, handle: Handle) -> bool {
+        self.target == Some(handle)
+    }
+
+    pub(crate) fn record(&mut self, handle: Handle, op: TraceOp, refs: usize) {
+        match op {
+            TraceOp::Insert => self.target = Some(handle),
+            _ if !self.is_target(handle) => return,
+            TraceOp::Remove => self.target = None,
+            _ => {}
+        }
+        if self.events.len() == TRACE_CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(TraceEvent {
+            seq: self.next_seq,
+            op,
+            refs,
+        });
+        self.next_seq += 1;
+    }
+
+    pub(crate) fn events(&self) -> Vec<TraceEvent> {
+        self.events.iter().copied().collect()
+    }
+}
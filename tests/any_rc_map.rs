@@ -0,0 +1,27 @@
+// AnyRcMap test suite.
+//
+// AnyRcMap is RcHashMap keyed by TypeId; these tests cover the per-type
+// surface rather than re-testing the map's liveness machinery.
+use rc_hashmap::{AnyRcMap, InsertError};
+
+// Test: one value per type.
+// Assumes: a type's entry is present iff ≥1 outstanding Ref.
+// Verifies: a second insert of the same type fails, distinct types coexist,
+// and each type's entry goes away with its last Ref.
+#[test]
+fn one_value_per_type() {
+    let mut m = AnyRcMap::new();
+    let a = m.insert(1u32).unwrap();
+    assert!(matches!(m.insert(2u32), Err(InsertError::DuplicateKey)));
+    let b = m.insert("text").unwrap();
+    assert_eq!(m.len(), 2);
+    assert_eq!(*m.get(&a).unwrap(), 1);
+    assert_eq!(*m.get(&m.find::<&str>().unwrap()).unwrap(), "text");
+    assert!(m.find::<u64>().is_none());
+
+    drop(a);
+    assert!(!m.contains::<u32>());
+    assert!(m.contains::<&str>());
+    drop(b);
+    assert!(m.is_empty());
+}
@@ -10,7 +10,10 @@
 // - Borrowing: holding &V or &mut V does not prevent unrelated removals.
 // - Drop ordering: unlink-before-drop allows nested cascades via drops
 //   of Refs held in keys/values (DAG scenarios).
-use rc_hashmap::{InsertError, RcHashMap, Ref};
+use rc_hashmap::{
+    AccessError, Cache, CacheStats, CappedInsertError, RcHashMap, Ref, TryInsertError,
+    WeakRcHashMap,
+};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
@@ -53,7 +56,8 @@ fn duplicate_insert_rejected() {
     let r = m.insert("dup".to_string(), 1).unwrap();
     let e = m.insert("dup".to_string(), 2);
     match e {
-        Err(InsertError::DuplicateKey) => {}
+        Err(CappedInsertError::DuplicateKey) => {}
+        Err(CappedInsertError::CapacityExceeded) => panic!("map is uncapped"),
         Ok(_) => panic!("expected duplicate insert to error"),
     }
     drop(r);
@@ -81,7 +85,7 @@ fn ref_equality_and_hash() {
 
 // Test: owner identity enforcement in accessors.
 // Assumes: accessors require the same RcHashMap instance.
-// Verifies: using a Ref with a different map returns Err(WrongMap).
+// Verifies: using a Ref with a different map returns Err(WrongOwner).
 #[test]
 fn wrong_map_accessors_reject() {
     let mut m1 = RcHashMap::new();
@@ -93,8 +97,8 @@ fn wrong_map_accessors_reject() {
     assert!(r.key(&m1).is_ok());
     assert!(r.value_mut(&mut m1).is_ok());
 
-    // Wrong map should be rejected
-    assert!(r.value(&m2).is_err());
+    // Wrong map should be rejected as misuse, not staleness
+    assert_eq!(r.value(&m2).err(), Some(AccessError::WrongOwner));
     assert!(r.key(&m2).is_err());
 }
 
@@ -458,3 +462,1347 @@ fn refs_survive_map_drop_and_can_clone_then_drop() {
     drop(r2);
     drop(r3);
 }
+
+// Test: RcHashMap parameterized over a non-default per-entry counter.
+// Assumes: `CheckedCount` follows the same token discipline as `UsizeCount`.
+// Verifies: clone/drop keep and release the entry exactly as with the default.
+#[test]
+fn checked_count_map_behaves_like_default() {
+    use rc_hashmap::tokens::CheckedCount;
+    let mut m: RcHashMap<String, i32, rc_hashmap::DefaultHashBuilder, CheckedCount> =
+        RcHashMap::default();
+    let r = m.insert("k".into(), 1).unwrap();
+    let r2 = r.clone();
+    drop(r);
+    assert_eq!(*r2.value(&m).unwrap(), 1);
+    drop(r2);
+    assert!(m.is_empty());
+}
+
+// Test: RcHashMap through the generic Cache interface.
+// Assumes: residency is governed by outstanding Refs, not by the cache.
+// Verifies: hit/miss accounting, and invalidate leaves Ref-held entries resident.
+#[test]
+fn cache_trait_get_or_insert_and_invalidate() {
+    fn load<C: Cache<String, usize>>(c: &mut C, k: &str) -> C::Ref {
+        c.get_or_insert_with(k.to_string(), || k.len()).unwrap()
+    }
+    let mut m = RcHashMap::new();
+    let a = load(&mut m, "abc");
+    let a2 = load(&mut m, "abc");
+    let b = load(&mut m, "de");
+    assert_eq!(*a2.value(&m).unwrap(), 3);
+    assert_eq!(Cache::len(&m), 2);
+    assert_eq!(m.stats(), CacheStats { hits: 1, misses: 2 });
+
+    assert!(!m.invalidate("abc"), "held entry stays resident");
+    drop((a, a2));
+    assert!(m.invalidate("abc"));
+    assert_eq!(Cache::len(&m), 1);
+    drop(b);
+    assert!(Cache::is_empty(&m));
+}
+
+// Test: Cache::invalidate on a pinned entry.
+// Assumes: a pin is the map's own hold on an entry.
+// Verifies: invalidate releases the pin, removing the entry at once when
+// unreferenced and after the last outside Ref otherwise.
+#[test]
+fn cache_invalidate_releases_pin() {
+    let mut m: RcHashMap<&str, u32> = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    assert!(m.pin("a"));
+    drop(a);
+    assert!(m.invalidate("a"), "pinned-only entry is released");
+    assert!(m.is_empty());
+
+    let b = m.insert("b", 2).unwrap();
+    assert!(m.pin("b"));
+    assert!(!m.invalidate("b"), "outside Ref keeps it resident");
+    drop(b);
+    assert!(m.is_empty(), "pin was released by invalidate");
+}
+
+// Test: memory footprint estimate.
+// Assumes: storage grows with the number of live entries.
+// Verifies: allocated_bytes is nonzero when empty and grows with inserts.
+#[test]
+fn allocated_bytes_grows_with_entries() {
+    let mut m = RcHashMap::new();
+    let empty = m.allocated_bytes();
+    assert!(empty > 0);
+    let refs: Vec<_> = (0..1000u64).map(|i| m.insert(i, i).unwrap()).collect();
+    assert!(m.allocated_bytes() > empty + 1000 * 2 * core::mem::size_of::<u64>());
+    drop(refs);
+}
+
+// Test: projecting a Ref onto a field of its value.
+// Assumes: a MappedRef holds the entry's count like the Ref it came from.
+// Verifies: get() sees only the field, keeps the entry alive, and checks the owner.
+#[test]
+fn map_value_projects_and_keeps_alive() {
+    struct User {
+        name: String,
+        #[allow(dead_code)]
+        secret: u64,
+    }
+    let mut m = RcHashMap::new();
+    let r = m
+        .insert(
+            1u32,
+            User {
+                name: "ann".to_string(),
+                secret: 7,
+            },
+        )
+        .unwrap();
+    let name = r.map_value(|u| u.name.as_str());
+    assert_eq!(name.get(&m).unwrap(), "ann");
+
+    let name2 = name.clone();
+    drop(name);
+    assert_eq!(m.len(), 1, "projection keeps the entry alive");
+
+    let other: RcHashMap<u32, User> = RcHashMap::new();
+    assert!(name2.get(&other).is_err());
+
+    let back = name2.into_ref();
+    assert_eq!(back.value(&m).unwrap().name, "ann");
+    drop(back);
+    assert!(m.is_empty());
+}
+
+// Test: map_value with a capturing closure.
+// Assumes: the projection is rerun on every get.
+// Verifies: the captured index selects the field, and the projection follows value_mut.
+#[test]
+fn map_value_accepts_capturing_closure() {
+    let mut m = RcHashMap::new();
+    let r = m.insert(1u32, vec![10u32, 20, 30]).unwrap();
+    let idx = 2;
+    let third = r.clone().map_value(move |v: &Vec<u32>| &v[idx]);
+    assert_eq!(*third.get(&m).unwrap(), 30);
+
+    *r.value_mut(&mut m).unwrap() = vec![1, 2, 3, 4];
+    assert_eq!(*third.get(&m).unwrap(), 3);
+    assert_eq!(*third.clone().get(&m).unwrap(), 3);
+}
+
+// Test: load-and-update through find_mut.
+// Assumes: find_mut mints a Ref like find.
+// Verifies: the value is updated in place and the returned Ref keeps the entry alive.
+#[test]
+fn find_mut_returns_ref_and_mut_value() {
+    let mut m = RcHashMap::new();
+    let r = m.insert("k".to_string(), 1).unwrap();
+    let (r2, v) = m.find_mut("k").expect("present");
+    *v += 41;
+    assert!(r2 == r);
+    drop(r);
+    assert_eq!(*r2.value(&m).unwrap(), 42);
+    assert!(m.find_mut("missing").is_none());
+    drop(r2);
+    assert!(m.is_empty());
+}
+
+// Test: cheap validity/ownership check for Refs received from elsewhere.
+// Assumes: owner identity is the Rc allocation of the map.
+// Verifies: contains_ref is true for own Refs and false for another map's.
+#[test]
+fn contains_ref_checks_owner() {
+    let mut a = RcHashMap::new();
+    let mut b = RcHashMap::new();
+    let ra = a.insert(1, "a").unwrap();
+    let rb = b.insert(1, "b").unwrap();
+    assert!(a.contains_ref(&ra));
+    assert!(!a.contains_ref(&rb));
+    assert!(b.contains_ref(&rb));
+}
+
+// Test: liveness check from a bare handle.
+// Assumes: handles are generational slot keys, so a reused slot gets a new one.
+// Verifies: contains_handle tracks the entry without changing its Ref count,
+// and a stale handle stays dead after its slot is reused.
+#[test]
+fn contains_handle_checks_liveness() {
+    let mut m = RcHashMap::new();
+    let r = m.insert(1, "a").unwrap();
+    let h = r.handle();
+    assert!(m.contains_handle(h));
+    assert_eq!(m.ref_count(&r), Ok(1));
+    drop(r);
+    assert!(!m.contains_handle(h));
+    let r2 = m.insert(2, "b").unwrap();
+    assert!(!m.contains_handle(h));
+    assert!(m.contains_handle(r2.handle()));
+}
+
+// Test: whole-map update pass without per-item Refs.
+// Assumes: the current entry is pinned while the closure runs.
+// Verifies: every value is updated, and entries whose last Ref is dropped
+// mid-pass are removed afterwards.
+#[test]
+fn for_each_value_mut_updates_and_honors_drops() {
+    let mut m = RcHashMap::new();
+    let mut refs: Vec<_> = (0..10u32).map(|i| m.insert(i, i).unwrap()).collect();
+    let mut visited = 0;
+    m.for_each_value_mut(|k, v| {
+        visited += 1;
+        *v += 100;
+        if *k == 3 {
+            // Drops the last Ref of entries 5.. including some not yet visited.
+            refs.truncate(5);
+        }
+    });
+    // Entries released mid-pass are still visited, then removed after it.
+    assert_eq!(visited, 10);
+    assert_eq!(m.len(), 5);
+    for r in &refs {
+        assert_eq!(*r.value(&m).unwrap(), *r.key(&m).unwrap() + 100);
+    }
+}
+
+// Test: fallible construction through insert_with_result.
+// Assumes: the keepalive is only taken for entries that are inserted.
+// Verifies: constructor failure inserts nothing; duplicates skip the constructor.
+#[test]
+fn insert_with_result_failure_inserts_nothing() {
+    let mut m: RcHashMap<String, u32> = RcHashMap::new();
+    let e = m.insert_with_result("n".to_string(), || "x".parse::<u32>());
+    assert!(matches!(e, Err(TryInsertError::Constructor(_))));
+    assert!(m.is_empty());
+
+    let r = m
+        .insert_with_result("n".to_string(), || "7".parse::<u32>())
+        .unwrap();
+    assert_eq!(*r.value(&m).unwrap(), 7);
+    let dup = m.insert_with_result("n".to_string(), || -> Result<u32, ()> {
+        panic!("constructor must not run for a duplicate")
+    });
+    assert!(matches!(dup, Err(TryInsertError::DuplicateKey)));
+}
+
+// Test: constructor that derives the value from the moved key.
+// Assumes: the constructor runs only for new keys.
+// Verifies: the value is built from &K; duplicates are rejected untouched.
+#[test]
+fn insert_with_key_borrows_stored_key() {
+    let mut m = RcHashMap::new();
+    let r = m.insert_with_key("hello".to_string(), |k| k.len()).unwrap();
+    assert_eq!(*r.value(&m).unwrap(), 5);
+    let dup = m.insert_with_key("hello".to_string(), |_| panic!("must not run"));
+    assert!(matches!(dup, Err(CappedInsertError::DuplicateKey)));
+}
+
+// Test: per-entry fan-out peak with a peak-tracking counter.
+// Assumes: HighWaterCount records the maximum count; UsizeCount does not.
+// Verifies: max_ref_count reports the peak after Refs are dropped.
+#[test]
+fn max_ref_count_tracks_peak_fan_out() {
+    use rc_hashmap::tokens::HighWaterCount;
+    use rc_hashmap::DefaultHashBuilder;
+
+    let mut m: RcHashMap<&str, (), DefaultHashBuilder, HighWaterCount> = RcHashMap::default();
+    let r = m.insert("hot", ()).unwrap();
+    let clones: Vec<_> = (0..4).map(|_| r.clone()).collect();
+    drop(clones);
+    assert_eq!(r.max_ref_count(&m), Ok(Some(5)));
+
+    let mut plain = RcHashMap::new();
+    let p = plain.insert("cold", ()).unwrap();
+    assert_eq!(p.max_ref_count(&plain), Ok(None));
+    assert!(p.max_ref_count(&RcHashMap::new()).is_err());
+}
+
+// Test: peak occupancy metric.
+// Assumes: entries leave when their last Ref drops.
+// Verifies: peak_len survives removals and reset restarts from len().
+#[test]
+fn peak_len_survives_drops_and_resets() {
+    let mut m = RcHashMap::new();
+    let refs: Vec<_> = (0..8).map(|i| m.insert(i, ()).unwrap()).collect();
+    drop(refs);
+    assert_eq!((m.len(), m.peak_len()), (0, 8));
+    m.reset_peak_len();
+    assert_eq!(m.peak_len(), 0);
+}
+
+// Test: per-entry finalizers.
+// Assumes: removal happens when the last Ref drops.
+// Verifies: the finalizer runs once with the key and value, only for its entry.
+#[test]
+fn insert_with_finalizer_runs_on_removal() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut m = RcHashMap::new();
+    let l = log.clone();
+    let a = m
+        .insert_with_finalizer("fd", 3, move |k: &&str, v| {
+            l.borrow_mut().push(format!("{k}={v}"))
+        })
+        .unwrap();
+    let b = m.insert("plain", 4).unwrap();
+    let a2 = a.clone();
+    drop(a);
+    assert!(log.borrow().is_empty());
+    drop(b);
+    drop(a2);
+    assert_eq!(*log.borrow(), vec!["fd=3".to_string()]);
+    assert!(m.is_empty());
+}
+
+// Test: batch lookups.
+// Assumes: each hit mints a Ref like find.
+// Verifies: results are in input order and hold the entries alive.
+#[test]
+fn find_many_returns_refs_in_order() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a".to_string(), 1).unwrap();
+    let b = m.insert("b".to_string(), 2).unwrap();
+    let found = m.find_many(["b", "zz", "a"]);
+    assert!(found[0].as_ref() == Some(&b));
+    assert!(found[1].is_none());
+    assert!(found[2].as_ref() == Some(&a));
+    drop((a, b));
+    assert_eq!(m.len(), 2, "batch Refs keep entries alive");
+    drop(found);
+    assert!(m.is_empty());
+}
+
+// Test: bulk Ref drop.
+// Assumes: entries leave when their count reaches zero.
+// Verifies: drop_refs removes exactly the entries whose last Ref was in the
+// batch, and cascades from dropped values still run.
+#[test]
+fn drop_refs_removes_in_one_pass() {
+    let mut m: RcHashMap<u32, Option<Ref<u32, ()>>> = RcHashMap::new();
+    let mut leaf_map: RcHashMap<u32, ()> = RcHashMap::new();
+    let leaf = leaf_map.insert(0, ()).unwrap();
+    let refs: Vec<_> = (0..100).map(|i| m.insert(i, None).unwrap()).collect();
+    *refs[0].value_mut(&mut m).unwrap() = Some(leaf);
+    let keep = refs[1].clone();
+
+    m.drop_refs(refs);
+    assert_eq!(m.len(), 1);
+    assert!(leaf_map.is_empty(), "value drops cascade");
+    drop(keep);
+    assert!(m.is_empty());
+}
+
+// Test: bulk drop of a foreign Ref whose value refers back.
+// Assumes: foreign Refs in the batch drop after this map's batch completes.
+// Verifies: a foreign entry whose value holds a Ref into this map cascades
+// into it cleanly, alongside this map's own Refs in the same batch.
+#[test]
+fn drop_refs_foreign_value_refers_back() {
+    struct Node(#[allow(dead_code)] Option<Ref<u32, Node>>);
+    let mut m: RcHashMap<u32, Node> = RcHashMap::new();
+    let mut other: RcHashMap<u32, Node> = RcHashMap::new();
+    let target = m.insert(0, Node(None)).unwrap();
+    let own = m.insert(1, Node(None)).unwrap();
+    let foreign = other.insert(0, Node(Some(target))).unwrap();
+
+    m.drop_refs([foreign, own]);
+    assert!(other.is_empty());
+    assert!(m.is_empty(), "the back-Ref released its entry");
+}
+
+// Test: routing Refs to their owning map.
+// Assumes: owner identity is the map's Rc allocation.
+// Verifies: is_owner picks the right map out of several.
+#[test]
+fn is_owner_routes_refs() {
+    let mut maps: Vec<RcHashMap<u32, u32>> = (0..3).map(|_| RcHashMap::new()).collect();
+    let refs: Vec<_> = (0..3u32)
+        .map(|i| maps[i as usize].insert(i, i * 10).unwrap())
+        .collect();
+    for r in &refs {
+        let owners: Vec<_> = maps.iter().filter(|m| m.is_owner(r)).collect();
+        assert_eq!(owners.len(), 1);
+        let v = *r.value(owners[0]).unwrap();
+        assert_eq!(v, *r.key(owners[0]).unwrap() * 10);
+    }
+}
+
+// Test: registry mode via pin/unpin.
+// Assumes: a pin is a Ref held by the map itself.
+// Verifies: pinned entries outlive external Refs, unpin releases them, and
+// dropping the map releases remaining pins.
+#[test]
+fn pin_keeps_entry_until_unpin_or_map_drop() {
+    use std::rc::Rc;
+
+    let tracker = Rc::new(());
+    let mut m = RcHashMap::new();
+    drop(m.insert("svc", tracker.clone()).unwrap());
+    assert!(m.is_empty());
+
+    let r = m.insert("svc", tracker.clone()).unwrap();
+    assert!(m.pin("svc"));
+    assert!(!m.pin("svc"), "already pinned");
+    assert!(!m.pin("missing"));
+    drop(r);
+    assert!(m.is_pinned("svc"));
+    assert_eq!(m.len(), 1);
+
+    assert!(m.unpin("svc"));
+    assert!(!m.unpin("svc"));
+    assert!(m.is_empty());
+
+    drop(m.insert("z", tracker.clone()).unwrap());
+    let r = m.insert("z", tracker.clone()).unwrap();
+    assert!(m.pin("z"));
+    drop(r);
+    drop(m);
+    assert_eq!(Rc::strong_count(&tracker), 1, "map drop releases pins");
+}
+
+// Test: iterate-and-drop over a handle snapshot.
+// Assumes: handles are generational, so removed entries do not resolve.
+// Verifies: entries removed mid-iteration are skipped, the rest are visited.
+#[test]
+fn snapshot_iter_tolerates_removals() {
+    let mut m = RcHashMap::new();
+    let mut refs: Vec<Option<_>> = (0..10u32).map(|i| Some(m.insert(i, i).unwrap())).collect();
+    // Walk an earlier snapshot in the same order: a key survives unless the
+    // entry for its predecessor was visited first and dropped it.
+    let order: Vec<u32> = m.snapshot_iter().map(|r| *r.key(&m).unwrap()).collect();
+    let mut expected = Vec::new();
+    for &k in &order {
+        if k == 0 || !expected.contains(&(k - 1)) {
+            expected.push(k);
+        }
+    }
+    let mut seen = Vec::new();
+    for r in m.snapshot_iter() {
+        let k = *r.key(&m).unwrap();
+        seen.push(k);
+        // Drop the external Refs of this entry and of its successor.
+        refs[k as usize] = None;
+        if k + 1 < 10 {
+            refs[k as usize + 1] = None;
+        }
+    }
+    assert_eq!(seen, expected, "exactly the undropped entries are visited");
+    assert!(m.is_empty());
+}
+
+// Test: registry delta between two maps.
+// Assumes: diff compares keys only; diff_values also compares values.
+// Verifies: each key lands in exactly the expected bucket.
+#[test]
+fn diff_reports_added_removed_and_changed() {
+    let mut old = RcHashMap::new();
+    let mut new = RcHashMap::new();
+    let _o: Vec<_> = [("a", 1), ("b", 2), ("c", 3)]
+        .into_iter()
+        .map(|(k, v)| old.insert(k, v).unwrap())
+        .collect();
+    let _n: Vec<_> = [("b", 2), ("c", 30), ("d", 4)]
+        .into_iter()
+        .map(|(k, v)| new.insert(k, v).unwrap())
+        .collect();
+
+    let d = old.diff(&new);
+    assert_eq!((d.only_in_self, d.only_in_other), (vec!["a"], vec!["d"]));
+    assert!(d.changed.is_empty());
+    assert_eq!(old.diff_values(&new).changed, vec!["c"]);
+    assert!(old.diff_values(&old).is_empty());
+}
+
+// Test: exporting to std collections.
+// Assumes: export clones and does not touch refcounts.
+// Verifies: to_hash_map and to_vec carry every pair; the map is unchanged.
+#[test]
+fn export_to_std_collections() {
+    let mut m = RcHashMap::new();
+    let refs: Vec<_> = (0..5u32)
+        .map(|i| m.insert(i, i.to_string()).unwrap())
+        .collect();
+    let hm = m.to_hash_map();
+    assert_eq!(hm.len(), 5);
+    assert_eq!(hm[&3], "3");
+    let mut v = m.to_vec();
+    v.sort();
+    assert_eq!(v[4], (4, "4".to_string()));
+    drop(refs);
+    assert!(m.is_empty());
+    assert_eq!(hm.len(), 5);
+}
+
+// Test: lifecycle trace of one key.
+// Assumes: the `lifecycle-trace` feature is enabled.
+// Verifies: inserts, mints, drops and removals of the traced key are
+// recorded with refcounts, across a re-insert; other keys are not.
+#[cfg(feature = "lifecycle-trace")]
+#[test]
+fn trace_records_one_entry_lifecycle() {
+    use rc_hashmap::{TraceEvent, TraceOp};
+
+    let mut m = RcHashMap::new();
+    m.trace_key("a");
+    let a = m.insert("a", 1).unwrap();
+    let _b = m.insert("b", 2).unwrap();
+    let a2 = a.clone();
+    drop(a);
+    drop(a2);
+    let a = m.insert("a", 3).unwrap();
+    m.drop_refs([a]);
+
+    let ops: Vec<_> = m
+        .trace_events()
+        .into_iter()
+        .map(|TraceEvent { op, refs, .. }| (op, refs))
+        .collect();
+    assert_eq!(
+        ops,
+        [
+            (TraceOp::Insert, 1),
+            (TraceOp::Get, 2),
+            (TraceOp::Put, 1),
+            (TraceOp::Remove, 0),
+            (TraceOp::Insert, 1),
+            (TraceOp::Remove, 0),
+        ]
+    );
+    assert_eq!(m.trace_events().last().unwrap().seq, 5);
+}
+
+// Test: force_remove with outstanding Refs.
+// Assumes: stale Refs keep the map's allocation alive on their own.
+// Verifies: the value is returned at once, stale Refs report Stale, clone
+// and drop safely (even after the map is gone), and the key can be reused.
+#[test]
+fn force_remove_leaves_stale_refs() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("k", String::from("old")).unwrap();
+    let b = a.clone();
+    assert!(m.pin("k"));
+    assert_eq!(m.force_remove("k"), Some(String::from("old")));
+    assert_eq!(m.force_remove("k"), None);
+    assert!(m.is_empty());
+    assert_eq!(a.value(&m), Err(AccessError::Stale));
+    assert!(!m.contains_ref(&a));
+
+    let n = m.insert("k", String::from("new")).unwrap();
+    let c = b.clone();
+    drop(b);
+    m.drop_refs([a]);
+    assert_eq!(n.value(&m).unwrap(), "new");
+    assert_eq!(c.key(&m), Err(AccessError::Stale));
+    drop(n);
+    drop(m);
+    drop(c);
+}
+
+// Test: weak handle to the whole map.
+// Assumes: outstanding Refs keep storage alive but not the owner.
+// Verifies: upgrade observes the live map and mints Refs; once the owner
+// drops, upgrade fails even though a Ref is still outstanding.
+#[test]
+fn weak_map_handle_tracks_owner() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let weak: WeakRcHashMap<_, _> = m.downgrade_map();
+    let obs = weak.upgrade().unwrap();
+    assert_eq!((obs.len(), obs.contains_key("a")), (1, true));
+    let b = obs.find("a").unwrap();
+    assert_eq!(b.value(&m), Ok(&1));
+    assert!(weak.clone().is_alive());
+
+    drop(m);
+    assert!(!weak.is_alive());
+    assert!(!obs.is_alive());
+    assert!(weak.upgrade().is_none());
+    drop((a, b, obs));
+    assert!(weak.upgrade().is_none());
+}
+
+// Test: map identity.
+// Assumes: ids come from a process-wide counter.
+// Verifies: Refs report their map's id, and distinct maps differ.
+#[test]
+fn refs_report_owner_id() {
+    let mut m1 = RcHashMap::new();
+    let mut m2 = RcHashMap::new();
+    let a = m1.insert(1, ()).unwrap();
+    let b = m2.insert(1, ()).unwrap();
+    assert_eq!(a.owner_id(), m1.id());
+    assert_eq!(b.owner_id(), m2.id());
+    assert_ne!(m1.id(), m2.id());
+    assert_eq!(m1.downgrade_map().upgrade().unwrap().id(), m1.id());
+}
+
+// Test: splitting a registry by predicate.
+// Assumes: only entries held solely by their pin can move.
+// Verifies: matching pinned entries move (still pinned, finalizer intact),
+// entries with external Refs and non-matching ones stay.
+#[test]
+fn split_off_if_moves_pinned_entries() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let finalized = Rc::new(Cell::new(0));
+    let mut m = RcHashMap::new();
+    for k in ["t1/a", "t1/b", "t2/a"] {
+        let f = finalized.clone();
+        let r = m.insert_with_finalizer(k, k.len(), move |_, _| f.set(f.get() + 1));
+        assert!(m.pin(&k));
+        drop(r);
+    }
+    let _held = m.find("t1/b").unwrap();
+
+    let mut t1 = m.split_off_if(|k, _| k.starts_with("t1/"));
+    assert_eq!(t1.len(), 1);
+    assert!(t1.is_pinned("t1/a"));
+    assert!(m.contains_key("t1/b") && m.contains_key("t2/a"));
+    assert!(!m.contains_key("t1/a"));
+    assert_eq!(finalized.get(), 0);
+    assert!(t1.unpin("t1/a"));
+    assert_eq!(finalized.get(), 1);
+}
+
+// Test: cloning the Ref iterator.
+// Assumes: each yielded Ref holds its own count.
+// Verifies: zipping an iterator with a clone of itself pairs each entry
+// with itself, and the Refs release cleanly.
+#[test]
+fn iter_clone_zips_with_self() {
+    let mut m = RcHashMap::new();
+    let refs: Vec<_> = (0..3).map(|i| m.insert(i, i).unwrap()).collect();
+    let it = m.iter();
+    assert_eq!(it.len(), 3);
+    assert!(it.clone().zip(it).all(|(a, b)| a == b));
+    drop(refs);
+    assert!(m.is_empty());
+}
+
+// Test: refcount-ordered diagnostics.
+// Assumes: pins count as Refs.
+// Verifies: entries come out in descending count order with exact counts,
+// and iterating does not change any count.
+#[test]
+fn iter_by_refcount_orders_hottest_first() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let b = m.insert("b", 2).unwrap();
+    let b2 = b.clone();
+    let c = m.insert("c", 3).unwrap();
+    assert!(m.pin("c"));
+    let c2 = c.clone();
+    let order: Vec<_> = m.iter_by_refcount().map(|(k, _, n)| (*k, n)).collect();
+    assert_eq!(order, [("c", 3), ("b", 2), ("a", 1)]);
+    assert_eq!(m.iter_by_refcount().count(), 3);
+    drop((a, b, b2, c, c2));
+    assert_eq!(m.iter_by_refcount().collect::<Vec<_>>(), [(&"c", &3, 1)]);
+}
+
+// Test: ItemMut derefs to the value and converts into its Ref.
+// Assumes: each ItemMut carries a freshly minted Ref.
+// Verifies: `*item += 1` updates in place; into_ref keeps the entry alive
+// after the loop without an extra clone.
+#[test]
+fn item_mut_deref_and_into_ref() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let b = m.insert("b", 2).unwrap();
+    let mut kept = None;
+    for mut item in m.iter_mut() {
+        *item += 10;
+        if *item.key() == "b" {
+            assert_eq!(*item, 12);
+            kept = Some(item.into_ref());
+        }
+    }
+    drop((a, b));
+    assert_eq!(m.len(), 1);
+    let kept = kept.unwrap();
+    assert_eq!(kept.key(&m).unwrap(), &"b");
+    assert_eq!(*kept.value(&m).unwrap(), 12);
+}
+
+// Test: entries() yields Ref, key and value together.
+// Assumes: each item's Ref is freshly minted like iter()'s.
+// Verifies: the borrows match what the Ref resolves to, and an item's Ref
+// can be kept past the loop.
+#[test]
+fn entries_yields_ref_key_and_value() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let b = m.insert("b", 2).unwrap();
+    let mut seen: Vec<_> = m
+        .entries()
+        .map(|it| {
+            assert_eq!(it.r#ref().key(&m).unwrap(), it.key());
+            assert_eq!(it.r#ref().value(&m).unwrap(), it.value());
+            (*it.key(), *it)
+        })
+        .collect();
+    seen.sort();
+    assert_eq!(seen, [("a", 1), ("b", 2)]);
+    assert_eq!(m.entries().len(), 2);
+
+    let kept = m.entries().find(|it| *it.key() == "a").unwrap().into_ref();
+    drop((a, b));
+    assert_eq!(m.len(), 1);
+    assert_eq!(*kept.value(&m).unwrap(), 1);
+}
+
+// Test: scan() borrows entries without minting Refs.
+// Assumes: iter_by_refcount reports exact counts.
+// Verifies: scanning leaves counts unchanged, and to_ref mints on demand.
+#[test]
+fn scan_mints_only_on_to_ref() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let b = m.insert("b", 2).unwrap();
+    let sum: i32 = m.scan().map(|it| *it).sum();
+    assert_eq!(sum, 3);
+    assert!(m.iter_by_refcount().all(|(_, _, n)| n == 1));
+
+    let kept = m.scan().find(|it| *it.key() == "b").unwrap().to_ref();
+    assert!(kept == b);
+    let counts: Vec<_> = m.iter_by_refcount().map(|(k, _, n)| (*k, n)).collect();
+    assert_eq!(counts, [("b", 2), ("a", 1)]);
+    drop((a, b, kept));
+    assert!(m.is_empty());
+}
+
+// Test: dropping an entry's last Ref while a ScanItem is alive.
+// Assumes: a ScanItem's borrows must stay valid for its whole life.
+// Verifies: removal waits until the last item drops; a Ref minted in the
+// meantime keeps the entry.
+#[test]
+fn scan_defers_removal_while_items_alive() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a".to_string(), 1).unwrap();
+    let b = m.insert("b".to_string(), 2).unwrap();
+    let items: Vec<_> = m.scan().collect();
+    drop((a, b));
+    assert_eq!(m.len(), 2);
+    let mut keys: Vec<_> = items.iter().map(|it| it.key().as_str()).collect();
+    keys.sort();
+    assert_eq!(keys, ["a", "b"]);
+    let revived = m.find("b").unwrap();
+    drop(items);
+    assert_eq!(m.len(), 1);
+    assert_eq!(*revived.value(&m).unwrap(), 2);
+    drop(revived);
+    assert!(m.is_empty());
+}
+
+// Test: hard entry cap.
+// Assumes: the cap counts live entries, not Refs.
+// Verifies: inserting a new key at the cap fails with CapacityExceeded on
+// every insert path, a present key still reports DuplicateKey, and room
+// returns when an entry goes away.
+#[test]
+fn max_entries_rejects_inserts_when_full() {
+    let mut m = RcHashMap::with_max_entries(2);
+    assert_eq!(m.max_entries(), 2);
+    let a = m.insert(1, "a").unwrap();
+    let a2 = a.clone();
+    let _b = m.insert(2, "b").unwrap();
+    assert!(matches!(
+        m.insert(3, "c"),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.insert_with_key(3, |_| "c"),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.insert_with_finalizer(3, "c", |_, _| {}),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.insert_with_result(3, || Ok::<_, ()>("c")),
+        Err(TryInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.entry(3).or_insert_with(|| "c"),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.get_or_insert_with(3, || "c"),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.insert(1, "x"),
+        Err(CappedInsertError::DuplicateKey)
+    ));
+    assert!(m.get_or_insert_with(1, || "x").is_ok());
+    assert_eq!(m.len(), 2);
+
+    drop((a, a2));
+    assert!(m.insert(3, "c").is_ok());
+    m.set_max_entries(usize::MAX);
+    assert!(m.insert(4, "d").is_ok());
+}
+
+// Test: per-entry Ref cap.
+// Assumes: the Ref returned by insert counts toward the cap.
+// Verifies: lookups and try_clone refuse at the cap, clone and iterators
+// do not, and room frees up again once a Ref drops.
+#[test]
+fn ref_cap_bounds_lookups() {
+    let mut m = RcHashMap::with_max_refs(2);
+    assert_eq!(m.max_refs(), 2);
+    let a = m.insert("buf", 0u8).unwrap();
+    let b = m.find("buf").unwrap();
+    assert!(m.find("buf").is_none());
+    assert!(m.find_many(["buf"])[0].is_none());
+    assert!(m.find_mut("buf").is_none());
+    assert!(m.upgrade(a.handle()).is_none());
+    assert!(a.downgrade().upgrade(&m).is_none());
+    assert!(!m.pin("buf"));
+    assert!(matches!(
+        m.find_or_default("buf"),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.entry("buf").or_insert_with(|| 1),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.get_or_insert_with("buf", || 1),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(a.try_clone().is_none());
+
+    let over = a.clone();
+    assert_eq!(m.ref_count(&a), Ok(3));
+    assert_eq!(m.iter().count(), 1);
+    drop((b, over));
+    let c = a.try_clone().unwrap();
+    assert_eq!(c.value(&m), Ok(&0));
+    drop(c);
+    assert!(m.entry("buf").or_insert_with(|| 1).is_ok());
+    drop(a);
+    assert!(m.is_empty());
+    assert_eq!(RcHashMap::<u8, u8>::new().max_refs(), usize::MAX);
+}
+
+// Test: entry cap on the value-constructing inserts.
+// Assumes: find_or_default and insert_any insert through the same cap check.
+// Verifies: both fail with CapacityExceeded for a new key at the cap and
+// still find an existing key.
+#[test]
+fn max_entries_caps_default_and_any_inserts() {
+    let mut m: RcHashMap<&str, u32> = RcHashMap::with_max_entries(1);
+    let a = m.find_or_default("a").unwrap();
+    assert!(matches!(
+        m.find_or_default("b"),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(m.find_or_default("a").unwrap() == a);
+
+    let mut any: RcHashMap<u32, Box<dyn std::any::Any>> = RcHashMap::with_max_entries(1);
+    let _x = any.insert_any(1, 1u8).unwrap();
+    assert!(matches!(
+        any.insert_any(2, 2u8),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+}
+
+// Test: typed access to Box<dyn Any> values.
+// Assumes: get_as mints a Ref like find.
+// Verifies: get_as reads the stored type, returns None for another type or
+// a missing key, reports a replaced value's new type as None instead of
+// panicking, and keeps the entry alive.
+#[test]
+fn any_values_typed_access() {
+    use std::any::Any;
+    let mut m: RcHashMap<&str, Box<dyn Any>> = RcHashMap::new();
+    let a = m.insert_any("count", 7u32).unwrap();
+    let b = m.insert_any("name", String::from("x")).unwrap();
+    let count = m.get_as::<u32, _>("count").unwrap();
+    assert_eq!(count.get(&m), Ok(Some(&7)));
+    assert_eq!(
+        m.get_as::<String, _>("name").unwrap().get(&m),
+        Ok(Some(&String::from("x")))
+    );
+    assert!(m.get_as::<u64, _>("count").is_none());
+    assert!(m.get_as::<u32, _>("missing").is_none());
+
+    *a.value_mut(&mut m).unwrap() = Box::new("replaced");
+    assert_eq!(count.get(&m), Ok(None));
+    *a.value_mut(&mut m).unwrap() = Box::new(8u32);
+    assert_eq!(count.clone().get(&m), Ok(Some(&8)));
+    drop((a, b));
+    assert_eq!(m.len(), 1);
+    drop(count);
+    assert!(m.is_empty());
+}
+
+// Test: canonicalized keys.
+// Assumes: Canonical normalizes on construction.
+// Verifies: differently spelled keys collide on insert and match on lookup
+// under a composed normalization.
+#[test]
+fn canonical_keys_normalize_insert_and_lookup() {
+    use rc_hashmap::canonical::{Canonical, Lowercase, Trim};
+    type Key = Canonical<String, (Trim, Lowercase)>;
+    let mut m: RcHashMap<Key, i32> = RcHashMap::new();
+    let r = m.insert(Key::from("  Apple "), 1).unwrap();
+    assert!(matches!(
+        m.insert(Key::from("APPLE"), 2),
+        Err(CappedInsertError::DuplicateKey)
+    ));
+    let found = m.find(&Key::from("apple\t")).unwrap();
+    assert!(found == r);
+    assert_eq!(found.key(&m).unwrap().get(), "apple");
+}
+
+// Test: find_or_default for accumulation.
+// Assumes: a miss inserts V::default().
+// Verifies: repeated calls share one entry and updates accumulate.
+#[test]
+fn find_or_default_accumulates() {
+    let mut m: RcHashMap<&str, u32> = RcHashMap::new();
+    let mut refs = Vec::new();
+    for w in ["a", "b", "a", "a"] {
+        let r = m.find_or_default(w).unwrap();
+        *r.value_mut(&mut m).unwrap() += 1;
+        refs.push(r);
+    }
+    assert_eq!(m.len(), 2);
+    assert_eq!(*refs[0].value(&m).unwrap(), 3);
+    assert_eq!(*refs[1].value(&m).unwrap(), 1);
+    assert!(refs[0] == refs[3]);
+}
+
+// Test: per-entry timestamps from a manual clock.
+// Assumes: lookups that mint a Ref count as accesses; clones do not.
+// Verifies: created_at is fixed at insert, last_accessed follows find, and
+// entries inserted before set_clock report None.
+#[test]
+fn timestamps_follow_clock() {
+    use rc_hashmap::ManualClock;
+    use std::rc::Rc;
+    use std::time::Duration;
+    let mut m = RcHashMap::new();
+    let early = m.insert("early", 0).unwrap();
+    let clock = Rc::new(ManualClock::new());
+    m.set_clock(clock.clone());
+    clock.advance(Duration::from_secs(1));
+    let a = m.insert("a", 1).unwrap();
+    clock.advance(Duration::from_secs(2));
+    let a2 = a.clone();
+    assert_eq!(a2.last_accessed(&m).unwrap(), Some(Duration::from_secs(1)));
+    let a3 = m.find("a").unwrap();
+    assert_eq!(a.created_at(&m).unwrap(), Some(Duration::from_secs(1)));
+    assert_eq!(a.last_accessed(&m).unwrap(), Some(Duration::from_secs(3)));
+    assert_eq!(early.created_at(&m).unwrap(), None);
+    drop((early, a, a2, a3));
+}
+
+// Test: a Clock that panics while the map stamps an insert or a lookup.
+// Assumes: the new entry's Ref exists before the clock is read.
+// Verifies: the panic unwinds instead of aborting, the unreturned Ref is
+// released (removing a fresh insert), and the map stays usable.
+#[test]
+fn panicking_clock_unwinds_cleanly() {
+    use rc_hashmap::Clock;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+    struct Flaky(Rc<Cell<bool>>);
+    impl Clock for Flaky {
+        fn now(&self) -> Duration {
+            assert!(!self.0.get(), "clock failed");
+            Duration::ZERO
+        }
+    }
+    let fail = Rc::new(Cell::new(false));
+    let mut m = RcHashMap::new();
+    m.set_clock(Flaky(fail.clone()));
+    let a = m.insert("a", 1).unwrap();
+    fail.set(true);
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| m.insert("b", 2)));
+    assert!(res.is_err());
+    assert!(!m.contains_key("b"));
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| m.find("a")));
+    assert!(res.is_err());
+    fail.set(false);
+    assert_eq!(m.ref_count(&a).unwrap(), 1);
+    assert_eq!(m.len(), 1);
+    drop(a);
+    assert!(m.is_empty());
+}
+
+// Test: panics from user code inside map operations.
+// Assumes: RcHashMap, Ref and the iterators are UnwindSafe when K, V and S
+// are, so catch_unwind needs no AssertUnwindSafe.
+// Verifies: a panicking V::drop on last-Ref drop and a panicking
+// for_each_value_mut closure neither abort nor corrupt the map.
+#[test]
+fn catch_unwind_leaves_map_usable() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    struct Bomb(bool);
+    impl Drop for Bomb {
+        fn drop(&mut self) {
+            if self.0 {
+                panic!("boom");
+            }
+        }
+    }
+    let mut m: RcHashMap<&str, Bomb> = RcHashMap::new();
+    let r = m.insert("a", Bomb(true)).unwrap();
+    assert!(catch_unwind(move || drop(r)).is_err());
+    assert_eq!(catch_unwind(|| m.len()).unwrap(), 0);
+
+    let keep = m.insert("b", Bomb(false)).unwrap();
+    let _c = m.insert("c", Bomb(false)).unwrap();
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        m.for_each_value_mut(|k, _| assert_ne!(*k, "b"));
+    }));
+    assert!(res.is_err());
+    assert_eq!(m.len(), 2);
+    assert_eq!(catch_unwind(|| m.iter().count()).unwrap(), 2);
+    assert!(m.find("b").unwrap() == keep);
+    drop(keep);
+    assert_eq!(m.len(), 1);
+}
+
+// Test: predicate search over values.
+// Assumes: find_by scans in iteration order and mints on match.
+// Verifies: find_by returns a live Ref to a matching entry or None, and
+// find_all_by returns every match.
+#[test]
+fn find_by_matches_values() {
+    let mut m = RcHashMap::new();
+    let _a = m.insert("a", 10).unwrap();
+    let _b = m.insert("b", 21).unwrap();
+    let _c = m.insert("c", 30).unwrap();
+    let odd = m.find_by(|_, v| v % 2 == 1).unwrap();
+    assert_eq!(*odd.key(&m).unwrap(), "b");
+    drop(_b);
+    assert_eq!(m.len(), 3);
+    drop(odd);
+    assert_eq!(m.len(), 2);
+    assert!(m.find_by(|_, v| *v > 100).is_none());
+    let _d = m.insert("d", 40).unwrap();
+    let mut big: Vec<_> = m
+        .find_all_by(|_, v| *v >= 20)
+        .iter()
+        .map(|r| *r.key(&m).unwrap())
+        .collect();
+    big.sort();
+    assert_eq!(big, ["c", "d"]);
+}
+
+// Test: refcount diagnostics iterator.
+// Assumes: iter_counts mints no Refs and counts pins.
+// Verifies: counts match outstanding Refs, and dropping a last Ref while
+// an item is alive leaves the entry in place until the item drops.
+#[test]
+fn iter_counts_reports_live_counts() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let a2 = a.clone();
+    let _b = m.insert("b", 2).unwrap();
+    assert!(m.pin("b"));
+    let mut counts: Vec<_> = m.iter_counts().map(|(_, it, n)| (*it.key(), n)).collect();
+    counts.sort();
+    assert_eq!(counts, [("a", 2), ("b", 2)]);
+
+    drop(a2);
+    let items: Vec<_> = m.iter_counts().collect();
+    drop(a);
+    assert_eq!(m.len(), 2);
+    drop(items);
+    assert_eq!(m.len(), 1);
+}
+
+// Test: any_entry as a drain loop.
+// Assumes: any_entry returns some live entry while the map is non-empty.
+// Verifies: unpinning whatever any_entry returns empties the map.
+#[test]
+fn any_entry_drains_pinned_entries() {
+    let mut m = RcHashMap::new();
+    assert!(m.any_entry().is_none());
+    for k in ["a", "b", "c"] {
+        let _r = m.insert(k, 1).unwrap();
+        assert!(m.pin(k));
+    }
+    let mut n = 0;
+    while let Some(r) = m.any_entry() {
+        let k = *r.key(&m).unwrap();
+        assert!(m.unpin(k));
+        drop(r);
+        n += 1;
+    }
+    assert_eq!(n, 3);
+    assert!(m.is_empty());
+}
+
+// Test: refcount-filtered iteration.
+// Assumes: iter_where_count mints no Refs.
+// Verifies: only entries whose Ref count matches are yielded.
+#[test]
+fn iter_where_count_selects_by_refcount() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let _a2 = a.clone();
+    let _a3 = a.clone();
+    let _b = m.insert("b", 2).unwrap();
+    let _c = m.insert("c", 3).unwrap();
+    let mut unique: Vec<_> = m.iter_where_count(|n| n == 1).map(|it| *it.key()).collect();
+    unique.sort();
+    assert_eq!(unique, ["b", "c"]);
+    let shared: Vec<_> = m
+        .iter_where_count(|n| n > 2)
+        .map(|it| *it.value())
+        .collect();
+    assert_eq!(shared, [1]);
+}
+
+// Test: positional access for pagination.
+// Assumes: positions are dense and in insertion order until a removal.
+// Verifies: get_index and get_index_of round-trip, a page of Refs matches
+// insertion order, and a removal fills its position with the last entry.
+#[test]
+fn get_index_pages_in_insertion_order() {
+    let mut m = RcHashMap::new();
+    let mut refs: Vec<_> = (0..10).map(|i| m.insert(i, i * 10).unwrap()).collect();
+    let page = |m: &RcHashMap<i32, i32>| -> Vec<i32> {
+        (4..8)
+            .map(|i| *m.get_index(i).unwrap().key(m).unwrap())
+            .collect()
+    };
+    assert_eq!(page(&m), [4, 5, 6, 7]);
+    for k in 0..10 {
+        assert_eq!(m.get_index_of(&k), Some(k as usize));
+    }
+    assert!(m.get_index(10).is_none());
+
+    drop(refs.remove(5));
+    assert_eq!(page(&m), [4, 9, 6, 7]);
+    assert_eq!(m.get_index_of(&9), Some(5));
+    assert_eq!(m.get_index_of(&5), None);
+    drop(refs);
+    assert!(m.is_empty());
+}
+
+// Test: Ref::try_unwrap.
+// Assumes: it succeeds only for the entry's sole Ref, pins included.
+// Verifies: shared, pinned and foreign Refs are given back; the sole Ref
+// yields the owned pair, removes the entry and skips its finalizer.
+#[test]
+fn try_unwrap_returns_owned_pair_for_sole_ref() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    let mut m = RcHashMap::new();
+    let mut other = RcHashMap::new();
+    let ran = Rc::new(Cell::new(false));
+    let flag = ran.clone();
+    let a = m
+        .insert_with_finalizer("a".to_string(), vec![1, 2], move |_, _| flag.set(true))
+        .unwrap();
+    let a2 = a.clone();
+    let a = a.try_unwrap(&mut m).unwrap_err();
+    drop(a2);
+    let a = a.try_unwrap(&mut other).unwrap_err();
+    assert!(m.pin("a"));
+    let a = a.try_unwrap(&mut m).unwrap_err();
+    assert!(m.unpin("a"));
+    let (k, v) = a.try_unwrap(&mut m).ok().unwrap();
+    assert_eq!((k.as_str(), v), ("a", vec![1, 2]));
+    assert!(m.is_empty());
+    assert!(!ran.get());
+    let _b = m.insert("b".to_string(), vec![]).unwrap();
+    assert_eq!(m.len(), 1);
+}
+
+// Test: integer-keyed maps.
+// Assumes: `new_integer_keyed` only swaps the hasher.
+// Verifies: dense and sparse integer IDs insert, find and free as usual.
+#[test]
+fn integer_keyed_map_behaves_like_default() {
+    let mut m = RcHashMap::new_integer_keyed();
+    let refs: Vec<_> = (0u64..1000)
+        .chain([u64::MAX, 1 << 40])
+        .map(|i| m.insert(i, i.wrapping_mul(2)).unwrap())
+        .collect();
+    assert_eq!(m.len(), 1002);
+    for i in (0u64..1000).chain([u64::MAX, 1 << 40]) {
+        let r = m.find(&i).unwrap();
+        assert_eq!(*r.value(&m).unwrap(), i.wrapping_mul(2));
+    }
+    assert!(m.find(&1000).is_none());
+    drop(refs);
+    assert!(m.is_empty());
+}
+
+// Key whose hash reads a `Cell`, so it can be mutated while in a map.
+#[derive(Debug, PartialEq, Eq)]
+struct CellKey(std::cell::Cell<u32>);
+
+impl Hash for CellKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.get().hash(state)
+    }
+}
+
+// Test: mutated-key detection via validate.
+// Assumes: keys must not change while in the map.
+// Verifies: validate passes on an intact map and panics naming a key
+// mutated after insert.
+#[test]
+#[should_panic(expected = "key CellKey(Cell { value: 7 })")]
+fn validate_names_key_mutated_after_insert() {
+    let mut m = RcHashMap::new();
+    let r = m.insert(CellKey(std::cell::Cell::new(1)), ()).unwrap();
+    m.validate();
+    r.key(&m).unwrap().0.set(7);
+    assert!(!m.contains_key(&CellKey(std::cell::Cell::new(7))));
+    m.validate();
+}
+
+// Test: mutated-key detection on Ref access (debug builds).
+// Assumes: debug builds rehash the key on Ref key/value access.
+// Verifies: accessing an entry whose key changed since insert panics.
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "hashes differently than when it was inserted")]
+fn ref_access_detects_mutated_key_in_debug() {
+    let mut m = RcHashMap::new();
+    let r = m.insert(CellKey(std::cell::Cell::new(1)), 0u8).unwrap();
+    r.key(&m).unwrap().0.set(7);
+    let _ = r.value(&m);
+}
+
+// Test: weak entry references.
+// Assumes: a WeakRef neither keeps its entry alive nor resolves once the
+// entry is gone, even if its slot is reused.
+// Verifies: a child holding a WeakRef to its parent does not keep it
+// alive; upgrade succeeds while the parent lives and fails afterwards,
+// after slot reuse, and against another map.
+#[test]
+fn weak_ref_back_edges_do_not_keep_entries_alive() {
+    struct Node(Option<rc_hashmap::WeakRef<&'static str, Node>>);
+    let mut m = RcHashMap::new();
+    let other = RcHashMap::new();
+    let parent = m.insert("parent", Node(None)).unwrap();
+    let child = m.insert("child", Node(Some(parent.downgrade()))).unwrap();
+    let back = child.value(&m).unwrap().0.unwrap();
+    let up = back.upgrade(&m).unwrap();
+    assert!(up == parent);
+    assert!(back.upgrade(&other).is_none());
+    drop((up, parent));
+    assert!(!m.contains_key(&"parent"));
+    assert!(back.upgrade(&m).is_none());
+    let _reuse = m.insert("parent", Node(None)).unwrap();
+    assert!(back.upgrade(&m).is_none());
+    assert_eq!(back.owner_id(), m.id());
+}
+
+// Test: entry API.
+// Assumes: `entry` probes once; Occupied holds a Ref, Vacant the key.
+// Verifies: vacant inserts mint the only Ref; occupied yields the existing
+// entry and allows in-place mutation; inserting respects the entry cap
+// even if Refs were dropped in between.
+#[test]
+fn entry_api_inserts_or_returns_existing() {
+    use rc_hashmap::Entry;
+    let mut m = RcHashMap::with_max_entries(2);
+    let a = match m.entry("a".to_string()) {
+        Entry::Vacant(v) => {
+            assert_eq!(v.key(), "a");
+            v.insert(1).ok().unwrap()
+        }
+        Entry::Occupied(_) => panic!("a is absent"),
+    };
+    let a2 = match m.entry("a".to_string()) {
+        Entry::Occupied(mut o) => {
+            assert_eq!(*o.get(), 1);
+            *o.get_mut() += 1;
+            o.into_ref()
+        }
+        Entry::Vacant(_) => panic!("a is present"),
+    };
+    assert!(a == a2);
+    assert_eq!(*a.value(&m).unwrap(), 2);
+    let b = m.entry("b".to_string()).or_insert_with(|| 5).ok().unwrap();
+    assert_eq!(m.len(), 2);
+    let Entry::Vacant(v) = m.entry("c".to_string()) else {
+        panic!("c is absent");
+    };
+    assert!(matches!(
+        v.insert(7),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    drop(b);
+    let c = m.entry("c".to_string()).or_insert_with(|| 7).ok().unwrap();
+    assert_eq!(*c.value(&m).unwrap(), 7);
+    drop((a, a2, c));
+    assert!(m.is_empty());
+}
+
+// Test: non-minting peek accessors.
+// Assumes: peek/peek_mut mint no Refs and defer removal while borrowed.
+// Verifies: counts are untouched; mutation through peek_mut is visible;
+// dropping the last Ref while peeking removes the entry only after the
+// borrow ends.
+#[test]
+fn peek_reads_and_mutates_without_minting() {
+    let mut m = RcHashMap::new();
+    let r = m.insert("a".to_string(), 1).unwrap();
+    assert_eq!(*m.peek("a").unwrap(), 1);
+    assert!(m.peek("b").is_none());
+    *m.peek_mut("a").unwrap() += 1;
+    assert_eq!(m.iter_by_refcount().next().map(|(_, _, n)| n), Some(1));
+    let seen = m.peek("a").unwrap();
+    drop(r);
+    assert_eq!(*seen, 2);
+    assert_eq!(m.len(), 1);
+    drop(seen);
+    assert!(m.is_empty());
+    assert!(m.peek_mut("a").is_none());
+}
+
+// Test: reference count introspection.
+// Assumes: counts include pins and are not disturbed by asking.
+// Verifies: ref_count/ref_count_of/is_unique track clones, pins and drops,
+// and reject Refs from another map.
+#[test]
+fn ref_count_tracks_clones_and_pins() {
+    let mut m = RcHashMap::new();
+    let other: RcHashMap<&str, i32> = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    assert_eq!(m.ref_count(&a), Ok(1));
+    assert_eq!(a.is_unique(&m), Ok(true));
+    let a2 = a.clone();
+    assert_eq!(m.ref_count_of("a"), Some(2));
+    assert_eq!(a.is_unique(&m), Ok(false));
+    drop(a2);
+    assert!(m.pin("a"));
+    assert_eq!(m.ref_count(&a), Ok(2));
+    assert!(m.unpin("a"));
+    assert_eq!(a.is_unique(&m), Ok(true));
+    assert_eq!(m.ref_count_of("b"), None);
+    assert_eq!(other.ref_count(&a), Err(AccessError::WrongOwner));
+}
+
+// Test: Ref::handle and RcHashMap::upgrade.
+// Assumes: handles hold no liveness and are generational.
+// Verifies: a handle re-acquires its entry while it lives, and neither the
+// removed entry nor a new one in its slot resolves from it afterwards.
+#[test]
+fn handle_upgrades_while_entry_lives() {
+    let mut m = RcHashMap::new();
+    let a = m.insert("a", 1).unwrap();
+    let h = a.handle();
+    let again = m.upgrade(h).unwrap();
+    assert!(again == a);
+    assert_eq!(m.ref_count(&a), Ok(2));
+    drop((a, again));
+    assert!(m.is_empty());
+    assert!(m.upgrade(h).is_none());
+    let b = m.insert("b", 2).unwrap();
+    assert!(m.upgrade(h).is_none());
+    assert!(m.upgrade(b.handle()).is_some());
+}
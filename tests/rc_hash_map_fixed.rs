@@ -0,0 +1,52 @@
+// RcHashMapFixed test suite.
+//
+// The fixed map is RcHashMap with a capacity check in front of insert; these
+// tests cover the cap and the no-growth guarantee.
+use rc_hashmap::{CappedInsertError, RcHashMapFixed};
+
+// Test: insert fails at capacity.
+// Assumes: capacity is N entries; a dropped entry frees its slot.
+// Verifies: CapacityExceeded when full, DuplicateKey takes precedence, and
+// room returns once an entry goes away.
+#[test]
+fn fixed_insert_fails_when_full() {
+    let mut m: RcHashMapFixed<u32, u32, 2> = RcHashMapFixed::new();
+    assert_eq!(m.capacity(), 2);
+    let a = m.insert(1, 10).unwrap();
+    let _b = m.insert(2, 20).unwrap();
+    assert!(m.is_full());
+    assert!(matches!(
+        m.insert(3, 30),
+        Err(CappedInsertError::CapacityExceeded)
+    ));
+    assert!(matches!(
+        m.insert(1, 11),
+        Err(CappedInsertError::DuplicateKey)
+    ));
+
+    *m.value_mut(&a).unwrap() += 1;
+    assert_eq!(*a.value(m.as_map()).unwrap(), 11);
+
+    drop(a);
+    assert!(!m.is_full());
+    assert!(m.insert(3, 30).is_ok());
+}
+
+// Test: storage is allocated once.
+// Assumes: allocated_bytes reflects the index and slot allocations.
+// Verifies: churning entries at full capacity never grows storage.
+#[test]
+fn fixed_storage_does_not_grow() {
+    const N: usize = 16;
+    let mut m: RcHashMapFixed<usize, usize, N> = RcHashMapFixed::new();
+    let bytes = m.as_map().allocated_bytes();
+    for round in 0..100 {
+        let refs: Vec<_> = (0..N)
+            .map(|i| m.insert(round * N + i, i).unwrap())
+            .collect();
+        assert!(m.is_full());
+        assert_eq!(m.as_map().allocated_bytes(), bytes);
+        drop(refs);
+    }
+    assert!(m.is_empty());
+}
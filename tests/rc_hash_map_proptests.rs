@@ -35,7 +35,7 @@ proptest! {
                     let res = m.insert(key.clone(), k as i32);
                     match res {
                         Ok(r) => live[k].push(r),
-                        Err(rc_hashmap::InsertError::DuplicateKey) => {},
+                        Err(e) => assert!(matches!(e, rc_hashmap::CappedInsertError::DuplicateKey)),
                     }
                 }
                 // Find returns a new Ref if present; also sanity-check the value accessor.
@@ -124,7 +124,7 @@ proptest! {
                 0 => {
                     match m.insert(ki.clone(), VNode { children: vec![] }) {
                         Ok(r) => live[i].push(r),
-                        Err(rc_hashmap::InsertError::DuplicateKey) => {}
+                        Err(e) => assert!(matches!(e, rc_hashmap::CappedInsertError::DuplicateKey)),
                     }
                 }
                 1 => { if let Some(r) = m.find(&ki) { live[i].push(r); } }
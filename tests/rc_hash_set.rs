@@ -0,0 +1,42 @@
+// RcHashSet test suite.
+//
+// RcHashSet is RcHashMap with unit values; these tests cover the set-shaped
+// surface rather than re-testing the map's liveness machinery.
+use rc_hashmap::{InsertError, RcHashSet};
+
+// Test: keyed liveness for set elements.
+// Assumes: an element is present iff ≥1 outstanding Ref.
+// Verifies: insert/get_or_insert share one element; last drop removes it.
+#[test]
+fn set_insert_get_or_insert_and_drop() {
+    let mut s = RcHashSet::new();
+    let a = s.insert("x".to_string()).unwrap();
+    assert!(matches!(
+        s.insert("x".to_string()),
+        Err(InsertError::DuplicateKey)
+    ));
+    let a2 = s.get_or_insert("x".to_string());
+    assert!(a == a2);
+    assert_eq!(s.get(&a2).unwrap(), "x");
+    assert!(s.contains("x"));
+    assert_eq!(s.iter().count(), 1);
+
+    drop(a);
+    assert_eq!(s.len(), 1);
+    drop(a2);
+    assert!(s.is_empty());
+    assert!(!s.contains("x"));
+}
+
+// Test: owner identity for set Refs.
+// Assumes: Refs from one set are rejected by another.
+// Verifies: get() and contains_ref() check the owner.
+#[test]
+fn set_rejects_foreign_refs() {
+    let mut s1 = RcHashSet::new();
+    let s2: RcHashSet<u32> = RcHashSet::new();
+    let r = s1.insert(5u32).unwrap();
+    assert!(s1.contains_ref(&r));
+    assert!(!s2.contains_ref(&r));
+    assert!(s2.get(&r).is_err());
+}
@@ -0,0 +1,84 @@
+// LocalRegistry test suite.
+//
+// Registries declared with static_rc_hashmap! are thread-local RefCell'd
+// maps; these tests pin down the borrow rules and the per-thread scoping.
+use rc_hashmap::static_rc_hashmap;
+use rc_hashmap::tokens::CheckedCount;
+use rc_hashmap::IdentityHashBuilder;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+// Test: with_mut nested inside with on the same registry.
+// Assumes: both accessors borrow the same RefCell.
+// Verifies: the nested call panics, and the registry is usable afterwards.
+#[test]
+fn with_mut_inside_with_panics() {
+    static_rc_hashmap! {
+        static NESTED: u32 => u32;
+    }
+    let res = catch_unwind(AssertUnwindSafe(|| {
+        NESTED.with(|_| NESTED.with_mut(|m| m.insert(1, 1).unwrap()))
+    }));
+    assert!(res.is_err());
+
+    let r = NESTED.with_mut(|m| m.insert(2, 2).unwrap());
+    assert_eq!(NESTED.with(|m| m.len()), 1);
+    drop(r);
+}
+
+// Test: dropping a Ref while the registry is mutably borrowed.
+// Assumes: Ref drop reaches the map through its owner pointer, not the RefCell.
+// Verifies: the drop removes the entry without a borrow panic.
+#[test]
+fn ref_dropped_inside_with_mut() {
+    static_rc_hashmap! {
+        static DROPS: String => u32;
+    }
+    let r = DROPS.with_mut(|m| m.insert("a".to_string(), 1).unwrap());
+    DROPS.with_mut(|m| {
+        assert_eq!(m.len(), 1);
+        drop(r);
+        assert!(m.is_empty());
+    });
+}
+
+// Test: each thread sees its own map.
+// Assumes: the registry is a thread_local!.
+// Verifies: entries inserted on one thread are invisible to another.
+#[test]
+fn registries_are_per_thread() {
+    static_rc_hashmap! {
+        static PER_THREAD: u32 => &'static str;
+    }
+    let main = PER_THREAD.with_mut(|m| m.insert(1, "main").unwrap());
+    std::thread::spawn(|| {
+        assert!(PER_THREAD.with(|m| m.is_empty()));
+        let r = PER_THREAD.with_mut(|m| m.insert(1, "worker").unwrap());
+        assert_eq!(PER_THREAD.with(|m| *r.value(m).unwrap()), "worker");
+    })
+    .join()
+    .unwrap();
+    assert_eq!(PER_THREAD.with(|m| *main.value(m).unwrap()), "main");
+    assert_eq!(PER_THREAD.with(|m| m.len()), 1);
+}
+
+// Test: declaring a registry with a custom hasher and count.
+// Assumes: the macro forwards optional S and C to RcHashMap.
+// Verifies: the registry has the requested type and behaves like the default one.
+#[test]
+fn custom_hasher_and_count() {
+    static_rc_hashmap! {
+        static IDS: u64 => String, IdentityHashBuilder, CheckedCount;
+    }
+    static_rc_hashmap! {
+        static HASHED: u64 => String, IdentityHashBuilder;
+    }
+    let _: &rc_hashmap::LocalRegistry<u64, String, IdentityHashBuilder, CheckedCount> = &IDS;
+    let r = IDS.with_mut(|m| m.insert(7, "seven".to_string()).unwrap());
+    assert_eq!(IDS.with(|m| r.value(m).unwrap().clone()), "seven");
+    drop(r);
+    assert!(IDS.with(|m| m.is_empty()));
+
+    let h = HASHED.with_mut(|m| m.insert(3, "three".to_string()).unwrap());
+    assert!(HASHED.with(|m| m.contains_key(&3)));
+    drop(h);
+}